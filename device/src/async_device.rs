@@ -0,0 +1,414 @@
+// Async API layered on the Device state machine. The radio requests of the state machine are
+// recorded by a RadioAdapter and carried out by an AsyncRadio, while the TimeoutRequests are
+// awaited on a Timer, so that joining or sending data is a single await for the application.
+//
+// Only core futures are used so it runs on no_std executors. The timestamps of the radio and the
+// times of the timer are on the same monotonic clock, in µs and ms respectively.
+
+use super::radio::{self, PhyRxTx, PhyRxTxBuf, RxConfig, RxQuality, TxConfig};
+use super::{
+    region, Credentials, CryptoFactory, Event, Response, RngCore, SendData, TimestampMs,
+    TimestampUs, Timings,
+};
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+use heapless::consts::*;
+use heapless::Vec;
+use lorawan_encoding::parser::DecryptedDataPayload;
+
+pub type Downlink = DecryptedDataPayload<Vec<u8, U256>>;
+
+#[allow(async_fn_in_trait)]
+pub trait AsyncRadio {
+    type PhyError: core::fmt::Debug;
+
+    // sends the packet and completes with the time at which the transmission ended
+    async fn tx(&mut self, config: TxConfig, buf: &[u8]) -> Result<TimestampUs, Self::PhyError>;
+    // receives a packet into buf and completes with its length, its quality and the time at
+    // which the reception ended, or None once the symbol timeout expired without a packet. The
+    // future is dropped when the RX window closes so it must stop receiving when dropped.
+    async fn rx(
+        &mut self,
+        config: RxConfig,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, RxQuality, TimestampUs)>, Self::PhyError>;
+}
+
+#[allow(async_fn_in_trait)]
+pub trait Timer {
+    // completes once the clock of the radio timestamps reaches the given time
+    async fn at(&mut self, time: TimestampMs);
+}
+
+#[derive(Debug)]
+pub enum PhyEvent<E> {
+    TxDone(TimestampUs),
+    RxDone(RxQuality, TimestampUs),
+    // the TX or RX failed, the state machine then gives up the join attempt or the uplink
+    Error(E),
+}
+
+const BUFFER_LEN: usize = 256;
+
+// a plain buffer rather than a heapless Vec, whose truncate in heapless 0.6 indexes past the end
+// of the slice
+#[derive(Debug)]
+pub struct Buffer {
+    data: [u8; BUFFER_LEN],
+    len: usize,
+}
+
+impl Default for Buffer {
+    fn default() -> Buffer {
+        Buffer {
+            data: [0; BUFFER_LEN],
+            len: 0,
+        }
+    }
+}
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl AsMut<[u8]> for Buffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..self.len]
+    }
+}
+
+impl PhyRxTxBuf for Buffer {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn extend(&mut self, buf: &[u8]) {
+        self.data[self.len..self.len + buf.len()].copy_from_slice(buf);
+        self.len += buf.len();
+    }
+}
+
+// PhyRxTx given to the state machine: requests are only recorded, the async Device then carries
+// them out and reports their completion as PhyEvents
+#[derive(Debug)]
+pub struct RadioAdapter<R> {
+    radio: R,
+    tx: Option<TxConfig>,
+    tx_buf: Buffer,
+    rx: Option<RxConfig>,
+    rx_buf: Buffer,
+}
+
+impl<R> RadioAdapter<R> {
+    fn new(radio: R) -> RadioAdapter<R> {
+        RadioAdapter {
+            radio,
+            tx: None,
+            tx_buf: Buffer::default(),
+            rx: None,
+            rx_buf: Buffer::default(),
+        }
+    }
+}
+
+impl<R: AsyncRadio> PhyRxTx for RadioAdapter<R> {
+    type PhyBuf = Buffer;
+    type PhyEvent = PhyEvent<R::PhyError>;
+    type PhyError = R::PhyError;
+    type PhyResponse = ();
+
+    fn get_mut_radio(&mut self) -> &mut Self {
+        self
+    }
+
+    fn get_received_packet(&mut self) -> &mut Buffer {
+        &mut self.rx_buf
+    }
+
+    fn handle_event(
+        &mut self,
+        event: radio::Event<Self>,
+    ) -> Result<radio::Response<Self>, radio::Error<Self>> {
+        match event {
+            radio::Event::TxRequest(config, buf) => {
+                self.tx_buf.clear();
+                self.tx_buf.extend(buf.as_ref());
+                self.tx = Some(config);
+                Ok(radio::Response::Txing)
+            }
+            radio::Event::RxRequest(config) => {
+                self.rx = Some(config);
+                Ok(radio::Response::Rxing)
+            }
+            radio::Event::CancelRx => {
+                self.rx = None;
+                Ok(radio::Response::Idle)
+            }
+            radio::Event::PhyEvent(PhyEvent::TxDone(timestamp_us)) => {
+                Ok(radio::Response::TxDone(timestamp_us))
+            }
+            radio::Event::PhyEvent(PhyEvent::RxDone(quality, timestamp_us)) => {
+                Ok(radio::Response::RxDone(quality, timestamp_us))
+            }
+            radio::Event::PhyEvent(PhyEvent::Error(e)) => Err(radio::Error::PhyError(e)),
+        }
+    }
+}
+
+impl<R: Timings> Timings for RadioAdapter<R> {
+    fn get_rx_window_duration_ms(&self) -> u32 {
+        self.radio.get_rx_window_duration_ms()
+    }
+    fn get_rx_window_latency_ms(&self) -> i32 {
+        self.radio.get_rx_window_latency_ms()
+    }
+    fn get_rx_wakeup_time_ms(&self) -> u32 {
+        self.radio.get_rx_wakeup_time_ms()
+    }
+    fn get_rx_timer_error_ms(&self) -> u32 {
+        self.radio.get_rx_timer_error_ms()
+    }
+    fn get_crystal_drift_ppm(&self) -> u32 {
+        self.radio.get_crystal_drift_ppm()
+    }
+    fn get_min_rx_symbols(&self) -> u32 {
+        self.radio.get_min_rx_symbols()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<R: AsyncRadio> {
+    Device(super::Error<RadioAdapter<R>>),
+    // the join procedure was given up after the maximum number of JoinRequests
+    NoJoinAccept,
+    // a confirmed uplink was not acknowledged
+    NoAck,
+    // the session has to be renewed by joining again, a downlink received along can still be
+    // taken from the Device
+    SessionExpired,
+    UnexpectedResponse(Response),
+}
+
+impl<R: AsyncRadio> From<super::Error<RadioAdapter<R>>> for Error<R> {
+    fn from(error: super::Error<RadioAdapter<R>>) -> Error<R> {
+        Error::Device(error)
+    }
+}
+
+pub struct Device<R, C, T, G>
+where
+    R: AsyncRadio + Timings,
+    C: CryptoFactory + Default,
+    T: Timer,
+    G: RngCore,
+{
+    // only taken while the state machine handles an event
+    device: Option<super::Device<RadioAdapter<R>, C, G>>,
+    timer: T,
+}
+
+impl<R, C, T, G> Device<R, C, T, G>
+where
+    R: AsyncRadio + Timings,
+    C: CryptoFactory + Default,
+    T: Timer,
+    G: RngCore,
+{
+    pub fn new(
+        region: region::Configuration,
+        radio: R,
+        timer: T,
+        deveui: [u8; 8],
+        appeui: [u8; 8],
+        appkey: [u8; 16],
+        rng: G,
+    ) -> Device<R, C, T, G> {
+        Device {
+            device: Some(super::Device::new(
+                region,
+                RadioAdapter::new(radio),
+                deveui,
+                appeui,
+                appkey,
+                rng,
+            )),
+            timer,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_v1_1(
+        region: region::Configuration,
+        radio: R,
+        timer: T,
+        deveui: [u8; 8],
+        joineui: [u8; 8],
+        appkey: [u8; 16],
+        nwkkey: [u8; 16],
+        rng: G,
+    ) -> Device<R, C, T, G> {
+        Device {
+            device: Some(super::Device::new_v1_1(
+                region,
+                RadioAdapter::new(radio),
+                deveui,
+                joineui,
+                appkey,
+                nwkkey,
+                rng,
+            )),
+            timer,
+        }
+    }
+
+    // the state machine, e.g. to configure the datarate or get the session keys
+    pub fn get_device(&mut self) -> &mut super::Device<RadioAdapter<R>, C, G> {
+        self.device
+            .as_mut()
+            .expect("device is only taken while handling an event")
+    }
+
+    pub fn get_radio(&mut self) -> &mut R {
+        &mut self.get_device().get_radio().radio
+    }
+
+    pub fn get_credentials(&mut self) -> &mut Credentials {
+        self.get_device().get_credentials()
+    }
+
+    pub fn get_timer(&mut self) -> &mut T {
+        &mut self.timer
+    }
+
+    // Joins the network, failed attempts being retried as configured by the max join attempts
+    // of the Device.
+    pub async fn join(&mut self) -> Result<(), Error<R>> {
+        match self.process(Event::NewSessionRequest).await? {
+            Response::JoinSuccess => Ok(()),
+            Response::NoJoinAccept => Err(Error::NoJoinAccept),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    // Sends an uplink and completes once its RX windows are over, with the downlink received in
+    // one of them if any.
+    pub async fn send(
+        &mut self,
+        data: &[u8],
+        fport: u8,
+        confirmed: bool,
+    ) -> Result<Option<Downlink>, Error<R>> {
+        let event = Event::SendDataRequest(SendData {
+            data,
+            fport,
+            confirmed,
+        });
+        match self.process(event).await? {
+            Response::DownlinkReceived(..) => Ok(self.get_device().take_data_downlink()),
+            Response::ReadyToSend => Ok(None),
+            Response::NoAck => Err(Error::NoAck),
+            Response::SessionExpired => Err(Error::SessionExpired),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    fn handle_event(&mut self, event: Event<RadioAdapter<R>>) -> Result<Response, Error<R>> {
+        let device = self
+            .device
+            .take()
+            .expect("device is only taken while handling an event");
+        let (device, response) = device.handle_event(event);
+        self.device = Some(device);
+        Ok(response?)
+    }
+
+    fn handle_phy_event(&mut self, event: PhyEvent<R::PhyError>) -> Result<Response, Error<R>> {
+        self.handle_event(Event::RadioEvent(radio::Event::PhyEvent(event)))
+    }
+
+    // drives the state machine until it gives a response that is not an intermediate one
+    async fn process(&mut self, event: Event<'_, RadioAdapter<R>>) -> Result<Response, Error<R>> {
+        let mut response = self.handle_event(event)?;
+        loop {
+            response = match response {
+                Response::JoinRequestSending | Response::UplinkSending(_) => {
+                    let adapter = self.get_device().get_radio();
+                    let config = match adapter.tx.take() {
+                        Some(config) => config,
+                        None => return Err(Error::UnexpectedResponse(response)),
+                    };
+                    let event = match adapter.radio.tx(config, adapter.tx_buf.as_ref()).await {
+                        Ok(timestamp_us) => PhyEvent::TxDone(timestamp_us),
+                        Err(e) => PhyEvent::Error(e),
+                    };
+                    self.handle_phy_event(event)?
+                }
+                Response::TimeoutRequest(time) => self.wait(time).await?,
+                response => return Ok(response),
+            }
+        }
+    }
+
+    // waits for a TimeoutRequest, receiving in the meantime if an RX window is open
+    async fn wait(&mut self, time: TimestampMs) -> Result<Response, Error<R>> {
+        let rx_config = self.get_device().get_radio().rx.take();
+        if let Some(config) = rx_config {
+            loop {
+                let adapter = self
+                    .device
+                    .as_mut()
+                    .expect("device is only taken while handling an event")
+                    .get_radio();
+                let rx = adapter.radio.rx(config.clone(), &mut adapter.rx_buf.data);
+                match select(rx, self.timer.at(time)).await {
+                    Either::First(Ok(Some((len, quality, timestamp_us)))) => {
+                        adapter.rx_buf.len = len.min(BUFFER_LEN);
+                        match self.handle_phy_event(PhyEvent::RxDone(quality, timestamp_us))? {
+                            // the packet was not for us, keep receiving until the window times out
+                            Response::NoUpdate | Response::JoinAcceptRejected => (),
+                            response => return Ok(response),
+                        }
+                    }
+                    Either::First(Ok(None)) => {
+                        adapter.rx_buf.clear();
+                        break;
+                    }
+                    Either::First(Err(e)) => {
+                        adapter.rx_buf.clear();
+                        return self.handle_phy_event(PhyEvent::Error(e));
+                    }
+                    Either::Second(()) => {
+                        adapter.rx_buf.clear();
+                        return self.handle_event(Event::TimeoutFired);
+                    }
+                }
+            }
+        }
+        self.timer.at(time).await;
+        self.handle_event(Event::TimeoutFired)
+    }
+}
+
+enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+// completes with the first of two futures to complete, dropping the other one
+async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = a.as_mut().poll(cx) {
+            return Poll::Ready(Either::First(output));
+        }
+        if let Poll::Ready(output) = b.as_mut().poll(cx) {
+            return Poll::Ready(Either::Second(output));
+        }
+        Poll::Pending
+    })
+    .await
+}
@@ -2,9 +2,13 @@
 
 use heapless::consts::*;
 use heapless::Vec;
+use rand_core::RngCore;
 
 pub mod radio;
 
+#[cfg(feature = "async")]
+pub mod async_device;
+
 mod mac;
 use mac::Mac;
 
@@ -18,16 +22,18 @@ mod state_machines;
 use core::marker::PhantomData;
 use lorawan_encoding::{keys::CryptoFactory, parser::DecryptedDataPayload};
 use state_machines::Shared;
-pub use state_machines::{no_session, session, JoinAccept};
+pub use state_machines::{no_session, session, JoinAccept, RxMetadata};
 
 type TimestampMs = u32;
+type TimestampUs = u64;
 
-pub struct Device<R, C>
+pub struct Device<R, C, G>
 where
     R: radio::PhyRxTx + Timings,
     C: CryptoFactory + Default,
+    G: RngCore,
 {
-    state: State<R>,
+    state: State<R, G>,
     crypto: PhantomData<C>,
 }
 
@@ -40,9 +46,14 @@ pub enum Response {
     TimeoutRequest(TimestampMs),
     JoinRequestSending,
     JoinSuccess,
+    // a join accept was received but failed the MIC check or replayed a JoinNonce and was ignored
+    JoinAcceptRejected,
+    // the join procedure was given up, either after the maximum number of JoinRequests or
+    // because a JoinRequest could not be sent
     NoJoinAccept,
     UplinkSending(FcntUp),
-    DownlinkReceived(FcntDown),
+    // the FCnt of the downlink and its FPending, set when the network has more downlinks queued
+    DownlinkReceived(FcntDown, bool),
     NoAck,
     ReadyToSend,
     SessionExpired,
@@ -53,6 +64,7 @@ pub enum Error<R: radio::PhyRxTx> {
     Radio(radio::Error<R>),
     Session(session::Error),
     NoSession(no_session::Error),
+    Region(region::Error),
 }
 
 impl<R> From<radio::Error<R>> for Error<R>
@@ -64,6 +76,15 @@ where
     }
 }
 
+impl<R> From<region::Error> for Error<R>
+where
+    R: radio::PhyRxTx,
+{
+    fn from(region_error: region::Error) -> Error<R> {
+        Error::Region(region_error)
+    }
+}
+
 pub enum Event<'a, R>
 where
     R: radio::PhyRxTx,
@@ -95,34 +116,57 @@ pub struct SendData<'a> {
     confirmed: bool,
 }
 
-pub enum State<R>
+pub enum State<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    NoSession(no_session::NoSession<R>),
-    Session(session::Session<R>),
+    NoSession(no_session::NoSession<R, G>),
+    Session(session::Session<R, G>),
 }
 
 use core::default::Default;
-impl<R> State<R>
+impl<R, G> State<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    fn new(shared: Shared<R>) -> Self {
+    fn new(shared: Shared<R, G>) -> Self {
         State::NoSession(no_session::NoSession::new(shared))
     }
 }
 
 pub trait Timings {
-    fn get_rx_window_offset_ms(&self) -> i32;
     fn get_rx_window_duration_ms(&self) -> u32;
+    // added to the offset at which the radio is started, which is computed from the timing errors
+    // below, e.g. to compensate a fixed latency of the platform
+    fn get_rx_window_latency_ms(&self) -> i32 {
+        0
+    }
+    // time between the RX request and the radio actually receiving
+    fn get_rx_wakeup_time_ms(&self) -> u32 {
+        0
+    }
+    // maximum error of the timer scheduling the RX windows
+    fn get_rx_timer_error_ms(&self) -> u32 {
+        10
+    }
+    // accuracy of the crystal of the timer scheduling the RX windows
+    fn get_crystal_drift_ppm(&self) -> u32 {
+        0
+    }
+    // number of preamble symbols the radio needs to detect a packet
+    fn get_min_rx_symbols(&self) -> u32 {
+        6
+    }
 }
 
 #[allow(dead_code)]
-impl<R, C> Device<R, C>
+impl<R, C, G> Device<R, C, G>
 where
     R: radio::PhyRxTx + Timings,
     C: CryptoFactory + Default,
+    G: RngCore,
 {
     pub fn new(
         region: region::Configuration,
@@ -130,8 +174,8 @@ where
         deveui: [u8; 8],
         appeui: [u8; 8],
         appkey: [u8; 16],
-        get_random: fn() -> u32,
-    ) -> Device<R, C> {
+        rng: G,
+    ) -> Device<R, C, G> {
         Device {
             crypto: PhantomData::default(),
             state: State::new(Shared::new(
@@ -139,7 +183,29 @@ where
                 Credentials::new(appeui, deveui, appkey),
                 region,
                 Mac::default(),
-                get_random,
+                rng,
+                Default::default(),
+            )),
+        }
+    }
+
+    pub fn new_v1_1(
+        region: region::Configuration,
+        radio: R,
+        deveui: [u8; 8],
+        joineui: [u8; 8],
+        appkey: [u8; 16],
+        nwkkey: [u8; 16],
+        rng: G,
+    ) -> Device<R, C, G> {
+        Device {
+            crypto: PhantomData::default(),
+            state: State::new(Shared::new(
+                radio,
+                Credentials::new_v1_1(joineui, deveui, appkey, nwkkey),
+                region,
+                Mac::default(),
+                rng,
                 Default::default(),
             )),
         }
@@ -155,7 +221,7 @@ where
         shared.get_mut_credentials()
     }
 
-    fn get_shared(&mut self) -> &mut Shared<R> {
+    fn get_shared(&mut self) -> &mut Shared<R, G> {
         match &mut self.state {
             State::NoSession(state) => state.get_mut_shared(),
             State::Session(state) => state.get_mut_shared(),
@@ -170,6 +236,46 @@ where
         self.get_shared().set_datarate(datarate);
     }
 
+    // Largest application payload that can be sent at the current datarate. Pending MAC commands
+    // that do not fit next to the payload are deferred to a later uplink.
+    pub fn max_payload_len(&mut self) -> Result<usize, region::Error> {
+        self.get_shared().get_max_payload_len()
+    }
+
+    // Once FCntUp reaches the threshold, SessionExpired is reported so that the device rejoins
+    // before the frame counter runs out. Without a threshold it is reported only when all 32-bit
+    // values have been used.
+    pub fn set_rejoin_threshold(&mut self, rejoin_threshold: Option<u32>) {
+        self.get_shared().set_rejoin_threshold(rejoin_threshold);
+    }
+
+    // Failed joins are retried automatically, the TimeoutRequest of the retry respecting the join
+    // duty cycle. Once the maximum number of JoinRequests is reached, NoJoinAccept is reported.
+    // It defaults to 16 JoinRequests; without a maximum the join procedure is retried until it
+    // succeeds.
+    pub fn set_max_join_attempts(&mut self, max_join_attempts: Option<usize>) {
+        self.get_shared().set_max_join_attempts(max_join_attempts);
+    }
+
+    // The DevNonce and JoinNonce of a LoRaWAN 1.1 device must be persisted across resets, the
+    // join server rejecting reused DevNonces and the device rejecting JoinNonces that are not
+    // greater than the last accepted one.
+    pub fn get_devnonce(&mut self) -> Option<u16> {
+        self.get_credentials().devnonce()
+    }
+
+    pub fn set_devnonce(&mut self, devnonce: Option<u16>) {
+        self.get_credentials().set_devnonce(devnonce);
+    }
+
+    pub fn get_joinnonce(&mut self) -> Option<u32> {
+        self.get_credentials().joinnonce()
+    }
+
+    pub fn set_joinnonce(&mut self, joinnonce: Option<u32>) {
+        self.get_credentials().set_joinnonce(joinnonce);
+    }
+
     pub fn ready_to_send_data(&self) -> bool {
         matches!(&self.state, State::Session(session::Session::Idle(_)))
     }
@@ -195,6 +301,27 @@ where
         }
     }
 
+    // Whether a confirmed downlink still has to be acknowledged. The ACK is sent with the next
+    // uplink, which may be an empty one if the application has no data to send.
+    pub fn ack_pending(&self) -> bool {
+        if let State::Session(session) = &self.state {
+            session.get_session_data().ack_pending.is_some()
+        } else {
+            false
+        }
+    }
+
+    // Whether the last downlink had FPending set, as reported by DownlinkReceived, the network
+    // having more downlinks queued. An uplink, which may be an empty one, lets the network send
+    // the next one.
+    pub fn downlink_pending(&self) -> bool {
+        if let State::Session(session) = &self.state {
+            session.get_session_data().downlink_pending
+        } else {
+            false
+        }
+    }
+
     pub fn get_session_keys(&self) -> Option<SessionKeys> {
         if let State::Session(session) = &self.state {
             Some(SessionKeys::copy_from_session_data(
@@ -213,6 +340,11 @@ where
         self.get_shared().take_join_accept()
     }
 
+    // reception details of the last accepted downlink, e.g. for time synchronisation
+    pub fn get_rx_metadata(&mut self) -> Option<RxMetadata> {
+        self.get_shared().get_rx_metadata()
+    }
+
     pub fn handle_event(self, event: Event<R>) -> (Self, Result<Response, Error<R>>) {
         match self.state {
             State::NoSession(state) => state.handle_event(event),
@@ -6,31 +6,38 @@ During Uplink assembly, this struct will be inquired to drive construction
 use heapless::consts::*;
 use heapless::Vec;
 
-use super::region;
-use lorawan_encoding::maccommands::{LinkADRAnsPayload, MacCommand};
+use super::region::{self, constants::ADR_ACK_LIMIT};
+use lorawan_encoding::maccommands::{LinkADRAnsPayload, MacCommand, RekeyIndPayload};
 
 #[derive(Default, Debug)]
 pub struct Mac {
     adr_ans: AdrAns,
+    // LoRaWAN 1.1 devices repeat RekeyInd until RekeyConf is received, counting the uplinks
+    // that carried it
+    rekey_ind: Option<u8>,
 }
 
 type AdrAns = u8;
 
+// LinkADRAns kept pending while they are deferred, leaving room for a RekeyInd in the commands
+// of an uplink
+const MAX_ADR_ANS: u8 = 7;
+
 //work around for E0390
 trait AdrAnsTrait {
     fn add(&mut self);
     fn clear(&mut self);
-    fn get(&mut self) -> u8;
+    fn get(&self) -> u8;
 }
 
 impl AdrAnsTrait for AdrAns {
     fn add(&mut self) {
-        *self += 1;
+        *self = self.saturating_add(1).min(MAX_ADR_ANS);
     }
     fn clear(&mut self) {
         *self = 0;
     }
-    fn get(&mut self) -> u8 {
+    fn get(&self) -> u8 {
         *self
     }
 }
@@ -42,21 +49,70 @@ impl Mac {
         cmds: &mut lorawan_encoding::maccommands::MacCommandIterator,
     ) {
         for cmd in cmds {
-            if let MacCommand::LinkADRReq(payload) = cmd {
-                // we ignore DR and TxPwr
-                region.set_channel_mask(payload.channel_mask());
-                self.adr_ans.add();
+            match cmd {
+                MacCommand::LinkADRReq(payload) => {
+                    // we ignore DR and TxPwr
+                    region.set_channel_mask(
+                        payload.redundancy().channel_mask_control(),
+                        payload.channel_mask(),
+                    );
+                    self.adr_ans.add();
+                }
+                MacCommand::RekeyConf(_) => self.rekey_ind = None,
+                _ => (),
             }
         }
     }
 
-    pub fn get_cmds(&mut self, macs: &mut Vec<MacCommand, U8>) {
+    pub fn set_rekey_ind(&mut self, rekey_ind: bool) {
+        self.rekey_ind = if rekey_ind { Some(0) } else { None };
+    }
+
+    // the session is given up once ADR_ACK_LIMIT uplinks carried RekeyInd without a RekeyConf
+    pub fn rekey_ind_expired(&self) -> bool {
+        self.rekey_ind
+            .is_some_and(|uplinks| uplinks as usize >= ADR_ACK_LIMIT)
+    }
+
+    // the commands stay pending until cmds_sent is called, so they can be deferred when they do
+    // not fit in an uplink
+    pub fn get_cmds(&self, macs: &mut Vec<MacCommand, U8>) {
         for _ in 0..self.adr_ans.get() {
-            macs.push(MacCommand::LinkADRAns(
-                LinkADRAnsPayload::new(&[0x07]).unwrap(),
-            ))
-            .unwrap();
+            let cmd = MacCommand::LinkADRAns(LinkADRAnsPayload::new(&[0x07]).unwrap());
+            if macs.push(cmd).is_err() {
+                return;
+            }
         }
+        if self.rekey_ind.is_some() {
+            // we only support LoRaWAN 1.1
+            let cmd = MacCommand::RekeyInd(RekeyIndPayload::new(&[0x01]).unwrap());
+            // a full list only defers it, RekeyInd being repeated until RekeyConf
+            macs.push(cmd).ok();
+        }
+    }
+
+    pub fn cmds_sent(&mut self) {
         self.adr_ans.clear();
+        if let Some(uplinks) = &mut self.rekey_ind {
+            *uplinks = uplinks.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deferred_adr_ans_are_capped() {
+        let mut mac = Mac::default();
+        for _ in 0..300 {
+            mac.adr_ans.add();
+        }
+        mac.set_rekey_ind(true);
+        let mut cmds = Vec::new();
+        mac.get_cmds(&mut cmds);
+        assert_eq!(cmds.len(), 8);
+        assert!(matches!(cmds[7], MacCommand::RekeyInd(_)));
     }
 }
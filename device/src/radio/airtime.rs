@@ -0,0 +1,261 @@
+// Time on air of LoRa and FSK packets, in microseconds.
+//
+// The LoRa computation follows the Semtech SX127x datasheet (section 4.1.1.7). It is done with
+// integer arithmetic only: the symbol time is always a multiple of 4 µs so the 4.25 symbols of the
+// preamble are exact.
+
+use super::{Bandwidth, CodingRate, RfConfig, SpreadingFactor};
+
+// LoRaWAN uses an 8 symbol preamble for LoRa and a 5 byte preamble for FSK
+const LORA_PREAMBLE_LEN: u16 = 8;
+const FSK_PREAMBLE_LEN: u16 = 5;
+// LDRO is mandated when the symbol time reaches 16 ms
+const LOW_DATA_RATE_OPTIMIZE_SYMBOL_TIME_US: u32 = 16_000;
+
+#[derive(Debug, Clone)]
+pub struct LoRaPacketConfig {
+    pub spreading_factor: SpreadingFactor,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    pub preamble_len: u16,
+    pub explicit_header: bool,
+    pub crc: bool,
+    pub low_data_rate_optimize: bool,
+}
+
+impl LoRaPacketConfig {
+    // packet settings used by LoRaWAN uplinks, downlinks have no payload CRC
+    pub fn new(
+        spreading_factor: SpreadingFactor,
+        bandwidth: Bandwidth,
+        coding_rate: CodingRate,
+    ) -> LoRaPacketConfig {
+        let low_data_rate_optimize =
+            symbol_time_us(&spreading_factor, &bandwidth) >= LOW_DATA_RATE_OPTIMIZE_SYMBOL_TIME_US;
+        LoRaPacketConfig {
+            spreading_factor,
+            bandwidth,
+            coding_rate,
+            preamble_len: LORA_PREAMBLE_LEN,
+            explicit_header: true,
+            crc: true,
+            low_data_rate_optimize,
+        }
+    }
+
+    pub fn set_preamble_len(&mut self, preamble_len: u16) -> &mut Self {
+        self.preamble_len = preamble_len;
+        self
+    }
+
+    pub fn set_explicit_header(&mut self, explicit_header: bool) -> &mut Self {
+        self.explicit_header = explicit_header;
+        self
+    }
+
+    pub fn set_crc(&mut self, crc: bool) -> &mut Self {
+        self.crc = crc;
+        self
+    }
+
+    pub fn set_low_data_rate_optimize(&mut self, low_data_rate_optimize: bool) -> &mut Self {
+        self.low_data_rate_optimize = low_data_rate_optimize;
+        self
+    }
+
+    pub fn symbol_time_us(&self) -> u32 {
+        symbol_time_us(&self.spreading_factor, &self.bandwidth)
+    }
+
+    // number of symbols of the payload, including the 8 symbols sent with the header
+    pub fn payload_symbols(&self, payload_len: usize) -> u32 {
+        let sf = spreading_factor_value(&self.spreading_factor) as i32;
+        let de = self.low_data_rate_optimize as i32;
+        let numerator = 8 * payload_len as i32 - 4 * sf + 28 + 16 * self.crc as i32
+            - 20 * (!self.explicit_header) as i32;
+        let denominator = 4 * (sf - 2 * de);
+        let blocks = if numerator > 0 {
+            (numerator as u32).div_ceil(denominator as u32)
+        } else {
+            0
+        };
+        8 + blocks * (coding_rate_value(&self.coding_rate) + 4)
+    }
+
+    /// Time on air of a packet with the given PHYPayload length in microseconds.
+    ///
+    /// ```
+    /// use lorawan_device::radio::*;
+    ///
+    /// let config =
+    ///     LoRaPacketConfig::new(SpreadingFactor::_7, Bandwidth::_125KHz, CodingRate::_4_5);
+    /// assert_eq!(config.time_on_air_us(13), 46_336);
+    /// ```
+    pub fn time_on_air_us(&self, payload_len: usize) -> u32 {
+        // the preamble lasts preamble_len + 4.25 symbols
+        let quarter_symbols =
+            4 * (self.preamble_len as u32 + self.payload_symbols(payload_len)) + 17;
+        quarter_symbols * (self.symbol_time_us() / 4)
+    }
+}
+
+impl From<&RfConfig> for LoRaPacketConfig {
+    fn from(rf: &RfConfig) -> LoRaPacketConfig {
+        LoRaPacketConfig::new(
+            rf.spreading_factor.clone(),
+            rf.bandwidth.clone(),
+            rf.coding_rate.clone(),
+        )
+    }
+}
+
+impl RfConfig {
+    pub fn time_on_air_us(&self, payload_len: usize) -> u32 {
+        LoRaPacketConfig::from(self).time_on_air_us(payload_len)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FskPacketConfig {
+    pub bitrate: u32,
+    pub preamble_len: u16,
+    pub sync_word_len: u8,
+    pub length_field: bool,
+    pub crc: bool,
+}
+
+impl FskPacketConfig {
+    // packet settings used by LoRaWAN: 3 byte sync word, length byte and CRC
+    pub fn new(bitrate: u32) -> FskPacketConfig {
+        FskPacketConfig {
+            bitrate,
+            preamble_len: FSK_PREAMBLE_LEN,
+            sync_word_len: 3,
+            length_field: true,
+            crc: true,
+        }
+    }
+
+    pub fn set_preamble_len(&mut self, preamble_len: u16) -> &mut Self {
+        self.preamble_len = preamble_len;
+        self
+    }
+
+    pub fn set_sync_word_len(&mut self, sync_word_len: u8) -> &mut Self {
+        self.sync_word_len = sync_word_len;
+        self
+    }
+
+    pub fn set_length_field(&mut self, length_field: bool) -> &mut Self {
+        self.length_field = length_field;
+        self
+    }
+
+    pub fn set_crc(&mut self, crc: bool) -> &mut Self {
+        self.crc = crc;
+        self
+    }
+
+    /// Time on air of a packet with the given PHYPayload length in microseconds.
+    ///
+    /// ```
+    /// use lorawan_device::radio::FskPacketConfig;
+    ///
+    /// assert_eq!(FskPacketConfig::new(50_000).time_on_air_us(13), 3_840);
+    /// ```
+    pub fn time_on_air_us(&self, payload_len: usize) -> u32 {
+        let bytes = self.preamble_len as u64
+            + self.sync_word_len as u64
+            + self.length_field as u64
+            + payload_len as u64
+            + 2 * self.crc as u64;
+        // rounded up to the next microsecond
+        (bytes * 8 * 1_000_000).div_ceil(self.bitrate as u64) as u32
+    }
+}
+
+fn symbol_time_us(spreading_factor: &SpreadingFactor, bandwidth: &Bandwidth) -> u32 {
+    (1 << spreading_factor_value(spreading_factor)) * 1_000_000 / bandwidth_hz(bandwidth)
+}
+
+fn spreading_factor_value(spreading_factor: &SpreadingFactor) -> u32 {
+    match spreading_factor {
+        SpreadingFactor::_7 => 7,
+        SpreadingFactor::_8 => 8,
+        SpreadingFactor::_9 => 9,
+        SpreadingFactor::_10 => 10,
+        SpreadingFactor::_11 => 11,
+        SpreadingFactor::_12 => 12,
+    }
+}
+
+fn bandwidth_hz(bandwidth: &Bandwidth) -> u32 {
+    match bandwidth {
+        Bandwidth::_125KHz => 125_000,
+        Bandwidth::_250KHz => 250_000,
+        Bandwidth::_500KHz => 500_000,
+    }
+}
+
+fn coding_rate_value(coding_rate: &CodingRate) -> u32 {
+    match coding_rate {
+        CodingRate::_4_5 => 1,
+        CodingRate::_4_6 => 2,
+        CodingRate::_4_7 => 3,
+        CodingRate::_4_8 => 4,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // reference values computed with the floating point formula of the Semtech LoRa calculator
+
+    #[test]
+    fn test_low_data_rate_optimize() {
+        let mut sf12 =
+            LoRaPacketConfig::new(SpreadingFactor::_12, Bandwidth::_125KHz, CodingRate::_4_5);
+        assert!(sf12.low_data_rate_optimize);
+        assert_eq!(sf12.time_on_air_us(64), 2_793_472);
+        let sf11 =
+            LoRaPacketConfig::new(SpreadingFactor::_11, Bandwidth::_125KHz, CodingRate::_4_5);
+        assert!(sf11.low_data_rate_optimize);
+        assert_eq!(sf11.time_on_air_us(64), 1_560_576);
+        let sf10 =
+            LoRaPacketConfig::new(SpreadingFactor::_10, Bandwidth::_125KHz, CodingRate::_4_5);
+        assert!(!sf10.low_data_rate_optimize);
+        assert_eq!(sf10.time_on_air_us(64), 698_368);
+
+        sf12.set_low_data_rate_optimize(false);
+        assert_eq!(sf12.time_on_air_us(64), 2_465_792);
+    }
+
+    #[test]
+    fn test_implicit_header() {
+        let mut config =
+            LoRaPacketConfig::new(SpreadingFactor::_7, Bandwidth::_125KHz, CodingRate::_4_5);
+        config.set_explicit_header(false);
+        assert_eq!(config.time_on_air_us(11), 36_096);
+        assert_eq!(config.time_on_air_us(13), 41_216);
+    }
+
+    #[test]
+    fn test_no_crc() {
+        let mut config =
+            LoRaPacketConfig::new(SpreadingFactor::_7, Bandwidth::_125KHz, CodingRate::_4_5);
+        config.set_crc(false);
+        assert_eq!(config.time_on_air_us(11), 41_216);
+        assert_eq!(config.time_on_air_us(13), 41_216);
+    }
+
+    #[test]
+    fn test_only_header_symbols() {
+        // the payload fits in the 8 symbols sent with the header
+        let mut config =
+            LoRaPacketConfig::new(SpreadingFactor::_9, Bandwidth::_125KHz, CodingRate::_4_5);
+        config.set_explicit_header(false).set_crc(false);
+        assert_eq!(config.payload_symbols(0), 8);
+        assert_eq!(config.time_on_air_us(0), 82_944);
+    }
+}
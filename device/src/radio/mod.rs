@@ -1,7 +1,10 @@
 mod types;
 pub use types::*;
 
-use super::TimestampMs;
+mod airtime;
+pub use airtime::*;
+
+use super::TimestampUs;
 use heapless::{ArrayLength, Vec};
 
 #[derive(Debug)]
@@ -10,7 +13,7 @@ where
     R: PhyRxTx,
 {
     TxRequest(TxConfig, &'a mut R::PhyBuf),
-    RxRequest(RfConfig),
+    RxRequest(RxConfig),
     CancelRx,
     PhyEvent(R::PhyEvent),
 }
@@ -23,8 +26,10 @@ where
     Idle,
     Txing,
     Rxing,
-    TxDone(TimestampMs),
-    RxDone(RxQuality),
+    // timestamps are in µs of a monotonic clock, the RX windows are then requested as timeouts
+    // in ms of the same clock
+    TxDone(TimestampUs),
+    RxDone(RxQuality, TimestampUs),
     PhyResponse(R::PhyResponse),
 }
 
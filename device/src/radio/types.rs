@@ -23,7 +23,7 @@ pub enum CodingRate {
     _4_8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RfConfig {
     pub frequency: u32,
     pub bandwidth: Bandwidth,
@@ -37,6 +37,13 @@ pub struct TxConfig {
     pub rf: RfConfig,
 }
 
+#[derive(Debug, Clone)]
+pub struct RxConfig {
+    pub rf: RfConfig,
+    // number of symbols after which the radio stops receiving if no preamble was detected
+    pub symbol_timeout: u16,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct RxQuality {
     rssi: i16,
@@ -22,3 +22,6 @@ pub(crate) const DATARATES: [Datarate; 5] = [
         bandwidth: Bandwidth::_125KHz,
     },
 ];
+
+// maximum FRMPayload size N of the uplink datarates
+pub(crate) const MAX_PAYLOAD_LEN: [usize; 5] = [51, 51, 51, 115, 222];
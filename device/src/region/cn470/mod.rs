@@ -12,15 +12,30 @@ use datarates::*;
 pub struct CN470 {
     last_tx: usize,
     cf_list: Option<[u32; 5]>,
+    // channels enabled by the CFList of the JoinAccept
+    enabled_channels: Option<u128>,
+    hopper: ChannelHopper,
 }
 
 impl CN470 {
     pub fn new() -> CN470 {
         Self::default()
     }
+
+    fn select_channel(&mut self, datarate: DR, enabled: u128, random: u32) -> Result<u32, Error> {
+        let channel = self
+            .hopper
+            .select(enabled, random)
+            .ok_or(Error::NoChannelAvailable(datarate))?;
+        self.last_tx = channel;
+        Ok(UPLINK_MAP[channel])
+    }
 }
 
+const ALL_CHANNELS_ENABLED: u128 = (1 << UPLINK_MAP.len()) - 1;
+
 use super::JoinAccept;
+use lorawan_encoding::parser::CfList;
 
 impl RegionHandler for CN470 {
     fn process_join_accept<T: core::convert::AsRef<[u8]>, C>(
@@ -28,10 +43,19 @@ impl RegionHandler for CN470 {
         join_accept: &super::DecryptedJoinAcceptPayload<T, C>,
     ) -> JoinAccept {
         let mut new_cf_list = [0, 0, 0, 0, 0];
-        if let Some(cf_list) = join_accept.c_f_list() {
-            for (index, freq) in cf_list.iter().enumerate() {
-                new_cf_list[index] = freq.value();
+        match join_accept.c_f_list() {
+            Some(CfList::DynamicChannel(cf_list)) => {
+                for (index, freq) in cf_list.iter().enumerate() {
+                    new_cf_list[index] = freq.value();
+                }
+            }
+            Some(CfList::FixedChannel(masks)) => {
+                let enabled = enabled_channels(&masks) & ALL_CHANNELS_ENABLED;
+                if enabled != 0 {
+                    self.enabled_channels = Some(enabled);
+                }
             }
+            None => (),
         }
         self.cf_list = Some(new_cf_list);
         JoinAccept {
@@ -39,16 +63,17 @@ impl RegionHandler for CN470 {
         }
     }
 
-    fn get_join_frequency(&mut self, random: u8) -> u32 {
-        let channel = random as usize % UPLINK_MAP.len();
-        self.last_tx = channel;
-        UPLINK_MAP[channel]
+    fn get_join_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        self.select_channel(datarate, ALL_CHANNELS_ENABLED, random)
     }
 
-    fn get_data_frequency(&mut self, random: u8) -> u32 {
-        let channel = random as usize % UPLINK_MAP.len();
-        self.last_tx = channel;
-        UPLINK_MAP[channel]
+    fn get_data_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        let enabled = self.enabled_channels.unwrap_or(ALL_CHANNELS_ENABLED);
+        self.select_channel(datarate, enabled, random)
+    }
+
+    fn get_tx_channel(&self) -> u8 {
+        self.last_tx as u8
     }
 
     fn get_rx_frequency(&self, _frame: &Frame, window: &Window) -> u32 {
@@ -58,14 +83,22 @@ impl RegionHandler for CN470 {
         }
     }
 
-    fn get_tx_datarate(&self, datarate: DR, _frame: &Frame) -> Datarate {
-        DATARATES[datarate as usize].clone()
+    fn get_datarate(&self, datarate: DR) -> Result<Datarate, Error> {
+        DATARATES
+            .get(datarate as usize)
+            .cloned()
+            .ok_or(Error::InvalidDatarate(datarate))
     }
-    fn get_rx_datarate(&self, datarate: DR, _frame: &Frame, window: &Window) -> Datarate {
-        let datarate = match window {
+    fn get_rx_dr(&self, datarate: DR, _frame: &Frame, window: &Window) -> Result<DR, Error> {
+        Ok(match window {
             Window::_1 => datarate,
             Window::_2 => DR::_0,
-        };
-        DATARATES[datarate as usize].clone()
+        })
+    }
+    fn get_max_payload_len(&self, datarate: DR) -> Result<usize, Error> {
+        MAX_PAYLOAD_LEN
+            .get(datarate as usize)
+            .copied()
+            .ok_or(Error::InvalidDatarate(datarate))
     }
 }
@@ -31,3 +31,6 @@ pub(crate) const DATARATES: [Datarate; 7] = [
     },
     //ignore FSK data rate for now
 ];
+
+// maximum FRMPayload size N of the uplink datarates
+pub(crate) const MAX_PAYLOAD_LEN: [usize; 7] = [51, 51, 51, 115, 222, 222, 222];
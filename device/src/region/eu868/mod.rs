@@ -12,15 +12,48 @@ pub struct EU868 {
     subband: Option<u8>,
     last_tx: usize,
     cf_list: Option<[u32; 5]>,
+    hopper: ChannelHopper,
 }
 
 impl EU868 {
     pub fn new() -> EU868 {
         Self::default()
     }
+
+    // the join channels followed by the ones of the CFList
+    fn channel_frequency(&self, channel: usize) -> u32 {
+        match (channel.checked_sub(JOIN_CHANNELS.len()), self.cf_list) {
+            (Some(cf_list_channel), Some(cf_list)) => cf_list[cf_list_channel],
+            _ => JOIN_CHANNELS[channel],
+        }
+    }
+
+    // datarates allowed on a channel, the defaults as NewChannelReq is not supported
+    fn channel_datarates(&self, _channel: usize) -> (DR, DR) {
+        (DR::_0, DR::_5)
+    }
+
+    fn select_channel(&mut self, datarate: DR, enabled: u128, random: u32) -> Result<u32, Error> {
+        let enabled = (0..JOIN_CHANNELS.len() + 5)
+            .filter(|channel| {
+                let (min, max) = self.channel_datarates(*channel);
+                (min as u8..=max as u8).contains(&(datarate as u8))
+            })
+            .fold(0, |allowed, channel| allowed | 1 << channel)
+            & enabled;
+        let channel = self
+            .hopper
+            .select(enabled, random)
+            .ok_or(Error::NoChannelAvailable(datarate))?;
+        self.last_tx = channel;
+        Ok(self.channel_frequency(channel))
+    }
 }
 
+const JOIN_CHANNELS_ENABLED: u128 = 0b111;
+
 use super::JoinAccept;
+use lorawan_encoding::parser::CfList;
 
 impl RegionHandler for EU868 {
     fn process_join_accept<T: core::convert::AsRef<[u8]>, C>(
@@ -28,7 +61,7 @@ impl RegionHandler for EU868 {
         join_accept: &super::DecryptedJoinAcceptPayload<T, C>,
     ) -> JoinAccept {
         let mut new_cf_list = [0, 0, 0, 0, 0];
-        if let Some(cf_list) = join_accept.c_f_list() {
+        if let Some(CfList::DynamicChannel(cf_list)) = join_accept.c_f_list() {
             for (index, freq) in cf_list.iter().enumerate() {
                 new_cf_list[index] = freq.value();
             }
@@ -39,55 +72,65 @@ impl RegionHandler for EU868 {
         }
     }
 
-    fn get_join_frequency(&mut self, random: u8) -> u32 {
-        let channel = random as usize % JOIN_CHANNELS.len();
-        self.last_tx = channel;
-        JOIN_CHANNELS[channel]
+    fn get_join_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        self.select_channel(datarate, JOIN_CHANNELS_ENABLED, random)
     }
 
-    fn get_data_frequency(&mut self, random: u8) -> u32 {
+    fn get_data_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        let mut enabled = JOIN_CHANNELS_ENABLED;
         if let Some(cf_list) = self.cf_list {
-            let channel = random as usize & 0b111;
-            self.last_tx = channel;
-            if channel < JOIN_CHANNELS.len() {
-                JOIN_CHANNELS[channel]
-            } else {
-                cf_list[channel - JOIN_CHANNELS.len()]
+            for (i, frequency) in cf_list.iter().enumerate() {
+                if *frequency != 0 {
+                    enabled |= 1 << (JOIN_CHANNELS.len() + i);
+                }
             }
-        } else {
-            let channel = random as usize % JOIN_CHANNELS.len();
-            self.last_tx = channel;
-            JOIN_CHANNELS[channel]
         }
+        self.select_channel(datarate, enabled, random)
+    }
+
+    fn get_tx_channel(&self) -> u8 {
+        self.last_tx as u8
     }
 
     fn get_rx_frequency(&self, _frame: &Frame, window: &Window) -> u32 {
         match window {
-            Window::_1 => {
-                if let Some(cf_list) = self.cf_list {
-                    let channel = self.last_tx;
-                    if channel < JOIN_CHANNELS.len() {
-                        JOIN_CHANNELS[channel]
-                    } else {
-                        cf_list[channel - JOIN_CHANNELS.len()]
-                    }
-                } else {
-                    let channel = self.last_tx;
-                    JOIN_CHANNELS[channel]
-                }
-            }
+            Window::_1 => self.channel_frequency(self.last_tx),
             Window::_2 => 869_525_000,
         }
     }
 
-    fn get_tx_datarate(&self, datarate: DR, _frame: &Frame) -> Datarate {
-        DATARATES[datarate as usize].clone()
+    fn get_datarate(&self, datarate: DR) -> Result<Datarate, Error> {
+        DATARATES
+            .get(datarate as usize)
+            .cloned()
+            .ok_or(Error::InvalidDatarate(datarate))
     }
-    fn get_rx_datarate(&self, datarate: DR, _frame: &Frame, window: &Window) -> Datarate {
-        let datarate = match window {
+    fn get_rx_dr(&self, datarate: DR, _frame: &Frame, window: &Window) -> Result<DR, Error> {
+        Ok(match window {
             Window::_1 => datarate,
             Window::_2 => DR::_0,
-        };
-        DATARATES[datarate as usize].clone()
+        })
+    }
+    fn get_max_payload_len(&self, datarate: DR) -> Result<usize, Error> {
+        MAX_PAYLOAD_LEN
+            .get(datarate as usize)
+            .copied()
+            .ok_or(Error::InvalidDatarate(datarate))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_channel_datarates() {
+        let mut eu868 = EU868::new();
+        assert!(eu868.get_data_frequency(DR::_5, 0).is_ok());
+        // SF7BW250 is not allowed on the default channels
+        assert!(matches!(
+            eu868.get_data_frequency(DR::_6, 0),
+            Err(Error::NoChannelAvailable(DR::_6))
+        ));
     }
 }
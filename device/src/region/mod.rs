@@ -2,7 +2,7 @@
 // generally, we allow upper_case_acronyms to make it match the LoRaWAN naming conventions better
 use lorawan_encoding::maccommands::ChannelMask;
 
-mod constants;
+pub(crate) mod constants;
 pub(crate) use crate::radio::*;
 use constants::*;
 
@@ -43,6 +43,37 @@ pub enum DR {
     _15 = 15,
 }
 
+impl DR {
+    // the next slower datarate, DR0 being the slowest
+    pub(crate) fn lower(self) -> DR {
+        match self {
+            DR::_0 | DR::_1 => DR::_0,
+            DR::_2 => DR::_1,
+            DR::_3 => DR::_2,
+            DR::_4 => DR::_3,
+            DR::_5 => DR::_4,
+            DR::_6 => DR::_5,
+            DR::_7 => DR::_6,
+            DR::_8 => DR::_7,
+            DR::_9 => DR::_8,
+            DR::_10 => DR::_9,
+            DR::_11 => DR::_10,
+            DR::_12 => DR::_11,
+            DR::_13 => DR::_12,
+            DR::_14 => DR::_13,
+            DR::_15 => DR::_14,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // the datarate is not defined in the region or can not be used in this context
+    InvalidDatarate(DR),
+    // none of the enabled channels allows the datarate
+    NoChannelAvailable(DR),
+}
+
 #[derive(Debug, Clone)]
 pub enum Region {
     US915,
@@ -146,20 +177,26 @@ impl Configuration {
         self.join_accept_delay2 = delay;
     }
 
-    pub(crate) fn create_tx_config(&mut self, random: u8, datarate: DR, frame: &Frame) -> TxConfig {
-        let datarate = self.get_tx_datarate(datarate, frame);
-        TxConfig {
+    pub(crate) fn create_tx_config(
+        &mut self,
+        random: u32,
+        datarate: DR,
+        frame: &Frame,
+    ) -> Result<TxConfig, Error> {
+        let frequency = match frame {
+            Frame::Data => self.get_data_frequency(datarate, random)?,
+            Frame::Join => self.get_join_frequency(datarate, random)?,
+        };
+        let datarate = self.get_tx_datarate(datarate, frame)?;
+        Ok(TxConfig {
             pw: self.get_dbm(),
             rf: RfConfig {
-                frequency: match frame {
-                    Frame::Data => self.get_data_frequency(random as u8),
-                    Frame::Join => self.get_join_frequency(random as u8),
-                },
+                frequency,
                 bandwidth: datarate.bandwidth,
                 spreading_factor: datarate.spreading_factor,
                 coding_rate: self.get_coding_rate(),
             },
-        }
+        })
     }
 
     pub(crate) fn get_rx_config(
@@ -167,14 +204,14 @@ impl Configuration {
         datarate: DR,
         frame: &Frame,
         window: &Window,
-    ) -> RfConfig {
-        let datarate = self.get_rx_datarate(datarate, frame, window);
-        RfConfig {
+    ) -> Result<RfConfig, Error> {
+        let datarate = self.get_rx_datarate(datarate, frame, window)?;
+        Ok(RfConfig {
             frequency: self.get_rx_frequency(frame, window),
             bandwidth: datarate.bandwidth,
             spreading_factor: datarate.spreading_factor,
             coding_rate: self.get_coding_rate(),
-        }
+        })
     }
 
     pub(crate) fn process_join_accept<T: core::convert::AsRef<[u8]>, C>(
@@ -184,19 +221,22 @@ impl Configuration {
         mut_region_dispatch!(self, process_join_accept, join_accept)
     }
 
-    pub(crate) fn set_channel_mask(&mut self, channel_mask: ChannelMask) {
-        mut_region_dispatch!(self, set_channel_mask, channel_mask)
+    pub(crate) fn set_channel_mask(&mut self, channel_mask_control: u8, channel_mask: ChannelMask) {
+        mut_region_dispatch!(self, set_channel_mask, channel_mask_control, channel_mask)
     }
 
     pub fn set_subband(&mut self, subband: u8) {
         mut_region_dispatch!(self, set_subband, subband)
     }
 
-    pub(crate) fn get_join_frequency(&mut self, random: u8) -> u32 {
-        mut_region_dispatch!(self, get_join_frequency, random)
+    pub(crate) fn get_join_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        mut_region_dispatch!(self, get_join_frequency, datarate, random)
+    }
+    pub(crate) fn get_data_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        mut_region_dispatch!(self, get_data_frequency, datarate, random)
     }
-    pub(crate) fn get_data_frequency(&mut self, random: u8) -> u32 {
-        mut_region_dispatch!(self, get_data_frequency, random)
+    pub(crate) fn get_tx_channel(&self) -> u8 {
+        region_dispatch!(self, get_tx_channel)
     }
     pub(crate) fn get_rx_delay(&self, frame: &Frame, window: &Window) -> u32 {
         match frame {
@@ -216,11 +256,32 @@ impl Configuration {
     pub(crate) fn get_default_datarate(&self) -> DR {
         region_dispatch!(self, get_default_datarate)
     }
-    pub(crate) fn get_tx_datarate(&self, datarate: DR, frame: &Frame) -> Datarate {
+    pub(crate) fn get_tx_datarate(&self, datarate: DR, frame: &Frame) -> Result<Datarate, Error> {
         region_dispatch!(self, get_tx_datarate, datarate, frame)
     }
-    pub(crate) fn get_rx_datarate(&self, datarate: DR, frame: &Frame, window: &Window) -> Datarate {
-        region_dispatch!(self, get_rx_datarate, datarate, frame, window)
+    pub(crate) fn selects_join_datarate(&self) -> bool {
+        region_dispatch!(self, selects_join_datarate)
+    }
+    pub(crate) fn get_rx_datarate(
+        &self,
+        datarate: DR,
+        frame: &Frame,
+        window: &Window,
+    ) -> Result<Datarate, Error> {
+        let datarate = self.get_rx_dr(datarate, frame, window)?;
+        region_dispatch!(self, get_datarate, datarate)
+    }
+    pub(crate) fn get_rx_dr(
+        &self,
+        datarate: DR,
+        frame: &Frame,
+        window: &Window,
+    ) -> Result<DR, Error> {
+        region_dispatch!(self, get_rx_dr, datarate, frame, window)
+    }
+
+    pub(crate) fn get_max_payload_len(&self, datarate: DR) -> Result<usize, Error> {
+        region_dispatch!(self, get_max_payload_len, datarate)
     }
 
     pub(crate) fn get_dbm(&self) -> i8 {
@@ -248,27 +309,90 @@ from_region!(EU868);
 use super::state_machines::JoinAccept;
 use lorawan_encoding::parser::DecryptedJoinAcceptPayload;
 
+// bitmap of the enabled channels, bit n being channel n
+pub(crate) fn enabled_channels(masks: &[ChannelMask]) -> u128 {
+    let mut enabled = 0;
+    for (i, mask) in masks.iter().enumerate() {
+        for (j, status) in mask.statuses().iter().enumerate() {
+            if *status {
+                enabled |= 1 << (i * 16 + j);
+            }
+        }
+    }
+    enabled
+}
+
+// picks one of the set bits of a bitmap, None if there is none
+pub(crate) fn nth_enabled(enabled: u128, random: u32) -> Option<usize> {
+    // scaling the random value rather than taking a modulo keeps the choice unbiased
+    let n = ((random as u64 * enabled.count_ones() as u64) >> 32) as usize;
+    (0..128).filter(|bit| enabled & (1 << bit) != 0).nth(n)
+}
+
+// Picks channels in a random order among the enabled ones, bit n of a bitmap being channel n.
+// Each enabled channel is used once before any is used again, and the last channel is not
+// used twice in a row unless it is the only one enabled.
+#[derive(Default)]
+pub(crate) struct ChannelHopper {
+    // channels not used yet in the current cycle
+    unused: u128,
+    last: Option<usize>,
+}
+
+impl ChannelHopper {
+    pub(crate) fn select(&mut self, enabled: u128, random: u32) -> Option<usize> {
+        let mut candidates = self.unused & enabled;
+        if candidates == 0 {
+            // start a new cycle
+            self.unused = enabled;
+            candidates = enabled;
+            if let Some(last) = self.last {
+                if candidates.count_ones() > 1 {
+                    candidates &= !(1 << last);
+                }
+            }
+        }
+        let channel = nth_enabled(candidates, random)?;
+        self.unused &= !(1 << channel);
+        self.last = Some(channel);
+        Some(channel)
+    }
+}
+
 pub(crate) trait RegionHandler {
     fn process_join_accept<T: core::convert::AsRef<[u8]>, C>(
         &mut self,
         join_accept: &DecryptedJoinAcceptPayload<T, C>,
     ) -> JoinAccept;
-    fn set_channel_mask(&mut self, _channel_mask: ChannelMask) {
+    // applies the ChMask of a LinkADRReq as interpreted by its ChMaskCntl
+    fn set_channel_mask(&mut self, _channel_mask_control: u8, _channel_mask: ChannelMask) {
         // does not apply to every region
     }
     fn set_subband(&mut self, _subband: u8) {
         // does not apply to every region
     }
 
-    fn get_join_frequency(&mut self, random: u8) -> u32;
-    fn get_data_frequency(&mut self, random: u8) -> u32;
+    fn get_join_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error>;
+    fn get_data_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error>;
+    // index of the channel used for the last transmission
+    fn get_tx_channel(&self) -> u8;
     fn get_rx_frequency(&self, frame: &Frame, window: &Window) -> u32;
 
     fn get_default_datarate(&self) -> DR {
         DR::_0
     }
-    fn get_tx_datarate(&self, datarate: DR, frame: &Frame) -> Datarate;
-    fn get_rx_datarate(&self, datarate: DR, frame: &Frame, window: &Window) -> Datarate;
+    fn get_datarate(&self, datarate: DR) -> Result<Datarate, Error>;
+    fn get_tx_datarate(&self, datarate: DR, _frame: &Frame) -> Result<Datarate, Error> {
+        self.get_datarate(datarate)
+    }
+    // whether the datarate of JoinRequests is picked by the region rather than the device
+    fn selects_join_datarate(&self) -> bool {
+        false
+    }
+    // datarate of an RX window for an uplink sent at the given datarate
+    fn get_rx_dr(&self, datarate: DR, frame: &Frame, window: &Window) -> Result<DR, Error>;
+    // maximum FRMPayload size of an uplink at the given datarate
+    fn get_max_payload_len(&self, datarate: DR) -> Result<usize, Error>;
     fn get_dbm(&self) -> i8 {
         DEFAULT_DBM
     }
@@ -276,3 +400,41 @@ pub(crate) trait RegionHandler {
         DEFAULT_CODING_RATE
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn random(i: u32) -> u32 {
+        i.wrapping_mul(0x9e37_79b9)
+    }
+
+    #[test]
+    fn test_hopper_uses_each_channel_once_per_cycle() {
+        let mut hopper = ChannelHopper::default();
+        let enabled: u128 = 0b1011_0110;
+        for cycle in 0..10 {
+            let mut used = 0;
+            for i in 0..enabled.count_ones() {
+                let channel = hopper.select(enabled, random(cycle * 8 + i)).unwrap();
+                assert_eq!(used & 1 << channel, 0);
+                used |= 1 << channel;
+            }
+            assert_eq!(used, enabled);
+        }
+    }
+
+    #[test]
+    fn test_hopper_does_not_repeat_the_last_channel() {
+        let mut hopper = ChannelHopper::default();
+        let mut last = None;
+        for i in 0..1000 {
+            let channel = hopper.select(0b111, random(i)).unwrap();
+            assert_ne!(Some(channel), last);
+            last = Some(channel);
+        }
+        // unless it is the only one enabled
+        assert_eq!(hopper.select(1 << last.unwrap(), 0), last);
+        assert_eq!(hopper.select(0, 0), None);
+    }
+}
@@ -49,3 +49,6 @@ pub(crate) const DATARATES: [Option<Datarate>; 14] = [
         bandwidth: Bandwidth::_500KHz,
     }),
 ];
+
+// maximum FRMPayload size N, for US915 these include the 400 ms dwell time limit
+pub(crate) const MAX_PAYLOAD_LEN: [usize; 5] = [11, 53, 125, 242, 242];
@@ -14,7 +14,15 @@ const US_DBM: i8 = 21;
 #[allow(clippy::upper_case_acronyms)]
 pub struct US915 {
     subband: Option<u8>,
-    last_tx: (u8, u8),
+    // channels 0 to 63 are the 125 kHz ones, 64 to 71 the 500 kHz ones
+    last_tx: usize,
+    // channels enabled by the CFList of the JoinAccept or by LinkADRReqs
+    enabled_channels: Option<u128>,
+    hopper: ChannelHopper,
+    // subbands already swept by JoinRequests, bit n being subband n + 1
+    join_subbands_tried: u8,
+    // subband of the last 125 kHz JoinRequest, the next one uses its 500 kHz channel
+    join_wide_block: Option<usize>,
 }
 
 impl US915 {
@@ -24,54 +32,146 @@ impl US915 {
     pub fn subband(subband: u8) -> US915 {
         US915 {
             subband: Some(subband),
-            last_tx: (0, 0),
+            ..Default::default()
         }
     }
+
+    fn channel_frequency(channel: usize) -> u32 {
+        match channel.checked_sub(NARROW_CHANNELS) {
+            Some(wide_channel) => 903_000_000 + 1_600_000 * wide_channel as u32,
+            None => UPLINK_CHANNEL_MAP[channel / 8][channel % 8],
+        }
+    }
+
+    // channels allowed by the CFList and LinkADRReqs or else by the subband, which enables its
+    // 125 kHz channels and the 500 kHz channel overlapping them
+    fn enabled_channels(&self) -> u128 {
+        match (self.enabled_channels, self.subband) {
+            (Some(enabled), _) => enabled,
+            (None, Some(subband)) => {
+                let block = subband as usize - 1;
+                0xff << (block * 8) | 1 << (NARROW_CHANNELS + block)
+            }
+            (None, None) => ALL_CHANNELS_ENABLED,
+        }
+    }
+
+    fn enabled_narrow_channels(&self) -> u64 {
+        self.enabled_channels() as u64
+    }
+
+    // JoinRequests alternate between a random 125 kHz channel at DR0, in a subband not tried
+    // yet, and the 500 kHz channel overlapping that subband at DR4
+    fn select_join_channel(&mut self, random: u32) -> Result<u32, Error> {
+        let channel = match self.join_wide_block.take() {
+            Some(block) => NARROW_CHANNELS + block,
+            None => {
+                let narrow = self.enabled_narrow_channels();
+                let blocks = (0..8)
+                    .filter(|block| (narrow >> (block * 8)) & 0xff != 0)
+                    .fold(0, |blocks, block| blocks | 1 << block);
+                let mut candidates = blocks & !self.join_subbands_tried;
+                if candidates == 0 {
+                    // every subband was tried, start a new sweep
+                    self.join_subbands_tried = 0;
+                    candidates = blocks;
+                }
+                let block = nth_enabled(candidates as u128, random)
+                    .ok_or(Error::NoChannelAvailable(DR::_0))?;
+                let subband_channels = (narrow >> (block * 8)) & 0xff;
+                // the lower half of the random value picks the channel within the subband
+                let channel = nth_enabled(subband_channels as u128, random << 16)
+                    .ok_or(Error::NoChannelAvailable(DR::_0))?;
+                self.join_subbands_tried |= 1 << block;
+                self.join_wide_block = Some(block);
+                block * 8 + channel
+            }
+        };
+        self.last_tx = channel;
+        Ok(Self::channel_frequency(channel))
+    }
+
+    fn join_datarate(&self) -> DR {
+        if self.last_tx >= NARROW_CHANNELS {
+            DR::_4
+        } else {
+            DR::_0
+        }
+    }
+
+    fn select_channel(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        let enabled = self.enabled_channels();
+        let enabled = match datarate {
+            DR::_0 | DR::_1 | DR::_2 | DR::_3 => enabled & NARROW_CHANNELS_ENABLED,
+            DR::_4 => enabled & !NARROW_CHANNELS_ENABLED,
+            _ => 0,
+        };
+        let channel = self
+            .hopper
+            .select(enabled, random)
+            .ok_or(Error::NoChannelAvailable(datarate))?;
+        self.last_tx = channel;
+        Ok(Self::channel_frequency(channel))
+    }
 }
 
+const NARROW_CHANNELS: usize = 64;
+const NARROW_CHANNELS_ENABLED: u128 = u64::MAX as u128;
+const ALL_CHANNELS_ENABLED: u128 = (1 << (NARROW_CHANNELS + 8)) - 1;
+
 use super::JoinAccept;
+use lorawan_encoding::parser::CfList;
 
 impl RegionHandler for US915 {
     fn process_join_accept<T: core::convert::AsRef<[u8]>, C>(
         &mut self,
-        _join_accept: &super::DecryptedJoinAcceptPayload<T, C>,
+        join_accept: &super::DecryptedJoinAcceptPayload<T, C>,
     ) -> JoinAccept {
+        if let Some(CfList::FixedChannel(masks)) = join_accept.c_f_list() {
+            let enabled = enabled_channels(&masks) & ALL_CHANNELS_ENABLED;
+            if enabled != 0 {
+                self.enabled_channels = Some(enabled);
+            }
+        }
         JoinAccept { cflist: None }
     }
 
-    fn set_channel_mask(&mut self, _chmask: ChannelMask) {
-        // one day this should truly be handled
+    fn set_channel_mask(&mut self, channel_mask_control: u8, channel_mask: ChannelMask) {
+        let mask = enabled_channels(&[channel_mask]);
+        let enabled = self.enabled_channels();
+        let enabled = match channel_mask_control {
+            // the mask applies to a block of 16 channels
+            0..=4 => {
+                let shift = channel_mask_control as usize * 16;
+                enabled & !(0xffff << shift) | mask << shift
+            }
+            // all 125 kHz channels on or off, the mask applies to the 500 kHz channels
+            6 => NARROW_CHANNELS_ENABLED | (mask & 0xff) << NARROW_CHANNELS,
+            7 => (mask & 0xff) << NARROW_CHANNELS,
+            _ => return,
+        };
+        self.enabled_channels = Some(enabled & ALL_CHANNELS_ENABLED);
     }
 
     fn set_subband(&mut self, subband: u8) {
         self.subband = Some(subband);
     }
 
-    fn get_join_frequency(&mut self, random: u8) -> u32 {
-        let subband_channel = random & 0b111;
-        let subband = if let Some(subband) = &self.subband {
-            subband - 1
-        } else {
-            (random >> 3) & 0b111
-        };
-        self.last_tx = (subband, subband_channel);
-        UPLINK_CHANNEL_MAP[subband as usize][subband_channel as usize]
+    fn get_join_frequency(&mut self, _datarate: DR, random: u32) -> Result<u32, Error> {
+        self.select_join_channel(random)
     }
 
-    fn get_data_frequency(&mut self, random: u8) -> u32 {
-        let subband_channel = random & 0b111;
-        let subband = if let Some(subband) = &self.subband {
-            subband - 1
-        } else {
-            (random >> 3) & 0b111
-        };
-        self.last_tx = (subband, subband_channel);
-        UPLINK_CHANNEL_MAP[subband as usize][subband_channel as usize]
+    fn get_data_frequency(&mut self, datarate: DR, random: u32) -> Result<u32, Error> {
+        self.select_channel(datarate, random)
+    }
+
+    fn get_tx_channel(&self) -> u8 {
+        self.last_tx as u8
     }
 
     fn get_rx_frequency(&self, _frame: &Frame, window: &Window) -> u32 {
         match window {
-            Window::_1 => DOWNLINK_CHANNEL_MAP[self.last_tx.1 as usize],
+            Window::_1 => DOWNLINK_CHANNEL_MAP[self.last_tx % 8],
             Window::_2 => 923_300_000,
         }
     }
@@ -80,28 +180,85 @@ impl RegionHandler for US915 {
         US_DBM
     }
 
-    fn get_tx_datarate(&self, datarate: DR, frame: &Frame) -> Datarate {
-        // datarate for JoinRequest is always 0
+    fn get_datarate(&self, datarate: DR) -> Result<Datarate, Error> {
+        DATARATES
+            .get(datarate as usize)
+            .cloned()
+            .flatten()
+            .ok_or(Error::InvalidDatarate(datarate))
+    }
+    fn get_tx_datarate(&self, datarate: DR, frame: &Frame) -> Result<Datarate, Error> {
+        // the datarate of a JoinRequest depends on the channel of the sweep
         let datarate = match frame {
-            Frame::Join => DR::_0,
+            Frame::Join => self.join_datarate(),
             Frame::Data => datarate,
         };
-        DATARATES[datarate as usize].clone().unwrap()
+        self.get_datarate(datarate)
+    }
+    fn selects_join_datarate(&self) -> bool {
+        true
     }
-    fn get_rx_datarate(&self, tx_datarate: DR, _frame: &Frame, window: &Window) -> Datarate {
-        let datarate = match window {
+    fn get_rx_dr(&self, tx_datarate: DR, frame: &Frame, window: &Window) -> Result<DR, Error> {
+        let tx_datarate = match frame {
+            Frame::Join => self.join_datarate(),
+            Frame::Data => tx_datarate,
+        };
+        Ok(match window {
             Window::_1 => {
                 // no support for RX1 DR Offset
                 match tx_datarate {
                     DR::_0 => DR::_10,
-                    DR::_1 => DR::_9,
-                    DR::_2 => DR::_8,
-                    DR::_3 => DR::_7,
-                    _ => panic!("Invalid TX datarate"),
+                    DR::_1 => DR::_11,
+                    DR::_2 => DR::_12,
+                    DR::_3 | DR::_4 => DR::_13,
+                    _ => return Err(Error::InvalidDatarate(tx_datarate)),
                 }
             }
             Window::_2 => DR::_8,
-        };
-        DATARATES[datarate as usize].clone().unwrap()
+        })
+    }
+    fn get_max_payload_len(&self, datarate: DR) -> Result<usize, Error> {
+        MAX_PAYLOAD_LEN
+            .get(datarate as usize)
+            .copied()
+            .ok_or(Error::InvalidDatarate(datarate))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_channel_mask() {
+        let mut us915 = US915::subband(2);
+        // LinkADRReq block enabling subband 3 and the 500 kHz channel 66
+        us915.set_channel_mask(7, ChannelMask::new_from_raw(&[0x04, 0x00]));
+        us915.set_channel_mask(1, ChannelMask::new_from_raw(&[0xff, 0x00]));
+        assert_eq!(us915.enabled_channels(), 0xff << 16 | 1 << 66);
+
+        us915.set_channel_mask(6, ChannelMask::new_from_raw(&[0x00, 0x00]));
+        assert_eq!(us915.enabled_channels(), NARROW_CHANNELS_ENABLED);
+    }
+
+    #[test]
+    fn test_join_sweep() {
+        let mut us915 = US915::new();
+        let mut subbands_tried = 0u8;
+        for i in 0..8u32 {
+            let random = i.wrapping_mul(0x9e37_79b9);
+            // a 125 kHz channel at DR0 in a subband not tried yet
+            us915.get_join_frequency(DR::_0, random).unwrap();
+            assert!(matches!(us915.join_datarate(), DR::_0));
+            let block = us915.get_tx_channel() as usize / 8;
+            assert_eq!(subbands_tried & 1 << block, 0);
+            subbands_tried |= 1 << block;
+            // followed by the 500 kHz channel overlapping the subband at DR4
+            let frequency = us915.get_join_frequency(DR::_0, random).unwrap();
+            assert!(matches!(us915.join_datarate(), DR::_4));
+            assert_eq!(us915.get_tx_channel() as usize, NARROW_CHANNELS + block);
+            assert_eq!(frequency, 903_000_000 + 1_600_000 * block as u32);
+        }
+        assert_eq!(subbands_tried, 0xff);
     }
 }
@@ -5,19 +5,28 @@ pub mod no_session;
 pub mod session;
 
 pub use region::DR;
+use region::{Frame, Window};
 
-pub struct Shared<R: radio::PhyRxTx + Timings> {
+pub struct Shared<R: radio::PhyRxTx + Timings, G: RngCore> {
     radio: R,
     credentials: Credentials,
     region: region::Configuration,
     mac: Mac,
-    // TODO: do something nicer for randomness
-    get_random: fn() -> u32,
+    // used for DevNonces, channel selection and retransmission jitter
+    rng: G,
     buffer: R::PhyBuf,
     downlink: Option<Downlink>,
     datarate: DR,
+    rx_metadata: Option<RxMetadata>,
+    // FCntUp at which the session is reported as expired so that the device rejoins early
+    rejoin_threshold: Option<u32>,
+    // JoinRequests sent before the join procedure is given up, retried forever if None
+    max_join_attempts: Option<usize>,
 }
 
+// enough for a US915 sweep of every subband or an EU868 join stepping down from DR5 to DR0
+const DEFAULT_MAX_JOIN_ATTEMPTS: usize = 16;
+
 enum Downlink {
     Data(DecryptedDataPayload<Vec<u8, U256>>),
     Join(JoinAccept),
@@ -28,7 +37,17 @@ pub struct JoinAccept {
     pub cflist: Option<[u32; 5]>,
 }
 
-impl<R: radio::PhyRxTx + Timings> Shared<R> {
+#[derive(Debug, Clone, Copy)]
+pub struct RxMetadata {
+    // end of the reception, on the clock of the radio timestamps
+    pub timestamp_us: TimestampUs,
+    pub frequency: u32,
+    pub datarate: DR,
+    pub rssi: i16,
+    pub snr: i8,
+}
+
+impl<R: radio::PhyRxTx + Timings, G: RngCore> Shared<R, G> {
     pub fn get_mut_radio(&mut self) -> &mut R {
         &mut self.radio
     }
@@ -38,9 +57,78 @@ impl<R: radio::PhyRxTx + Timings> Shared<R> {
     pub fn get_datarate(&mut self) -> DR {
         self.datarate
     }
+    pub fn get_max_payload_len(&self) -> Result<usize, region::Error> {
+        self.region.get_max_payload_len(self.datarate)
+    }
     pub fn set_datarate(&mut self, datarate: DR) {
         self.datarate = datarate;
     }
+    pub fn get_rejoin_threshold(&self) -> Option<u32> {
+        self.rejoin_threshold
+    }
+    pub fn set_rejoin_threshold(&mut self, rejoin_threshold: Option<u32>) {
+        self.rejoin_threshold = rejoin_threshold;
+    }
+    pub fn get_max_join_attempts(&self) -> Option<usize> {
+        self.max_join_attempts
+    }
+    pub fn set_max_join_attempts(&mut self, max_join_attempts: Option<usize>) {
+        self.max_join_attempts = max_join_attempts;
+    }
+
+    // RX configuration of a window and the offset from the nominal start of the window at which
+    // the radio has to be started so the preamble is caught despite the timing errors
+    pub(crate) fn get_rx_window(
+        &mut self,
+        frame: &Frame,
+        window: &Window,
+    ) -> Result<(radio::RxConfig, i32), region::Error> {
+        let rf = self.region.get_rx_config(self.datarate, frame, window)?;
+        let delay_ms = self.region.get_rx_delay(frame, window);
+        let symbol_time_us = radio::LoRaPacketConfig::from(&rf).symbol_time_us();
+        let (offset_ms, symbol_timeout) =
+            rx_window_parameters(&self.radio, symbol_time_us, delay_ms);
+        Ok((
+            radio::RxConfig { rf, symbol_timeout },
+            offset_ms + self.radio.get_rx_window_latency_ms(),
+        ))
+    }
+
+    // timestamp at which the radio is started for a window nominally opening at nominal_ms
+    pub(crate) fn get_rx_window_start(
+        &mut self,
+        frame: &Frame,
+        window: &Window,
+        nominal_ms: TimestampMs,
+    ) -> Result<TimestampMs, region::Error> {
+        let (_, offset_ms) = self.get_rx_window(frame, window)?;
+        Ok((nominal_ms as i32 + offset_ms) as u32)
+    }
+
+    pub fn get_rx_metadata(&self) -> Option<RxMetadata> {
+        self.rx_metadata
+    }
+
+    // records the reception details of a downlink accepted in the given RX window
+    pub(crate) fn set_rx_metadata(
+        &mut self,
+        frame: &Frame,
+        window: &Window,
+        quality: radio::RxQuality,
+        timestamp_us: TimestampUs,
+    ) {
+        self.rx_metadata = self
+            .region
+            .get_rx_dr(self.datarate, frame, window)
+            .ok()
+            .map(|datarate| RxMetadata {
+                timestamp_us,
+                frequency: self.region.get_rx_frequency(frame, window),
+                datarate,
+                rssi: quality.rssi(),
+                snr: quality.snr(),
+            });
+    }
 
     pub fn take_data_downlink(&mut self) -> Option<DecryptedDataPayload<Vec<u8, U256>>> {
         if let Some(Downlink::Data(payload)) = self.downlink.take() {
@@ -59,29 +147,82 @@ impl<R: radio::PhyRxTx + Timings> Shared<R> {
     }
 }
 
-impl<R: radio::PhyRxTx + Timings> Shared<R> {
+impl<R: radio::PhyRxTx + Timings, G: RngCore> Shared<R, G> {
     pub fn new(
         radio: R,
         credentials: Credentials,
         region: region::Configuration,
         mac: Mac,
-        get_random: fn() -> u32,
+        rng: G,
         buffer: R::PhyBuf,
-    ) -> Shared<R> {
+    ) -> Shared<R, G> {
         let datarate = region.get_default_datarate();
         Shared {
             radio,
             credentials,
             region,
             mac,
-            get_random,
+            rng,
             buffer,
             downlink: None,
             datarate,
+            rx_metadata: None,
+            rejoin_threshold: None,
+            max_join_attempts: Some(DEFAULT_MAX_JOIN_ATTEMPTS),
         }
     }
 }
 
-trait CommonState<R: radio::PhyRxTx + Timings> {
-    fn get_mut_shared(&mut self) -> &mut Shared<R>;
+trait CommonState<R: radio::PhyRxTx + Timings, G: RngCore> {
+    fn get_mut_shared(&mut self) -> &mut Shared<R, G>;
+}
+
+// Symbol timeout and offset of an RX window, as done by Semtech's LoRaMac-node: the window is
+// long enough for the radio to detect min_rx_symbols of the preamble even if the window opens
+// early or late by the timing error, and it is centered on the preamble.
+fn rx_window_parameters<T: Timings>(timings: &T, symbol_time_us: u32, delay_ms: u32) -> (i32, u16) {
+    let min_rx_symbols = timings.get_min_rx_symbols();
+    // the crystal drift accumulates over the delay between the end of the TX and the window
+    let rx_error_us =
+        timings.get_rx_timer_error_ms() * 1000 + delay_ms * timings.get_crystal_drift_ppm() / 1000;
+    let symbol_timeout = core::cmp::max(
+        ((2 * min_rx_symbols).saturating_sub(8) * symbol_time_us + 2 * rx_error_us)
+            .div_ceil(symbol_time_us),
+        min_rx_symbols,
+    );
+    let offset_us = 4 * symbol_time_us as i32
+        - (symbol_timeout * symbol_time_us / 2) as i32
+        - (timings.get_rx_wakeup_time_ms() * 1000) as i32;
+    // rounded down so that the window opens early rather than late
+    (
+        offset_us.div_euclid(1000),
+        core::cmp::min(symbol_timeout, u16::MAX as u32) as u16,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct DefaultTimings;
+
+    impl Timings for DefaultTimings {
+        fn get_rx_window_duration_ms(&self) -> u32 {
+            100
+        }
+    }
+
+    #[test]
+    fn test_rx_window_parameters() {
+        // SF7BW125, the timer error dominates so the window opens before the preamble
+        assert_eq!(
+            rx_window_parameters(&DefaultTimings, 1_024, 1_000),
+            (-9, 24)
+        );
+        // SF12BW125, the preamble is long enough to open the window in it
+        assert_eq!(
+            rx_window_parameters(&DefaultTimings, 32_768, 1_000),
+            (32, 6)
+        );
+    }
 }
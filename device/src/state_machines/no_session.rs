@@ -4,24 +4,25 @@ use super::super::*;
 use super::{
     radio::PhyRxTxBuf,
     region::{Frame, Window},
-    CommonState, Shared,
+    CommonState, Shared, DR,
 };
 use lorawan_encoding::{
     self,
     creator::JoinRequestCreator,
-    keys::AES128,
+    keys::{derive_js_int_key, AES128},
     parser::DevAddr,
     parser::{parse_with_factory as lorawan_parse, *},
 };
 
-pub enum NoSession<R>
+pub enum NoSession<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    Idle(Idle<R>),
-    SendingJoin(SendingJoin<R>),
-    WaitingForRxWindow(WaitingForRxWindow<R>),
-    WaitingForJoinResponse(WaitingForJoinResponse<R>),
+    Idle(Idle<R, G>),
+    SendingJoin(SendingJoin<R, G>),
+    WaitingForRxWindow(WaitingForRxWindow<R, G>),
+    WaitingForJoinResponse(WaitingForJoinResponse<R, G>),
 }
 
 enum JoinRxWindow {
@@ -32,12 +33,13 @@ enum JoinRxWindow {
 macro_rules! into_state {
     ($($from:tt),*) => {
     $(
-        impl<R, C> From<$from<R>> for Device<R,C>
+        impl<R, C, G> From<$from<R, G>> for Device<R, C, G>
         where
             R: radio::PhyRxTx + Timings,
+            G: RngCore,
             C: CryptoFactory + Default
         {
-            fn from(state: $from<R>) -> Device<R, C> {
+            fn from(state: $from<R, G>) -> Device<R, C, G> {
                 Device {
                     crypto: PhantomData::default(),
                     state: SuperState::NoSession(NoSession::$from(state))
@@ -45,8 +47,8 @@ macro_rules! into_state {
             }
         }
 
-        impl<R: radio::PhyRxTx + Timings> CommonState<R> for $from<R> {
-            fn get_mut_shared(&mut self) -> &mut Shared<R> {
+        impl<R: radio::PhyRxTx + Timings, G: RngCore> CommonState<R, G> for $from<R, G> {
+            fn get_mut_shared(&mut self) -> &mut Shared<R, G> {
                 &mut self.shared
             }
         }
@@ -60,27 +62,29 @@ into_state![
     WaitingForJoinResponse
 ];
 
-impl<R> From<NoSession<R>> for SuperState<R>
+impl<R, G> From<NoSession<R, G>> for SuperState<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    fn from(no_session: NoSession<R>) -> SuperState<R> {
+    fn from(no_session: NoSession<R, G>) -> SuperState<R, G> {
         SuperState::NoSession(no_session)
     }
 }
 
-impl<R> NoSession<R>
+impl<R, G> NoSession<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    pub fn new(shared: Shared<R>) -> NoSession<R> {
+    pub fn new(shared: Shared<R, G>) -> NoSession<R, G> {
         NoSession::Idle(Idle {
             shared,
-            join_attempts: 0,
+            join_attempts: JoinAttempts::default(),
         })
     }
 
-    pub fn get_mut_shared(&mut self) -> &mut Shared<R> {
+    pub fn get_mut_shared(&mut self) -> &mut Shared<R, G> {
         match self {
             NoSession::Idle(state) => state.get_mut_shared(),
             NoSession::SendingJoin(state) => state.get_mut_shared(),
@@ -92,7 +96,7 @@ where
     pub fn handle_event<C: CryptoFactory + Default>(
         self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match self {
             NoSession::Idle(state) => state.handle_event(event),
             NoSession::SendingJoin(state) => state.handle_event(event),
@@ -110,6 +114,9 @@ pub enum Error {
     NewSessionWhileWaitingForJoinWindow,
     SendDataWhileWaitingForJoinWindow,
     NewSessionWhileWaitingForJoinResponse,
+    UnexpectedRadioResponse,
+    // every DevNonce of the LoRaWAN 1.1 device was used, it cannot join anymore
+    DevNonceExhausted,
 }
 
 impl<R> From<Error> for super::super::Error<R>
@@ -122,26 +129,93 @@ where
 }
 type DevNonce = lorawan_encoding::parser::DevNonce<[u8; 2]>;
 
-pub struct Idle<R>
+// JoinReqType used in the MIC of LoRaWAN 1.1 JoinAccepts answering a JoinRequest
+const JOIN_REQ_TYPE: u8 = 0xff;
+
+// JoinRequests sent at a datarate before stepping down to the next slower one
+const JOIN_ATTEMPTS_PER_DR: usize = 2;
+// the random backoff before a retry doubles with every attempt up to this maximum
+const JOIN_BACKOFF_MAX_MS: u32 = 64_000;
+const HOUR_US: TimestampUs = 3_600_000_000;
+
+#[derive(Default)]
+struct JoinAttempts {
+    count: usize,
+    // datarate when the join procedure was started, retries step down from it
+    datarate: Option<DR>,
+    // time on air of the last JoinRequest
+    airtime_us: u32,
+    // start of the first JoinRequest, the allowed duty cycle decreases as time goes by
+    first_us: Option<TimestampUs>,
+    // earliest start of the next JoinRequest allowed by the duty cycle
+    next_us: TimestampUs,
+}
+
+impl JoinAttempts {
+    // a new join procedure, the duty cycle history is kept
+    fn restart(&mut self) {
+        self.count = 0;
+        self.datarate = None;
+    }
+
+    // datarate of the next JoinRequest
+    fn next_datarate(&mut self, datarate: DR) -> DR {
+        let mut datarate = *self.datarate.get_or_insert(datarate);
+        for _ in 0..self.count / JOIN_ATTEMPTS_PER_DR {
+            datarate = datarate.lower();
+        }
+        datarate
+    }
+
+    // accounts for the time on air of a JoinRequest whose transmission ended at the given time
+    fn transmitted(&mut self, tx_done_us: TimestampUs) {
+        let start_us = tx_done_us.saturating_sub(self.airtime_us as TimestampUs);
+        let elapsed_us = start_us.saturating_sub(*self.first_us.get_or_insert(start_us));
+        // 1% during the first hour, 0.1% during the next 10 hours and 0.01% afterwards
+        let off_factor = match elapsed_us / HOUR_US {
+            0 => 100,
+            1..=10 => 1_000,
+            _ => 10_000,
+        };
+        self.next_us = start_us + self.airtime_us as TimestampUs * off_factor;
+    }
+
+    // time of the next JoinRequest after the RX windows of the last one closed at `now`
+    fn retry_time(&self, now: TimestampMs, random: u32) -> TimestampMs {
+        let backoff_ms = (1000 << self.count.min(6)).min(JOIN_BACKOFF_MAX_MS);
+        let earliest = ((self.next_us / 1000) as TimestampMs).max(now);
+        earliest + ((random as u64 * backoff_ms as u64) >> 32) as TimestampMs
+    }
+}
+
+pub struct Idle<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
-    join_attempts: usize,
+    shared: Shared<R, G>,
+    join_attempts: JoinAttempts,
 }
 
-impl<'a, R> Idle<R>
+impl<'a, R, G> Idle<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // NewSession Request or a Timeout from previously failed Join attempt
             Event::NewSessionRequest | Event::TimeoutFired => {
-                let (devnonce, tx_config) = self.create_join_request::<C>();
+                if matches!(event, Event::NewSessionRequest) {
+                    self.join_attempts.restart();
+                }
+                let (devnonce, tx_config) = match self.create_join_request::<C>() {
+                    Ok(join_request) => join_request,
+                    Err(e) => return (self.into(), Err(e)),
+                };
                 let radio_event: radio::Event<R> =
                     radio::Event::TxRequest(tx_config, &mut self.shared.buffer);
 
@@ -157,18 +231,15 @@ where
                             ),
                             // directly jump to waiting for RxWindow
                             // allows for synchronous sending
-                            radio::Response::TxDone(ms) => {
+                            radio::Response::TxDone(timestamp_us) => {
+                                self.join_attempts.transmitted(timestamp_us);
                                 let first_window =
-                                    self.shared.region.get_rx_delay(&Frame::Join, &Window::_1) + ms;
-                                (
-                                    self.into_waiting_for_rxwindow(devnonce, first_window)
-                                        .into(),
-                                    Ok(Response::TimeoutRequest(first_window)),
-                                )
-                            }
-                            _ => {
-                                panic!("NoSession::Idle:: Unexpected radio response");
+                                    self.shared.region.get_rx_delay(&Frame::Join, &Window::_1)
+                                        + (timestamp_us / 1000) as TimestampMs;
+                                self.into_waiting_for_rxwindow(devnonce, first_window)
+                                    .into_timeout_request()
                             }
+                            _ => (self.into(), Err(Error::UnexpectedRadioResponse.into())),
                         }
                     }
                     Err(e) => (self.into(), Err(e.into())),
@@ -181,10 +252,30 @@ where
         }
     }
 
-    fn create_join_request<C: CryptoFactory + Default>(&mut self) -> (DevNonce, radio::TxConfig) {
-        let mut random = (self.shared.get_random)();
-        // use lowest 16 bits for devnonce
-        let devnonce_bytes = random as u16;
+    fn create_join_request<C: CryptoFactory + Default>(
+        &mut self,
+    ) -> Result<(DevNonce, radio::TxConfig), super::super::Error<R>> {
+        let random = self.shared.rng.next_u32();
+        // the datarate of the session follows the one of the JoinRequest, unless the region picks
+        // it during a channel sweep
+        if !self.shared.region.selects_join_datarate() {
+            self.shared.datarate = self.join_attempts.next_datarate(self.shared.datarate);
+        }
+        let tx_config = self.shared.region.create_tx_config(
+            self.shared.rng.next_u32(),
+            self.shared.datarate,
+            &Frame::Join,
+        )?;
+        // use lowest 16 bits for devnonce, unless LoRaWAN 1.1 which requires a counter
+        let devnonce_bytes = if self.shared.credentials.is_v1_1() {
+            self.shared
+                .credentials
+                .next_devnonce()
+                .ok_or(Error::DevNonceExhausted)?
+        } else {
+            random as u16
+        };
+        self.join_attempts.count += 1;
 
         self.shared.buffer.clear();
 
@@ -193,60 +284,56 @@ where
 
         let devnonce = [devnonce_bytes as u8, (devnonce_bytes >> 8) as u8];
 
-        phy.set_app_eui(EUI64::new(creds.appeui()).unwrap())
+        phy.set_app_eui(EUI64::new(creds.joineui()).unwrap())
             .set_dev_eui(EUI64::new(creds.deveui()).unwrap())
             .set_dev_nonce(&devnonce);
-        let vec = phy.build(&creds.appkey()).unwrap();
+        let vec = phy.build(creds.nwkkey()).unwrap();
 
         let devnonce_copy = DevNonce::new(devnonce).unwrap();
+        self.join_attempts.airtime_us = tx_config.rf.time_on_air_us(vec.len());
 
         self.shared.buffer.extend(vec);
 
-        // we'll use the rest for frequency and subband selection
-        random >>= 16;
-        (
-            devnonce_copy,
-            self.shared
-                .region
-                .create_tx_config(random as u8, self.shared.datarate, &Frame::Join),
-        )
+        Ok((devnonce_copy, tx_config))
     }
 
-    fn into_sending_join(self, devnonce: DevNonce) -> SendingJoin<R> {
+    fn into_sending_join(self, devnonce: DevNonce) -> SendingJoin<R, G> {
         SendingJoin {
             shared: self.shared,
-            join_attempts: self.join_attempts + 1,
+            join_attempts: self.join_attempts,
             devnonce,
         }
     }
 
-    fn into_waiting_for_rxwindow(self, devnonce: DevNonce, time: u32) -> WaitingForRxWindow<R> {
+    fn into_waiting_for_rxwindow(self, devnonce: DevNonce, time: u32) -> WaitingForRxWindow<R, G> {
         WaitingForRxWindow {
             shared: self.shared,
-            join_attempts: self.join_attempts + 1,
+            join_attempts: self.join_attempts,
             join_rx_window: JoinRxWindow::_1(time),
             devnonce,
         }
     }
 }
 
-pub struct SendingJoin<R>
+pub struct SendingJoin<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
-    join_attempts: usize,
+    shared: Shared<R, G>,
+    join_attempts: JoinAttempts,
     devnonce: DevNonce,
 }
 
-impl<R> SendingJoin<R>
+impl<R, G> SendingJoin<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // we are waiting for the async tx to complete
             Event::RadioEvent(radio_event) => {
@@ -255,23 +342,27 @@ where
                     Ok(response) => {
                         match response {
                             // expect a complete transmit
-                            radio::Response::TxDone(ms) => {
+                            radio::Response::TxDone(timestamp_us) => {
+                                self.join_attempts.transmitted(timestamp_us);
                                 let first_window =
                                     self.shared.region.get_rx_delay(&Frame::Join, &Window::_1)
-                                        + ms
-                                        + self.shared.radio.get_rx_window_offset_ms() as u32;
-                                (
-                                    self.into_waiting_for_rxwindow(first_window).into(),
-                                    Ok(Response::TimeoutRequest(first_window)),
-                                )
+                                        + (timestamp_us / 1000) as TimestampMs;
+                                self.into_waiting_for_rxwindow(first_window)
+                                    .into_timeout_request()
                             }
                             // anything other than TxComplete | Idle is unexpected
-                            _ => {
-                                panic!("SendingJoin: Unexpected radio response");
-                            }
+                            _ => (self.into(), Err(Error::UnexpectedRadioResponse.into())),
                         }
                     }
-                    Err(e) => (self.into(), Err(e.into())),
+                    // the join attempt is given up
+                    Err(e) => (
+                        Idle {
+                            shared: self.shared,
+                            join_attempts: self.join_attempts,
+                        }
+                        .into(),
+                        Err(e.into()),
+                    ),
                 }
             }
             // anything other than a RadioEvent is unexpected
@@ -279,39 +370,49 @@ where
                 self.into(),
                 Err(Error::NewSessionWhileWaitingForJoinResponse.into()),
             ),
-            Event::TimeoutFired => panic!("TODO: implement timeouts"),
+            // the transmission never completed so the join attempt is given up
+            Event::TimeoutFired => (
+                Idle {
+                    shared: self.shared,
+                    join_attempts: self.join_attempts,
+                }
+                .into(),
+                Ok(Response::NoJoinAccept),
+            ),
             Event::SendDataRequest(_) => (self.into(), Err(Error::SendDataWhileNoSession.into())),
         }
     }
 
-    fn into_waiting_for_rxwindow(self, time: u32) -> WaitingForRxWindow<R> {
+    fn into_waiting_for_rxwindow(self, time: u32) -> WaitingForRxWindow<R, G> {
         WaitingForRxWindow {
             shared: self.shared,
-            join_attempts: self.join_attempts + 1,
+            join_attempts: self.join_attempts,
             join_rx_window: JoinRxWindow::_1(time),
             devnonce: self.devnonce,
         }
     }
 }
 
-pub struct WaitingForRxWindow<R>
+pub struct WaitingForRxWindow<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
-    join_attempts: usize,
+    shared: Shared<R, G>,
+    join_attempts: JoinAttempts,
     devnonce: DevNonce,
     join_rx_window: JoinRxWindow,
 }
 
-impl<R> WaitingForRxWindow<R>
+impl<R, G> WaitingForRxWindow<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // we are waiting for a Timeout
             Event::TimeoutFired => {
@@ -319,10 +420,20 @@ where
                     JoinRxWindow::_1(_) => Window::_1,
                     JoinRxWindow::_2(_) => Window::_2,
                 };
-                let rx_config =
-                    self.shared
-                        .region
-                        .get_rx_config(self.shared.datarate, &Frame::Join, &window);
+                let rx_config = match self.shared.get_rx_window(&Frame::Join, &window) {
+                    Ok((rx_config, _)) => rx_config,
+                    // the join accept can not be received so the join attempt is given up
+                    Err(e) => {
+                        return (
+                            Idle {
+                                shared: self.shared,
+                                join_attempts: self.join_attempts,
+                            }
+                            .into(),
+                            Err(e.into()),
+                        )
+                    }
+                };
                 // configure the radio for the RX
                 match self
                     .shared
@@ -356,7 +467,15 @@ where
                             Ok(Response::TimeoutRequest(window_close)),
                         )
                     }
-                    Err(e) => (self.into(), Err(e.into())),
+                    // the join accept can not be received so the join attempt is given up
+                    Err(e) => (
+                        Idle {
+                            shared: self.shared,
+                            join_attempts: self.join_attempts,
+                        }
+                        .into(),
+                        Err(e.into()),
+                    ),
                 }
             }
             Event::RadioEvent(_) => (
@@ -372,11 +491,40 @@ where
     }
 }
 
-impl<R> From<WaitingForRxWindow<R>> for WaitingForJoinResponse<R>
+impl<R, G> WaitingForRxWindow<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    fn from(val: WaitingForRxWindow<R>) -> WaitingForJoinResponse<R> {
+    // requests the timeout at which the radio is started for the RX window
+    fn into_timeout_request<C: CryptoFactory + Default>(
+        mut self,
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
+        let (window, time) = match self.join_rx_window {
+            JoinRxWindow::_1(time) => (Window::_1, time),
+            JoinRxWindow::_2(time) => (Window::_2, time),
+        };
+        match self.shared.get_rx_window_start(&Frame::Join, &window, time) {
+            Ok(start) => (self.into(), Ok(Response::TimeoutRequest(start))),
+            // the join accept can not be received so the join attempt is given up
+            Err(e) => (
+                Idle {
+                    shared: self.shared,
+                    join_attempts: self.join_attempts,
+                }
+                .into(),
+                Err(e.into()),
+            ),
+        }
+    }
+}
+
+impl<R, G> From<WaitingForRxWindow<R, G>> for WaitingForJoinResponse<R, G>
+where
+    R: radio::PhyRxTx + Timings,
+    G: RngCore,
+{
+    fn from(val: WaitingForRxWindow<R, G>) -> WaitingForJoinResponse<R, G> {
         WaitingForJoinResponse {
             join_rx_window: val.join_rx_window,
             shared: val.shared,
@@ -386,64 +534,135 @@ where
     }
 }
 
-pub struct WaitingForJoinResponse<R>
+pub struct WaitingForJoinResponse<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
-    join_attempts: usize,
+    shared: Shared<R, G>,
+    join_attempts: JoinAttempts,
     devnonce: DevNonce,
     join_rx_window: JoinRxWindow,
 }
 
-impl<R> WaitingForJoinResponse<R>
+impl<R, G> WaitingForJoinResponse<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // we are waiting for the async tx to complete
             Event::RadioEvent(radio_event) => {
                 // send the transmit request to the radio
                 match self.shared.radio.handle_event(radio_event) {
                     Ok(response) => match response {
-                        radio::Response::RxDone(_quality) => {
+                        radio::Response::RxDone(quality, timestamp_us) => {
                             if let Ok(PhyPayload::JoinAccept(JoinAcceptPayload::Encrypted(
                                 encrypted,
                             ))) =
                                 lorawan_parse(self.shared.radio.get_received_packet(), C::default())
                             {
                                 let credentials = &self.shared.credentials;
-                                let decrypt = encrypted.decrypt(credentials.appkey());
-                                self.shared.downlink = Some(super::Downlink::Join(
-                                    self.shared.region.process_join_accept(&decrypt),
-                                ));
-                                if decrypt.validate_mic(credentials.appkey()) {
-                                    let session = SessionData::derive_new(
-                                        &decrypt,
-                                        self.devnonce,
-                                        credentials,
+                                let decrypt = encrypted.decrypt(credentials.nwkkey());
+                                // a LoRaWAN 1.1 device falls back to 1.0 if OptNeg is not set
+                                let opt_neg =
+                                    credentials.is_v1_1() && decrypt.dl_settings().opt_neg();
+                                let mic_ok = if opt_neg {
+                                    let js_int_key = derive_js_int_key(
+                                        credentials.nwkkey(),
+                                        &EUI64::new(credentials.deveui()).unwrap(),
+                                        &C::default(),
                                     );
+                                    decrypt.validate_mic_v1_1(
+                                        &js_int_key,
+                                        JOIN_REQ_TYPE,
+                                        &EUI64::new(credentials.joineui()).unwrap(),
+                                        &self.devnonce,
+                                    )
+                                } else {
+                                    decrypt.validate_mic(credentials.nwkkey())
+                                };
+                                // a LoRaWAN 1.1 JoinNonce must increase, replayed join
+                                // accepts being rejected
+                                let join_nonce = opt_neg.then(|| {
+                                    let join_nonce = decrypt.join_nonce();
+                                    let join_nonce = join_nonce.as_ref();
+                                    u32::from_le_bytes([
+                                        join_nonce[0],
+                                        join_nonce[1],
+                                        join_nonce[2],
+                                        0,
+                                    ])
+                                });
+                                let nonce_ok = match (join_nonce, credentials.joinnonce()) {
+                                    (Some(join_nonce), Some(last)) => join_nonce > last,
+                                    _ => true,
+                                };
+                                // region configuration is only updated once the join accept
+                                // is known to be authentic
+                                if mic_ok && nonce_ok {
+                                    if join_nonce.is_some() {
+                                        self.shared.credentials.set_joinnonce(join_nonce);
+                                    }
+                                    let credentials = &self.shared.credentials;
+                                    self.shared.downlink = Some(super::Downlink::Join(
+                                        self.shared.region.process_join_accept(&decrypt),
+                                    ));
+                                    let session = if opt_neg {
+                                        SessionData::derive_new_v1_1(
+                                            &decrypt,
+                                            self.devnonce,
+                                            credentials,
+                                        )
+                                    } else {
+                                        SessionData::derive_new(
+                                            &decrypt,
+                                            self.devnonce,
+                                            credentials,
+                                        )
+                                    };
+                                    let window = match self.join_rx_window {
+                                        JoinRxWindow::_1(_) => Window::_1,
+                                        JoinRxWindow::_2(_) => Window::_2,
+                                    };
+                                    self.shared.set_rx_metadata(
+                                        &Frame::Join,
+                                        &window,
+                                        quality,
+                                        timestamp_us,
+                                    );
+                                    self.shared.mac.set_rekey_ind(opt_neg);
                                     return (
                                         Session::new(self.shared, session).into(),
                                         Ok(Response::JoinSuccess),
                                     );
                                 }
+                                return (self.into(), Ok(Response::JoinAcceptRejected));
                             }
                             (self.into(), Ok(Response::NoUpdate))
                         }
                         _ => (self.into(), Ok(Response::NoUpdate)),
                     },
-                    Err(e) => (self.into(), Err(e.into())),
+                    // the radio state is unknown so the join attempt is given up
+                    Err(e) => (Idle::from(self).into(), Err(e.into())),
                 }
             }
             Event::TimeoutFired => {
                 // send the transmit request to the radio
-                if let Err(_e) = self.shared.radio.handle_event(radio::Event::CancelRx) {
-                    panic!("Error cancelling Rx");
+                // the radio state is unknown so the join attempt is given up
+                if let Err(e) = self.shared.radio.handle_event(radio::Event::CancelRx) {
+                    return (
+                        Idle {
+                            shared: self.shared,
+                            join_attempts: self.join_attempts,
+                        }
+                        .into(),
+                        Err(e.into()),
+                    );
                 }
 
                 match self.join_rx_window {
@@ -453,26 +672,20 @@ where
                                 - self.shared.region.get_rx_delay(&Frame::Join, &Window::_1);
                         let t2 = t1 + time_between_windows;
                         // TODO: jump to RxWindow2 if t2 == now
-                        (
-                            WaitingForRxWindow {
-                                shared: self.shared,
-                                devnonce: self.devnonce,
-                                join_attempts: self.join_attempts,
-                                join_rx_window: JoinRxWindow::_2(t2),
-                            }
-                            .into(),
-                            Ok(Response::TimeoutRequest(t2)),
-                        )
-                    }
-                    // Timeout during second RxWindow leads to giving up
-                    JoinRxWindow::_2(_) => (
-                        Idle {
+                        WaitingForRxWindow {
                             shared: self.shared,
+                            devnonce: self.devnonce,
                             join_attempts: self.join_attempts,
+                            join_rx_window: JoinRxWindow::_2(t2),
                         }
-                        .into(),
-                        Ok(Response::NoJoinAccept),
-                    ),
+                        .into_timeout_request()
+                    }
+                    // Timeout during second RxWindow leads to a retry, or giving up after the
+                    // maximum number of attempts
+                    JoinRxWindow::_2(t2) => {
+                        let window_close = t2 + self.shared.radio.get_rx_window_duration_ms();
+                        self.into_retry(window_close)
+                    }
                 }
             }
             Event::NewSessionRequest => (
@@ -484,11 +697,35 @@ where
     }
 }
 
-impl<R> From<WaitingForJoinResponse<R>> for Idle<R>
+impl<R, G> WaitingForJoinResponse<R, G>
+where
+    R: radio::PhyRxTx + Timings,
+    G: RngCore,
+{
+    fn into_retry<C: CryptoFactory + Default>(
+        mut self,
+        window_close: TimestampMs,
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
+        let max_join_attempts = self.shared.max_join_attempts;
+        if max_join_attempts.is_some_and(|max| self.join_attempts.count >= max) {
+            if let Some(datarate) = self.join_attempts.datarate {
+                self.shared.datarate = datarate;
+            }
+            self.join_attempts.restart();
+            return (Idle::from(self).into(), Ok(Response::NoJoinAccept));
+        }
+        let random = self.shared.rng.next_u32();
+        let retry = self.join_attempts.retry_time(window_close, random);
+        (Idle::from(self).into(), Ok(Response::TimeoutRequest(retry)))
+    }
+}
+
+impl<R, G> From<WaitingForJoinResponse<R, G>> for Idle<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    fn from(val: WaitingForJoinResponse<R>) -> Idle<R> {
+    fn from(val: WaitingForJoinResponse<R, G>) -> Idle<R, G> {
         Idle {
             shared: val.shared,
             join_attempts: val.join_attempts,
@@ -497,11 +734,25 @@ where
 }
 
 pub struct SessionData {
+    // NwkSKey for LoRaWAN 1.0 and FNwkSIntKey for LoRaWAN 1.1
     newskey: AES128,
     appskey: AES128,
+    // for LoRaWAN 1.0 the SNwkSIntKey and NwkSEncKey are the same as NwkSKey
+    snwksintkey: AES128,
+    nwksenckey: AES128,
+    v1_1: bool,
     devaddr: DevAddr<[u8; 4]>,
     fcnt_up: u32,
-    pub fcnt_down: u32,
+    // set once all 32-bit values of FCntUp have been used
+    fcnt_up_exhausted: bool,
+    // last accepted FCntDown for LoRaWAN 1.0 and NFCntDown for LoRaWAN 1.1, None until the
+    // first downlink
+    pub fcnt_down: Option<u32>,
+    pub afcnt_down: Option<u32>,
+    // FCntDown of a confirmed downlink to be acknowledged by the next uplink
+    pub ack_pending: Option<u32>,
+    // FPending of the last downlink, set when the network has more downlinks queued
+    pub downlink_pending: bool,
 }
 
 impl SessionData {
@@ -510,21 +761,61 @@ impl SessionData {
         devnonce: DevNonce,
         credentials: &Credentials,
     ) -> SessionData {
+        let newskey = decrypt.derive_newskey(&devnonce, credentials.nwkkey());
+        SessionData {
+            newskey,
+            appskey: decrypt.derive_appskey(&devnonce, credentials.nwkkey()),
+            snwksintkey: newskey,
+            nwksenckey: newskey,
+            v1_1: false,
+            devaddr: Self::copy_devaddr(decrypt),
+            fcnt_up: 0,
+            fcnt_up_exhausted: false,
+            fcnt_down: None,
+            afcnt_down: None,
+            ack_pending: None,
+            downlink_pending: false,
+        }
+    }
+
+    pub fn derive_new_v1_1<
+        T: core::convert::AsRef<[u8]>,
+        F: lorawan_encoding::keys::CryptoFactory,
+    >(
+        decrypt: &DecryptedJoinAcceptPayload<T, F>,
+        devnonce: DevNonce,
+        credentials: &Credentials,
+    ) -> SessionData {
+        let joineui = EUI64::new(credentials.joineui()).unwrap();
+        let nwkkey = credentials.nwkkey();
         SessionData {
-            newskey: decrypt.derive_newskey(&devnonce, credentials.appkey()),
-            appskey: decrypt.derive_appskey(&devnonce, credentials.appkey()),
-            devaddr: DevAddr::new([
-                decrypt.dev_addr().as_ref()[0],
-                decrypt.dev_addr().as_ref()[1],
-                decrypt.dev_addr().as_ref()[2],
-                decrypt.dev_addr().as_ref()[3],
-            ])
-            .unwrap(),
+            newskey: decrypt.derive_fnwksintkey(&joineui, &devnonce, nwkkey),
+            appskey: decrypt.derive_appskey_v1_1(&joineui, &devnonce, credentials.appkey()),
+            snwksintkey: decrypt.derive_snwksintkey(&joineui, &devnonce, nwkkey),
+            nwksenckey: decrypt.derive_nwksenckey(&joineui, &devnonce, nwkkey),
+            v1_1: true,
+            devaddr: Self::copy_devaddr(decrypt),
             fcnt_up: 0,
-            fcnt_down: 0,
+            fcnt_up_exhausted: false,
+            fcnt_down: None,
+            afcnt_down: None,
+            ack_pending: None,
+            downlink_pending: false,
         }
     }
 
+    fn copy_devaddr<T: core::convert::AsRef<[u8]>, F>(
+        decrypt: &DecryptedJoinAcceptPayload<T, F>,
+    ) -> DevAddr<[u8; 4]> {
+        DevAddr::new([
+            decrypt.dev_addr().as_ref()[0],
+            decrypt.dev_addr().as_ref()[1],
+            decrypt.dev_addr().as_ref()[2],
+            decrypt.dev_addr().as_ref()[3],
+        ])
+        .unwrap()
+    }
+
     pub fn newskey(&self) -> &AES128 {
         &self.newskey
     }
@@ -533,6 +824,22 @@ impl SessionData {
         &self.appskey
     }
 
+    pub fn fnwksintkey(&self) -> &AES128 {
+        &self.newskey
+    }
+
+    pub fn snwksintkey(&self) -> &AES128 {
+        &self.snwksintkey
+    }
+
+    pub fn nwksenckey(&self) -> &AES128 {
+        &self.nwksenckey
+    }
+
+    pub fn is_v1_1(&self) -> bool {
+        self.v1_1
+    }
+
     pub fn devaddr(&self) -> &DevAddr<[u8; 4]> {
         &self.devaddr
     }
@@ -542,6 +849,72 @@ impl SessionData {
     }
 
     pub fn fcnt_up_increment(&mut self) {
-        self.fcnt_up += 1;
+        match self.fcnt_up.checked_add(1) {
+            Some(fcnt_up) => self.fcnt_up = fcnt_up,
+            None => self.fcnt_up_exhausted = true,
+        }
+    }
+
+    // no more uplinks can be sent in this session
+    pub fn fcnt_up_exhausted(&self) -> bool {
+        self.fcnt_up_exhausted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_join_duty_cycle() {
+        let mut join_attempts = JoinAttempts {
+            airtime_us: 100_000,
+            ..Default::default()
+        };
+        // 1% during the first hour
+        join_attempts.transmitted(100_000);
+        assert_eq!(join_attempts.next_us, 10_000_000);
+        join_attempts.transmitted(HOUR_US);
+        assert_eq!(join_attempts.next_us, HOUR_US - 100_000 + 10_000_000);
+        // 0.1% during the next 10 hours
+        join_attempts.transmitted(HOUR_US + 100_000);
+        assert_eq!(join_attempts.next_us, HOUR_US + 100_000_000);
+        join_attempts.transmitted(11 * HOUR_US);
+        assert_eq!(join_attempts.next_us, 11 * HOUR_US - 100_000 + 100_000_000);
+        // 0.01% afterwards
+        join_attempts.transmitted(11 * HOUR_US + 100_000);
+        assert_eq!(join_attempts.next_us, 11 * HOUR_US + 1_000_000_000);
+    }
+
+    #[test]
+    fn test_join_backoff() {
+        let mut join_attempts = JoinAttempts {
+            next_us: 5_000_000,
+            ..Default::default()
+        };
+        // the retry waits for the duty cycle and the RX windows to be over
+        assert_eq!(join_attempts.retry_time(2_000, 0), 5_000);
+        assert_eq!(join_attempts.retry_time(8_000, 0), 8_000);
+        assert_eq!(join_attempts.retry_time(8_000, u32::MAX), 8_999);
+
+        join_attempts.count = 3;
+        assert_eq!(join_attempts.retry_time(8_000, u32::MAX), 15_999);
+        join_attempts.count = 6;
+        assert_eq!(join_attempts.retry_time(8_000, u32::MAX), 71_999);
+        // the backoff is capped
+        join_attempts.count = 100;
+        assert_eq!(join_attempts.retry_time(8_000, u32::MAX), 71_999);
+    }
+
+    #[test]
+    fn test_join_datarate_steps_down() {
+        let mut join_attempts = JoinAttempts::default();
+        let datarates: [u8; 6] = core::array::from_fn(|count| {
+            join_attempts.count = count;
+            join_attempts.next_datarate(DR::_2) as u8
+        });
+        assert_eq!(datarates, [2, 2, 1, 1, 0, 0]);
+        join_attempts.restart();
+        assert!(matches!(join_attempts.next_datarate(DR::_5), DR::_5));
     }
 }
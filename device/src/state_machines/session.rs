@@ -55,17 +55,18 @@ use generic_array::{typenum::U256, GenericArray};
 use lorawan_encoding::{
     self,
     creator::DataPayloadCreator,
-    maccommands::SerializableMacCommand,
+    maccommands::{mac_commands_len, SerializableMacCommand},
     parser::{parse_with_factory as lorawan_parse, *},
 };
-pub enum Session<R>
+pub enum Session<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    Idle(Idle<R>),
-    SendingData(SendingData<R>),
-    WaitingForRxWindow(WaitingForRxWindow<R>),
-    WaitingForRx(WaitingForRx<R>),
+    Idle(Idle<R, G>),
+    SendingData(SendingData<R, G>),
+    WaitingForRxWindow(WaitingForRxWindow<R, G>),
+    WaitingForRx(WaitingForRx<R, G>),
 }
 
 enum RxWindow {
@@ -73,16 +74,16 @@ enum RxWindow {
     _2(u32),
 }
 
-trait SessionState<R: radio::PhyRxTx + Timings> {
+trait SessionState<R: radio::PhyRxTx + Timings, G: RngCore> {
     fn get_session(&self) -> &SessionData;
 }
 
 macro_rules! into_state {
     ($($from:tt),*) => {
     $(
-        impl<R: radio::PhyRxTx + Timings, C: CryptoFactory + Default> From<$from<R>> for Device<R, C>
+        impl<R: radio::PhyRxTx + Timings, C: CryptoFactory + Default, G: RngCore> From<$from<R, G>> for Device<R, C, G>
         {
-            fn from(state: $from<R>) -> Device<R, C> {
+            fn from(state: $from<R, G>) -> Device<R, C, G> {
                 Device {
                     crypto: PhantomData::default(),
                     state: SuperState::Session(Session::$from(state))
@@ -90,26 +91,27 @@ macro_rules! into_state {
             }
         }
 
-        impl<R: radio::PhyRxTx + Timings> SessionState<R> for $from<R> {
+        impl<R: radio::PhyRxTx + Timings, G: RngCore> SessionState<R, G> for $from<R, G> {
             fn get_session(&self) -> &SessionData {
                 &self.session
             }
         }
 
-        impl<R: radio::PhyRxTx + Timings> CommonState<R> for $from<R> {
-            fn get_mut_shared(&mut self) -> &mut Shared<R> {
+        impl<R: radio::PhyRxTx + Timings, G: RngCore> CommonState<R, G> for $from<R, G> {
+            fn get_mut_shared(&mut self) -> &mut Shared<R, G> {
                 &mut self.shared
             }
         }
     )*};
 }
 
-impl<R, C> From<Session<R>> for Device<R, C>
+impl<R, C, G> From<Session<R, G>> for Device<R, C, G>
 where
     R: radio::PhyRxTx + Timings,
     C: CryptoFactory + Default,
+    G: RngCore,
 {
-    fn from(session: Session<R>) -> Device<R, C> {
+    fn from(session: Session<R, G>) -> Device<R, C, G> {
         Device {
             state: SuperState::Session(session),
             crypto: PhantomData::default(),
@@ -119,6 +121,19 @@ where
 
 into_state![Idle, SendingData, WaitingForRxWindow, WaitingForRx];
 
+// either all FCntUp values were used, the configured rejoin threshold was reached or a
+// LoRaWAN 1.1 session was never confirmed by a RekeyConf
+fn session_expired<R: radio::PhyRxTx + Timings, G: RngCore>(
+    shared: &Shared<R, G>,
+    session: &SessionData,
+) -> bool {
+    session.fcnt_up_exhausted()
+        || shared
+            .get_rejoin_threshold()
+            .is_some_and(|threshold| session.fcnt_up() >= threshold)
+        || shared.mac.rekey_ind_expired()
+}
+
 #[derive(Debug)]
 pub enum Error {
     RadioEventWhileIdle,
@@ -127,6 +142,12 @@ pub enum Error {
     SendDataWhileWaitingForRxWindow,
     NewSessionWhileWaitingForRx,
     SendDataWhileWaitingForRx,
+    SendDataWhileSessionExpired,
+    NewSessionWhileSendingData,
+    SendDataWhileSendingData,
+    UnexpectedRadioResponse,
+    PayloadTooLong,
+    Encoding(lorawan_encoding::error::Error),
 }
 
 impl<R> From<Error> for super::super::Error<R>
@@ -138,15 +159,16 @@ where
     }
 }
 
-impl<R> Session<R>
+impl<R, G> Session<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    pub fn new(shared: Shared<R>, session: SessionData) -> Session<R> {
+    pub fn new(shared: Shared<R, G>, session: SessionData) -> Session<R, G> {
         Session::Idle(Idle { shared, session })
     }
 
-    pub fn get_mut_shared(&mut self) -> &mut Shared<R> {
+    pub fn get_mut_shared(&mut self) -> &mut Shared<R, G> {
         match self {
             Session::Idle(state) => state.get_mut_shared(),
             Session::SendingData(state) => state.get_mut_shared(),
@@ -167,7 +189,7 @@ where
     pub fn handle_event<C: CryptoFactory + Default>(
         self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match self {
             Session::Idle(state) => state.handle_event(event),
             Session::SendingData(state) => state.handle_event(event),
@@ -177,18 +199,30 @@ where
     }
 }
 
-impl<'a, R> Idle<R>
+impl<'a, R, G> Idle<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    #[allow(clippy::match_wild_err_arm)]
-    fn prepare_buffer<C: CryptoFactory + Default>(&mut self, data: &SendData) -> FcntUp {
+    fn prepare_buffer<C: CryptoFactory + Default>(
+        &mut self,
+        data: &SendData,
+    ) -> Result<FcntUp, super::super::Error<R>> {
+        let max_payload_len = self.shared.get_max_payload_len()?;
+        if data.data.len() > max_payload_len {
+            return Err(Error::PayloadTooLong.into());
+        }
+
         let fcnt = self.session.fcnt_up();
         let mut phy: DataPayloadCreator<GenericArray<u8, U256>, C> = DataPayloadCreator::default();
         phy.set_confirmed(data.confirmed)
-            .set_f_port(data.fport)
             .set_dev_addr(*self.session.devaddr())
             .set_fcnt(fcnt);
+        if let Some(conf_fcnt) = self.session.ack_pending {
+            // ACK bit of FCtrl
+            phy.set_fctrl(&FCtrl::new(0x20, true))
+                .set_conf_fcnt(conf_fcnt as u16);
+        }
 
         let mut cmds = Vec::new();
         self.shared.mac.get_cmds(&mut cmds);
@@ -196,43 +230,83 @@ where
         let mut dyn_cmds: Vec<&dyn SerializableMacCommand, U8> = Vec::new();
 
         for cmd in &cmds {
-            if let Err(_e) = dyn_cmds.push(cmd) {
-                panic!("dyn_cmds too small compared to cmds")
-            }
+            dyn_cmds
+                .push(cmd)
+                .map_err(|_| Error::Encoding(lorawan_encoding::error::Error::MacCommandsTooLong))?;
         }
 
-        match phy.build(
-            &data.data,
-            dyn_cmds.as_slice(),
-            self.session.newskey(),
-            self.session.appskey(),
-        ) {
-            Ok(packet) => {
-                self.shared.buffer.clear();
-                self.shared.buffer.extend(packet);
+        let mac_cmds_len = mac_commands_len(&dyn_cmds);
+        let mut fport = data.fport;
+        if mac_cmds_len > 0 {
+            let can_piggyback = DataPayloadCreator::<[u8; 256], C>::can_piggyback(&dyn_cmds);
+            if can_piggyback && data.data.len() + mac_cmds_len <= max_payload_len {
+                // the MAC commands are sent in FOpts
+            } else if data.data.is_empty() && mac_cmds_len <= max_payload_len {
+                // the MAC commands are sent as FRMPayload
+                fport = 0;
+            } else {
+                // the MAC commands are deferred to a later uplink, the Vec being replaced as
+                // clear indexes past the end of the slice in heapless 0.6
+                dyn_cmds = Vec::new();
             }
-            Err(_) => panic!("Error assembling packet!"),
         }
-        fcnt
+        phy.set_f_port(fport);
+
+        let packet = if self.session.is_v1_1() {
+            phy.set_tx_dr(self.shared.datarate as u8)
+                .set_tx_ch(self.shared.region.get_tx_channel());
+            phy.build_v1_1(
+                data.data,
+                dyn_cmds.as_slice(),
+                self.session.fnwksintkey(),
+                self.session.snwksintkey(),
+                self.session.nwksenckey(),
+                self.session.appskey(),
+            )
+        } else {
+            phy.build(
+                &data.data,
+                dyn_cmds.as_slice(),
+                self.session.newskey(),
+                self.session.appskey(),
+            )
+        };
+        let packet = packet.map_err(Error::Encoding)?;
+        self.shared.buffer.clear();
+        self.shared.buffer.extend(packet);
+        if !dyn_cmds.is_empty() {
+            self.shared.mac.cmds_sent();
+        }
+        Ok(fcnt)
     }
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
+            Event::SendDataRequest(_) if self.session.fcnt_up_exhausted() => {
+                (self.into(), Err(Error::SendDataWhileSessionExpired.into()))
+            }
             Event::SendDataRequest(send_data) => {
+                let random = self.shared.rng.next_u32();
+                // the channel is selected first as LoRaWAN 1.1 MIC depends on it
+                let tx_config = match self.shared.region.create_tx_config(
+                    random,
+                    self.shared.datarate,
+                    &Frame::Data,
+                ) {
+                    Ok(tx_config) => tx_config,
+                    Err(e) => return (self.into(), Err(e.into())),
+                };
+
                 // encodes the packet and places it in send buffer
-                let fcnt = self.prepare_buffer::<C>(&send_data);
-                let random = (self.shared.get_random)();
-
-                let event: radio::Event<R> = radio::Event::TxRequest(
-                    self.shared.region.create_tx_config(
-                        random as u8,
-                        self.shared.datarate,
-                        &Frame::Data,
-                    ),
-                    &mut self.shared.buffer,
-                );
+                let fcnt = match self.prepare_buffer::<C>(&send_data) {
+                    Ok(fcnt) => fcnt,
+                    Err(e) => return (self.into(), Err(e)),
+                };
+
+                let event: radio::Event<R> =
+                    radio::Event::TxRequest(tx_config, &mut self.shared.buffer);
 
                 let confirmed = send_data.confirmed;
 
@@ -248,12 +322,18 @@ where
                             ),
                             // directly jump to waiting for RxWindow
                             // allows for synchronous sending
-                            radio::Response::TxDone(ms) => {
-                                data_rxwindow1_timeout(Session::Idle(self), confirmed, ms)
-                            }
-                            _ => {
-                                panic!("Idle: Unexpected radio response");
+                            radio::Response::TxDone(timestamp_us) => {
+                                // the ACK was sent and the RX windows of this uplink
+                                // tell whether more downlinks are queued
+                                self.session.ack_pending = None;
+                                self.session.downlink_pending = false;
+                                let first_window =
+                                    self.shared.region.get_rx_delay(&Frame::Data, &Window::_1)
+                                        + (timestamp_us / 1000) as TimestampMs;
+                                self.into_waiting_for_rxwindow(confirmed, first_window)
+                                    .into_timeout_request()
                             }
+                            _ => (self.into(), Err(Error::UnexpectedRadioResponse.into())),
                         }
                     }
                     Err(e) => (self.into(), Err(e.into())),
@@ -271,7 +351,7 @@ where
         }
     }
 
-    fn into_sending_data(self, confirmed: bool) -> SendingData<R> {
+    fn into_sending_data(self, confirmed: bool) -> SendingData<R, G> {
         SendingData {
             session: self.session,
             shared: self.shared,
@@ -279,7 +359,7 @@ where
         }
     }
 
-    fn into_waiting_for_rxwindow(self, confirmed: bool, time: u32) -> WaitingForRxWindow<R> {
+    fn into_waiting_for_rxwindow(self, confirmed: bool, time: u32) -> WaitingForRxWindow<R, G> {
         WaitingForRxWindow {
             session: self.session,
             shared: self.shared,
@@ -289,31 +369,34 @@ where
     }
 }
 
-pub struct Idle<R>
+pub struct Idle<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
+    shared: Shared<R, G>,
     session: SessionData,
 }
 
-pub struct SendingData<R>
+pub struct SendingData<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
+    shared: Shared<R, G>,
     session: SessionData,
     confirmed: bool,
 }
 
-impl<R> SendingData<R>
+impl<R, G> SendingData<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // we are waiting for the async tx to complete
             Event::RadioEvent(radio_event) => {
@@ -322,29 +405,37 @@ where
                     Ok(response) => {
                         match response {
                             // expect a complete transmit
-                            radio::Response::TxDone(ms) => {
+                            radio::Response::TxDone(timestamp_us) => {
+                                // the ACK was sent and the RX windows of this uplink
+                                // tell whether more downlinks are queued
+                                self.session.ack_pending = None;
+                                self.session.downlink_pending = false;
                                 let confirmed = self.confirmed;
-                                data_rxwindow1_timeout(Session::SendingData(self), confirmed, ms)
+                                let first_window =
+                                    self.shared.region.get_rx_delay(&Frame::Data, &Window::_1)
+                                        + (timestamp_us / 1000) as TimestampMs;
+                                self.into_waiting_for_rxwindow(confirmed, first_window)
+                                    .into_timeout_request()
                             }
                             // anything other than TxComplete is unexpected
-                            _ => {
-                                panic!("SendingData: Unexpected radio response");
-                            }
+                            _ => (self.into(), Err(Error::UnexpectedRadioResponse.into())),
                         }
                     }
-                    Err(e) => (self.into(), Err(e.into())),
+                    // the uplink is given up
+                    Err(e) => (self.into_idle().into(), Err(e.into())),
                 }
             }
             // tolerate unexpected timeout
             Event::TimeoutFired => (self.into(), Ok(Response::NoUpdate)),
             // anything other than a RadioEvent is unexpected
-            Event::NewSessionRequest | Event::SendDataRequest(_) => {
-                panic!("Unexpected event while SendingJoin")
+            Event::NewSessionRequest => {
+                (self.into(), Err(Error::NewSessionWhileSendingData.into()))
             }
+            Event::SendDataRequest(_) => (self.into(), Err(Error::SendDataWhileSendingData.into())),
         }
     }
 
-    fn into_waiting_for_rxwindow(self, confirmed: bool, time: u32) -> WaitingForRxWindow<R> {
+    fn into_waiting_for_rxwindow(self, confirmed: bool, time: u32) -> WaitingForRxWindow<R, G> {
         WaitingForRxWindow {
             session: self.session,
             shared: self.shared,
@@ -352,26 +443,39 @@ where
             confirmed,
         }
     }
+
+    // the uplink may have been transmitted, so its FCnt is not reused unless it is retransmitted
+    fn into_idle(mut self) -> Idle<R, G> {
+        if !self.confirmed {
+            self.session.fcnt_up_increment();
+        }
+        Idle {
+            shared: self.shared,
+            session: self.session,
+        }
+    }
 }
 
-pub struct WaitingForRxWindow<R>
+pub struct WaitingForRxWindow<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
+    shared: Shared<R, G>,
     session: SessionData,
     confirmed: bool,
     rx_window: RxWindow,
 }
 
-impl<'a, R> WaitingForRxWindow<R>
+impl<'a, R, G> WaitingForRxWindow<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // we are waiting for a Timeout
             Event::TimeoutFired => {
@@ -379,10 +483,11 @@ where
                     RxWindow::_1(_) => Window::_1,
                     RxWindow::_2(_) => Window::_2,
                 };
-                let rx_config =
-                    self.shared
-                        .region
-                        .get_rx_config(self.shared.datarate, &Frame::Join, &window);
+                let rx_config = match self.shared.get_rx_window(&Frame::Data, &window) {
+                    Ok((rx_config, _)) => rx_config,
+                    // no downlink can be received so the uplink is complete
+                    Err(e) => return (self.into_idle().into(), Err(e.into())),
+                };
 
                 // configure the radio for the RX
                 match self
@@ -417,7 +522,8 @@ where
                             Ok(Response::TimeoutRequest(window_close)),
                         )
                     }
-                    Err(e) => (self.into(), Err(e.into())),
+                    // no downlink can be received so the uplink is complete
+                    Err(e) => (self.into_idle().into(), Err(e.into())),
                 }
             }
             Event::RadioEvent(_) => (
@@ -436,11 +542,44 @@ where
     }
 }
 
-impl<R> From<WaitingForRxWindow<R>> for WaitingForRx<R>
+impl<R, G> WaitingForRxWindow<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    fn from(val: WaitingForRxWindow<R>) -> WaitingForRx<R> {
+    // requests the timeout at which the radio is started for the RX window
+    fn into_timeout_request<C: CryptoFactory + Default>(
+        mut self,
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
+        let (window, time) = match self.rx_window {
+            RxWindow::_1(time) => (Window::_1, time),
+            RxWindow::_2(time) => (Window::_2, time),
+        };
+        match self.shared.get_rx_window_start(&Frame::Data, &window, time) {
+            Ok(start) => (self.into(), Ok(Response::TimeoutRequest(start))),
+            // no downlink can be received so the uplink is complete
+            Err(e) => (self.into_idle().into(), Err(e.into())),
+        }
+    }
+
+    fn into_idle(mut self) -> Idle<R, G> {
+        // if this was not a confirmed frame, we can still increment the FCnt Up
+        if !self.confirmed {
+            self.session.fcnt_up_increment();
+        }
+        Idle {
+            shared: self.shared,
+            session: self.session,
+        }
+    }
+}
+
+impl<R, G> From<WaitingForRxWindow<R, G>> for WaitingForRx<R, G>
+where
+    R: radio::PhyRxTx + Timings,
+    G: RngCore,
+{
+    fn from(val: WaitingForRxWindow<R, G>) -> WaitingForRx<R, G> {
         WaitingForRx {
             shared: val.shared,
             session: val.session,
@@ -450,46 +589,87 @@ where
     }
 }
 
-pub struct WaitingForRx<R>
+pub struct WaitingForRx<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
-    shared: Shared<R>,
+    shared: Shared<R, G>,
     session: SessionData,
     confirmed: bool,
     rx_window: RxWindow,
 }
 
-impl<'a, R> WaitingForRx<R>
+impl<'a, R, G> WaitingForRx<R, G>
 where
     R: radio::PhyRxTx + Timings,
+    G: RngCore,
 {
     pub fn handle_event<C: CryptoFactory + Default>(
         mut self,
         event: Event<R>,
-    ) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
+    ) -> (Device<R, C, G>, Result<Response, super::super::Error<R>>) {
         match event {
             // we are waiting for the async tx to complete
             Event::RadioEvent(radio_event) => {
                 // send the transmit request to the radio
                 match self.shared.radio.handle_event(radio_event) {
                     Ok(response) => match response {
-                        radio::Response::RxDone(_quality) => {
+                        radio::Response::RxDone(quality, timestamp_us) => {
                             if let Ok(PhyPayload::Data(DataPayload::Encrypted(encrypted_data))) =
                                 lorawan_parse(self.shared.radio.get_received_packet(), C::default())
                             {
                                 let session = &mut self.session;
-                                if session.devaddr() == &encrypted_data.fhdr().dev_addr() {
-                                    let fcnt = encrypted_data.fhdr().fcnt() as u32;
-                                    if encrypted_data.validate_mic(&session.newskey(), fcnt)
-                                        && (fcnt > session.fcnt_down || fcnt == 0)
-                                    {
-                                        session.fcnt_down = fcnt;
+                                // LoRaWAN 1.1 uses AFCntDown for application payloads
+                                let is_app_fcnt =
+                                    session.is_v1_1() && encrypted_data.f_port().unwrap_or(0) != 0;
+                                let last_fcnt_down = if is_app_fcnt {
+                                    session.afcnt_down
+                                } else {
+                                    session.fcnt_down
+                                };
+                                // the full FCnt is needed for the MIC and replayed frames are
+                                // rejected before even checking it
+                                let next_fcnt_down = match last_fcnt_down {
+                                    Some(last) => last.checked_add(1),
+                                    None => Some(0),
+                                };
+                                let fcnt = next_fcnt_down.and_then(|next| {
+                                    restore_fcnt(next, encrypted_data.fhdr().fcnt())
+                                });
+                                let for_us = session.devaddr() == &encrypted_data.fhdr().dev_addr();
+                                if let Some(fcnt) = fcnt.filter(|_| for_us) {
+                                    let mic_ok = if session.is_v1_1() {
+                                        // ConfFCnt is the FCnt of the confirmed uplink being
+                                        // acknowledged
+                                        let conf_fcnt = if self.confirmed {
+                                            session.fcnt_up() as u16
+                                        } else {
+                                            0
+                                        };
+                                        encrypted_data.validate_downlink_mic_v1_1(
+                                            session.snwksintkey(),
+                                            fcnt,
+                                            conf_fcnt,
+                                        )
+                                    } else {
+                                        encrypted_data.validate_mic(&session.newskey(), fcnt)
+                                    };
+                                    if mic_ok {
+                                        if is_app_fcnt {
+                                            session.afcnt_down = Some(fcnt);
+                                        } else {
+                                            session.fcnt_down = Some(fcnt);
+                                        }
                                         // increment the FcntUp since we have received
                                         // downlink - only reason to not increment
                                         // is if confirmed frame is sent and no
                                         // confirmation (ie: downlink) occurs
                                         session.fcnt_up_increment();
+                                        // an unconfirmed downlink means the network no
+                                        // longer expects an ACK
+                                        session.ack_pending =
+                                            encrypted_data.is_confirmed().then_some(fcnt);
 
                                         let mut copy = Vec::new();
                                         copy.extend_from_slice(encrypted_data.as_bytes()).unwrap();
@@ -498,16 +678,24 @@ where
                                         // * making a new EncryptedDataPayload with owned bytes will
                                         //      always work when copy bytes from another EncryptedPayload
                                         // * the decrypt will always work when we have verified MIC previously
-                                        let decrypted = EncryptedDataPayload::new_with_factory(
+                                        let encrypted = EncryptedDataPayload::new_with_factory(
                                             copy,
                                             C::default(),
                                         )
-                                        .unwrap()
-                                        .decrypt(
-                                            Some(&session.newskey()),
-                                            Some(&session.appskey()),
-                                            session.fcnt_down,
-                                        )
+                                        .unwrap();
+                                        let decrypted = if session.is_v1_1() {
+                                            encrypted.decrypt_v1_1(
+                                                Some(session.nwksenckey()),
+                                                Some(session.appskey()),
+                                                fcnt,
+                                            )
+                                        } else {
+                                            encrypted.decrypt(
+                                                Some(&session.newskey()),
+                                                Some(&session.appskey()),
+                                                fcnt,
+                                            )
+                                        }
                                         .unwrap();
 
                                         self.shared.mac.handle_downlink_macs(
@@ -524,18 +712,36 @@ where
                                             );
                                         }
 
+                                        let window = match self.rx_window {
+                                            RxWindow::_1(_) => Window::_1,
+                                            RxWindow::_2(_) => Window::_2,
+                                        };
+                                        self.shared.set_rx_metadata(
+                                            &Frame::Data,
+                                            &window,
+                                            quality,
+                                            timestamp_us,
+                                        );
+
+                                        // the network has more downlinks queued
+                                        self.session.downlink_pending =
+                                            decrypted.fhdr().fctrl().f_pending();
                                         self.shared.downlink =
                                             Some(super::Downlink::Data(decrypted));
 
                                         // check if FCnt is used up
-                                        let response = if self.session.fcnt_up() == (0xFFFF + 1) {
-                                            // signal that the session is expired
-                                            // client must know to check for potential data
-                                            // (FCnt may be extracted when client checks)
-                                            Ok(Response::SessionExpired)
-                                        } else {
-                                            Ok(Response::DownlinkReceived(fcnt))
-                                        };
+                                        let response =
+                                            if session_expired(&self.shared, &self.session) {
+                                                // signal that the session is expired
+                                                // client must know to check for potential data
+                                                // (FCnt may be extracted when client checks)
+                                                Ok(Response::SessionExpired)
+                                            } else {
+                                                Ok(Response::DownlinkReceived(
+                                                    fcnt,
+                                                    self.session.downlink_pending,
+                                                ))
+                                            };
                                         return (self.into_idle().into(), response);
                                     }
                                 }
@@ -544,13 +750,15 @@ where
                         }
                         _ => (self.into(), Ok(Response::NoUpdate)),
                     },
-                    Err(e) => (self.into(), Err(e.into())),
+                    // the radio state is unknown so the RX windows are given up
+                    Err(e) => (self.into_idle_without_downlink().into(), Err(e.into())),
                 }
             }
             Event::TimeoutFired => {
                 // send the transmit request to the radio
-                if let Err(_e) = self.shared.radio.handle_event(radio::Event::CancelRx) {
-                    panic!("Error cancelling Rx");
+                // the radio state is unknown so the RX windows are given up
+                if let Err(e) = self.shared.radio.handle_event(radio::Event::CancelRx) {
+                    return (self.into_idle_without_downlink().into(), Err(e.into()));
                 }
 
                 match self.rx_window {
@@ -560,36 +768,29 @@ where
                                 - self.shared.region.get_rx_delay(&Frame::Data, &Window::_1);
                         let t2 = t1 + time_between_windows;
                         // TODO: jump to RxWindow2 if t2 == now
-                        (
-                            WaitingForRxWindow {
-                                shared: self.shared,
-                                session: self.session,
-                                confirmed: self.confirmed,
-                                rx_window: RxWindow::_2(t2),
-                            }
-                            .into(),
-                            Ok(Response::TimeoutRequest(t2)),
-                        )
+                        WaitingForRxWindow {
+                            shared: self.shared,
+                            session: self.session,
+                            confirmed: self.confirmed,
+                            rx_window: RxWindow::_2(t2),
+                        }
+                        .into_timeout_request()
                     }
                     // Timeout during second RxWindow leads to giving up
                     RxWindow::_2(_) => {
-                        if !self.confirmed {
-                            // if this was not a confirmed frame, we can still
-                            // increment the FCnt Up
-                            self.session.fcnt_up_increment();
-                        }
-
-                        let response = if self.confirmed {
+                        let confirmed = self.confirmed;
+                        let idle = self.into_idle_without_downlink();
+                        let response = if confirmed {
                             // check if FCnt is used up
                             Ok(Response::NoAck)
-                        } else if self.session.fcnt_up() == (0xFFFF + 1) {
+                        } else if session_expired(&idle.shared, &idle.session) {
                             // signal that the session is expired
                             // client must know to check for potential data
                             Ok(Response::SessionExpired)
                         } else {
                             Ok(Response::ReadyToSend)
                         };
-                        (self.into_idle().into(), response)
+                        (idle.into(), response)
                     }
                 }
             }
@@ -602,42 +803,19 @@ where
         }
     }
 
-    fn into_idle(self) -> Idle<R> {
+    fn into_idle(self) -> Idle<R, G> {
         Idle {
             shared: self.shared,
             session: self.session,
         }
     }
-}
 
-fn data_rxwindow1_timeout<R: radio::PhyRxTx + Timings, C: CryptoFactory + Default>(
-    state: Session<R>,
-    confirmed: bool,
-    timestamp_ms: TimestampMs,
-) -> (Device<R, C>, Result<Response, super::super::Error<R>>) {
-    let (new_state, first_window) = match state {
-        Session::Idle(state) => {
-            let first_window = (state.shared.region.get_rx_delay(&Frame::Data, &Window::_1) as i32
-                + timestamp_ms as i32
-                + state.shared.radio.get_rx_window_offset_ms())
-                as u32;
-            (
-                state.into_waiting_for_rxwindow(confirmed, first_window),
-                first_window,
-            )
+    // the RX windows are over without a downlink
+    fn into_idle_without_downlink(mut self) -> Idle<R, G> {
+        // if this was not a confirmed frame, we can still increment the FCnt Up
+        if !self.confirmed {
+            self.session.fcnt_up_increment();
         }
-        Session::SendingData(state) => {
-            let first_window = (state.shared.region.get_rx_delay(&Frame::Data, &Window::_1) as i32
-                + timestamp_ms as i32
-                + state.shared.radio.get_rx_window_offset_ms())
-                as u32;
-            (
-                state.into_waiting_for_rxwindow(confirmed, first_window),
-                first_window,
-            )
-        }
-        _ => panic!("Invalid state to transition to WaitingForRxWindow"),
-    };
-
-    (new_state.into(), Ok(Response::TimeoutRequest(first_window)))
+        self.into_idle()
+    }
 }
@@ -1,26 +1,57 @@
 use lorawan_encoding::keys::AES128;
 
-pub type AppEui = [u8; 8];
+pub type JoinEui = [u8; 8];
+// AppEUI was renamed to JoinEUI in LoRaWAN 1.1
+pub type AppEui = JoinEui;
 pub type DevEui = [u8; 8];
 
 #[derive(Debug)]
 pub struct Credentials {
     deveui: DevEui,
-    appeui: AppEui,
+    joineui: JoinEui,
     appkey: AES128,
+    // only present for LoRaWAN 1.1 devices
+    nwkkey: Option<AES128>,
+    // LoRaWAN 1.1 devices use a counter instead of random DevNonce, None once all were used
+    devnonce: Option<u16>,
+    // last JoinNonce accepted by a LoRaWAN 1.1 device, JoinAccepts must increase it
+    joinnonce: Option<u32>,
 }
 
 impl Credentials {
     pub fn new(appeui: AppEui, deveui: DevEui, appkey: [u8; 16]) -> Credentials {
         Credentials {
             deveui,
-            appeui,
+            joineui: appeui,
             appkey: appkey.into(),
+            nwkkey: None,
+            devnonce: Some(0),
+            joinnonce: None,
+        }
+    }
+
+    pub fn new_v1_1(
+        joineui: JoinEui,
+        deveui: DevEui,
+        appkey: [u8; 16],
+        nwkkey: [u8; 16],
+    ) -> Credentials {
+        Credentials {
+            deveui,
+            joineui,
+            appkey: appkey.into(),
+            nwkkey: Some(nwkkey.into()),
+            devnonce: Some(0),
+            joinnonce: None,
         }
     }
 
     pub fn appeui(&self) -> &AppEui {
-        &self.appeui
+        &self.joineui
+    }
+
+    pub fn joineui(&self) -> &JoinEui {
+        &self.joineui
     }
 
     pub fn deveui(&self) -> &DevEui {
@@ -30,6 +61,42 @@ impl Credentials {
     pub fn appkey(&self) -> &AES128 {
         &self.appkey
     }
+
+    // the root key for the join procedure, which is the AppKey for LoRaWAN 1.0 devices
+    pub fn nwkkey(&self) -> &AES128 {
+        self.nwkkey.as_ref().unwrap_or(&self.appkey)
+    }
+
+    pub fn is_v1_1(&self) -> bool {
+        self.nwkkey.is_some()
+    }
+
+    // the next DevNonce that a LoRaWAN 1.1 device will use, None once all of them were used; it
+    // must be persisted by the application across resets as the join server rejects reused values
+    pub fn devnonce(&self) -> Option<u16> {
+        self.devnonce
+    }
+
+    pub fn set_devnonce(&mut self, devnonce: Option<u16>) {
+        self.devnonce = devnonce;
+    }
+
+    // the last JoinNonce accepted by a LoRaWAN 1.1 device; it must be persisted along with the
+    // DevNonce so that replayed JoinAccepts are rejected after a reset
+    pub fn joinnonce(&self) -> Option<u32> {
+        self.joinnonce
+    }
+
+    pub fn set_joinnonce(&mut self, joinnonce: Option<u32>) {
+        self.joinnonce = joinnonce;
+    }
+
+    // DevNonces are never reused, so the device cannot join anymore once they run out
+    pub(crate) fn next_devnonce(&mut self) -> Option<u16> {
+        let devnonce = self.devnonce?;
+        self.devnonce = devnonce.checked_add(1);
+        Some(devnonce)
+    }
 }
 
 pub struct SessionKeys {
@@ -0,0 +1,737 @@
+use lorawan_device::async_device::{AsyncRadio, Device, Error, Timer};
+use lorawan_device::radio::{RxConfig, RxQuality, SpreadingFactor, TxConfig};
+use lorawan_device::region::{self, DR};
+use lorawan_device::{no_session, radio, session, Timings};
+use lorawan_encoding::creator::{DataPayloadCreator, JoinAcceptCreator};
+use lorawan_encoding::default_crypto::DefaultFactory;
+use lorawan_encoding::keys::{derive_js_int_key, AES128};
+use lorawan_encoding::maccommandcreator::LinkADRReqCreator;
+use lorawan_encoding::maccommands::SerializableMacCommand;
+use lorawan_encoding::parser::{
+    DataHeader, DecryptedJoinAcceptPayload, DevNonce, EncryptedDataPayload,
+    EncryptedJoinAcceptPayload, FCtrl, FRMPayload, JoinRequestPayload, EUI64, MAX_FCNT_GAP,
+};
+use rand_core::RngCore;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+const APP_KEY: [u8; 16] = [0x11; 16];
+const NWK_KEY: [u8; 16] = [0x22; 16];
+const DEV_EUI: [u8; 8] = [1; 8];
+const JOIN_EUI: [u8; 8] = [2; 8];
+const DEV_ADDR: [u8; 4] = [0x04, 0x03, 0x02, 0x01];
+const EU868_RX2_FREQUENCY: u32 = 869_525_000;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Uplink {
+    start_us: u64,
+    airtime_us: u64,
+    spreading_factor: SpreadingFactor,
+    data: Vec<u8>,
+}
+
+// a radio whose transmissions and receptions are recorded and which receives the queued
+// downlinks, one per reception once the missed ones are over, on a clock shared with the timer
+#[derive(Debug)]
+struct MockRadio {
+    clock_us: Rc<Cell<u64>>,
+    uplinks: Vec<Uplink>,
+    rx_configs: Vec<RxConfig>,
+    downlinks: VecDeque<Vec<u8>>,
+    missed_rx: usize,
+    fail_tx: bool,
+    fail_rx: bool,
+}
+
+impl AsyncRadio for MockRadio {
+    type PhyError = ();
+
+    async fn tx(&mut self, config: TxConfig, buf: &[u8]) -> Result<u64, ()> {
+        if self.fail_tx {
+            return Err(());
+        }
+        let start_us = self.clock_us.get();
+        let airtime_us = config.rf.time_on_air_us(buf.len()) as u64;
+        self.clock_us.set(start_us + airtime_us);
+        self.uplinks.push(Uplink {
+            start_us,
+            airtime_us,
+            spreading_factor: config.rf.spreading_factor,
+            data: buf.to_vec(),
+        });
+        Ok(start_us + airtime_us)
+    }
+
+    async fn rx(
+        &mut self,
+        config: RxConfig,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, RxQuality, u64)>, ()> {
+        self.rx_configs.push(config);
+        if self.fail_rx {
+            return Err(());
+        }
+        if self.missed_rx > 0 {
+            self.missed_rx -= 1;
+            return Ok(None);
+        }
+        Ok(self.downlinks.pop_front().map(|downlink| {
+            buf[..downlink.len()].copy_from_slice(&downlink);
+            (downlink.len(), RxQuality::new(-80, 5), self.clock_us.get())
+        }))
+    }
+}
+
+impl Timings for MockRadio {
+    fn get_rx_window_duration_ms(&self) -> u32 {
+        100
+    }
+}
+
+struct MockTimer {
+    clock_us: Rc<Cell<u64>>,
+}
+
+impl Timer for MockTimer {
+    async fn at(&mut self, time: u32) {
+        let time_us = time as u64 * 1000;
+        if time_us > self.clock_us.get() {
+            self.clock_us.set(time_us);
+        }
+    }
+}
+
+struct XorShift(u32);
+
+impl RngCore for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_u32(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+fn new_radio() -> (MockRadio, MockTimer) {
+    let clock_us = Rc::new(Cell::new(0));
+    let radio = MockRadio {
+        clock_us: clock_us.clone(),
+        uplinks: Vec::new(),
+        rx_configs: Vec::new(),
+        downlinks: VecDeque::new(),
+        missed_rx: 0,
+        fail_tx: false,
+        fail_rx: false,
+    };
+    (radio, MockTimer { clock_us })
+}
+
+fn new_device() -> Device<MockRadio, DefaultFactory, MockTimer, XorShift> {
+    let (radio, timer) = new_radio();
+    Device::new(
+        region::Configuration::new(region::Region::EU868),
+        radio,
+        timer,
+        DEV_EUI,
+        JOIN_EUI,
+        APP_KEY,
+        XorShift(0x1234_5678),
+    )
+}
+
+fn new_device_v1_1() -> Device<MockRadio, DefaultFactory, MockTimer, XorShift> {
+    let (radio, timer) = new_radio();
+    Device::new_v1_1(
+        region::Configuration::new(region::Region::EU868),
+        radio,
+        timer,
+        DEV_EUI,
+        JOIN_EUI,
+        APP_KEY,
+        NWK_KEY,
+        XorShift(0x1234_5678),
+    )
+}
+
+fn join_accept() -> Vec<u8> {
+    let mut phy = JoinAcceptCreator::new();
+    phy.set_app_nonce(&[0x01, 0x02, 0x03])
+        .set_net_id(&[0x01, 0x00, 0x00])
+        .set_dev_addr(&DEV_ADDR)
+        .set_dl_settings(0)
+        .set_rx_delay(1);
+    phy.build(&AES128(APP_KEY)).unwrap().to_vec()
+}
+
+fn join_accept_v1_1(join_nonce: u8, dev_nonce: u16) -> Vec<u8> {
+    let nwk_key = AES128(NWK_KEY);
+    let js_int_key = derive_js_int_key(&nwk_key, &EUI64::new(DEV_EUI).unwrap(), &DefaultFactory);
+    let mut phy = JoinAcceptCreator::new();
+    phy.set_app_nonce(&[join_nonce, 0x00, 0x00])
+        .set_net_id(&[0x01, 0x00, 0x00])
+        .set_dev_addr(&DEV_ADDR)
+        .set_dl_settings(0)
+        .set_opt_neg(true)
+        .set_rx_delay(1);
+    phy.build_v1_1(
+        &nwk_key,
+        &js_int_key,
+        0xff,
+        &EUI64::new(JOIN_EUI).unwrap(),
+        &DevNonce::new(dev_nonce.to_le_bytes()).unwrap(),
+    )
+    .unwrap()
+    .to_vec()
+}
+
+// joins and gives the session keys that the network derives from the DevNonce of the
+// JoinRequest
+fn join(device: &mut Device<MockRadio, DefaultFactory, MockTimer, XorShift>) -> (AES128, AES128) {
+    let join_accept = join_accept();
+    device.get_radio().downlinks.push_back(join_accept.clone());
+    block_on(device.join()).unwrap();
+
+    let key = AES128(APP_KEY);
+    let join_request = JoinRequestPayload::new(&device.get_radio().uplinks[0].data[..]).unwrap();
+    let decrypted = DecryptedJoinAcceptPayload::new(join_accept, &key).unwrap();
+    let nwkskey = decrypted.derive_newskey(&join_request.dev_nonce(), &key);
+    let appskey = decrypted.derive_appskey(&join_request.dev_nonce(), &key);
+    (nwkskey, appskey)
+}
+
+fn data_downlink(keys: &(AES128, AES128), fcnt: u32, confirmed: bool, data: &[u8]) -> Vec<u8> {
+    let mut phy = DataPayloadCreator::new();
+    phy.set_uplink(false)
+        .set_confirmed(confirmed)
+        .set_dev_addr(&DEV_ADDR)
+        .set_fcnt(fcnt)
+        .set_f_port(1);
+    phy.build(data, &[], &keys.0, &keys.1).unwrap().to_vec()
+}
+
+// a downlink with a LinkADRReq enabling the default channels in its FOpts
+fn link_adr_req_downlink(keys: &(AES128, AES128), fcnt: u32) -> Vec<u8> {
+    let mut link_adr_req = LinkADRReqCreator::new();
+    link_adr_req.set_channel_mask([0x07, 0x00]);
+    let mut phy = DataPayloadCreator::new();
+    phy.set_uplink(false).set_dev_addr(&DEV_ADDR).set_fcnt(fcnt);
+    phy.build(&[], &[&link_adr_req], &keys.0, &keys.1)
+        .unwrap()
+        .to_vec()
+}
+
+// FOptsLen of an uplink
+fn f_opts_len(uplink: &Uplink) -> u8 {
+    uplink.data[5] & 0x0f
+}
+
+#[test]
+fn test_join_then_send_with_downlink() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+    assert!(device.get_device().get_session_keys().is_some());
+
+    let downlink = data_downlink(&keys, 0, false, b"pong");
+    device.get_radio().downlinks.push_back(downlink);
+
+    let downlink = block_on(device.send(b"ping", 1, false)).unwrap().unwrap();
+    assert_eq!(downlink.f_port(), Some(1));
+    assert_eq!(downlink.frm_payload().unwrap(), FRMPayload::Data(b"pong"));
+    assert_eq!(device.get_device().get_fcnt_up(), Some(1));
+
+    // without downlink the send completes once the RX windows are over
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    assert_eq!(device.get_radio().uplinks.len(), 3);
+}
+
+#[test]
+fn test_downlink_in_rx2() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+    device.get_radio().rx_configs.clear();
+
+    device.get_radio().missed_rx = 1;
+    let downlink = data_downlink(&keys, 0, false, b"pong");
+    device.get_radio().downlinks.push_back(downlink);
+
+    let downlink = block_on(device.send(b"ping", 1, false)).unwrap().unwrap();
+    assert_eq!(downlink.frm_payload().unwrap(), FRMPayload::Data(b"pong"));
+    let rx_configs = &device.get_radio().rx_configs;
+    assert_eq!(rx_configs.len(), 2);
+    assert_eq!(rx_configs[1].rf.frequency, EU868_RX2_FREQUENCY);
+}
+
+#[test]
+fn test_receives_again_after_foreign_packet() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+    device.get_radio().rx_configs.clear();
+
+    // a packet that is not for the device, then the downlink within the same window
+    device.get_radio().downlinks.push_back(Vec::new());
+    let downlink = data_downlink(&keys, 0, false, b"pong");
+    device.get_radio().downlinks.push_back(downlink);
+
+    let downlink = block_on(device.send(b"ping", 1, false)).unwrap().unwrap();
+    assert_eq!(downlink.frm_payload().unwrap(), FRMPayload::Data(b"pong"));
+    let rx_configs = &device.get_radio().rx_configs;
+    assert_eq!(rx_configs.len(), 2);
+    assert_eq!(rx_configs[0].rf.frequency, rx_configs[1].rf.frequency);
+    assert_ne!(rx_configs[1].rf.frequency, EU868_RX2_FREQUENCY);
+}
+
+#[test]
+fn test_no_ack() {
+    let mut device = new_device();
+    join(&mut device);
+    device.get_radio().rx_configs.clear();
+
+    assert!(matches!(
+        block_on(device.send(b"ping", 1, true)),
+        Err(Error::NoAck)
+    ));
+    // both RX windows were opened
+    assert_eq!(device.get_radio().rx_configs.len(), 2);
+}
+
+#[test]
+fn test_ack_pending_until_sent() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+
+    let downlink = data_downlink(&keys, 0, true, b"pong");
+    device.get_radio().downlinks.push_back(downlink);
+    block_on(device.send(b"ping", 1, false)).unwrap().unwrap();
+    assert!(device.get_device().ack_pending());
+
+    // the ACK bit is set in the FCtrl of the next uplink only
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    assert!(!device.get_device().ack_pending());
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    let uplinks = &device.get_radio().uplinks;
+    assert_eq!(uplinks[2].data[5] & 0x20, 0x20);
+    assert_eq!(uplinks[3].data[5] & 0x20, 0);
+}
+
+#[test]
+fn test_downlink_pending() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+
+    let mut phy = DataPayloadCreator::new();
+    phy.set_uplink(false)
+        .set_fctrl(&FCtrl::new(0x10, false))
+        .set_dev_addr(&DEV_ADDR)
+        .set_fcnt(0)
+        .set_f_port(1);
+    let downlink = phy.build(b"pong", &[], &keys.0, &keys.1).unwrap().to_vec();
+    device.get_radio().downlinks.push_back(downlink);
+    let downlink = block_on(device.send(b"ping", 1, false)).unwrap().unwrap();
+    assert_eq!(downlink.frm_payload().unwrap(), FRMPayload::Data(b"pong"));
+    assert!(device.get_device().downlink_pending());
+
+    assert!(block_on(device.send(&[], 1, false)).unwrap().is_none());
+    assert!(!device.get_device().downlink_pending());
+}
+
+#[test]
+fn test_join_retries_within_duty_cycle() {
+    let mut device = new_device();
+    device.get_device().set_datarate(DR::_5);
+    device.get_device().set_max_join_attempts(Some(3));
+
+    assert!(matches!(block_on(device.join()), Err(Error::NoJoinAccept)));
+
+    let uplinks = &device.get_radio().uplinks;
+    assert_eq!(uplinks.len(), 3);
+    // 1% duty cycle during the first hour
+    for (previous, next) in uplinks.iter().zip(&uplinks[1..]) {
+        assert!(next.start_us >= previous.start_us + 100 * previous.airtime_us);
+    }
+    // two JoinRequests per datarate before stepping down
+    assert!(matches!(uplinks[0].spreading_factor, SpreadingFactor::_7));
+    assert!(matches!(uplinks[1].spreading_factor, SpreadingFactor::_7));
+    assert!(matches!(uplinks[2].spreading_factor, SpreadingFactor::_8));
+    // the datarate is restored once the join procedure is given up
+    assert!(matches!(device.get_device().get_datarate(), DR::_5));
+}
+
+#[test]
+fn test_join_gives_up_by_default() {
+    let mut device = new_device();
+
+    assert!(matches!(block_on(device.join()), Err(Error::NoJoinAccept)));
+    assert_eq!(device.get_radio().uplinks.len(), 16);
+}
+
+#[test]
+fn test_us915_join_keeps_datarate() {
+    let (radio, timer) = new_radio();
+    let mut device: Device<MockRadio, DefaultFactory, MockTimer, XorShift> = Device::new(
+        region::Configuration::new(region::Region::US915),
+        radio,
+        timer,
+        DEV_EUI,
+        JOIN_EUI,
+        APP_KEY,
+        XorShift(0x1234_5678),
+    );
+    device.get_device().set_datarate(DR::_3);
+    // the JoinAccept answers the third JoinRequest
+    device.get_radio().missed_rx = 4;
+    device.get_radio().downlinks.push_back(join_accept());
+
+    block_on(device.join()).unwrap();
+
+    // the sweep alternates between DR0 and DR4 without stepping down the session datarate
+    let uplinks = &device.get_radio().uplinks;
+    assert_eq!(uplinks.len(), 3);
+    assert!(matches!(uplinks[0].spreading_factor, SpreadingFactor::_10));
+    assert!(matches!(uplinks[1].spreading_factor, SpreadingFactor::_8));
+    assert!(matches!(uplinks[2].spreading_factor, SpreadingFactor::_10));
+    assert!(matches!(device.get_device().get_datarate(), DR::_3));
+}
+
+#[test]
+fn test_join_v1_1_rejects_replayed_join_nonce() {
+    let mut device = new_device_v1_1();
+    // the nonces persisted from a previous join
+    device.get_device().set_devnonce(Some(10));
+    device.get_device().set_joinnonce(Some(5));
+    // a replayed JoinAccept in RX1 and a fresh one in RX2
+    device
+        .get_radio()
+        .downlinks
+        .push_back(join_accept_v1_1(5, 10));
+    device
+        .get_radio()
+        .downlinks
+        .push_back(join_accept_v1_1(6, 10));
+
+    block_on(device.join()).unwrap();
+
+    let join_request = JoinRequestPayload::new(&device.get_radio().uplinks[0].data[..]).unwrap();
+    assert_eq!(join_request.dev_nonce().as_ref(), &[10, 0]);
+    assert_eq!(device.get_radio().uplinks.len(), 1);
+    assert_eq!(device.get_device().get_devnonce(), Some(11));
+    assert_eq!(device.get_device().get_joinnonce(), Some(6));
+}
+
+#[test]
+fn test_join_v1_1_devnonce_exhausted() {
+    let mut device = new_device_v1_1();
+    device.get_device().set_devnonce(Some(u16::MAX));
+
+    // the last DevNonce is used, the retry then has none left
+    assert!(matches!(
+        block_on(device.join()),
+        Err(Error::Device(lorawan_device::Error::NoSession(
+            no_session::Error::DevNonceExhausted
+        )))
+    ));
+    assert_eq!(device.get_radio().uplinks.len(), 1);
+    let join_request = JoinRequestPayload::new(&device.get_radio().uplinks[0].data[..]).unwrap();
+    assert_eq!(join_request.dev_nonce().as_ref(), &[0xff, 0xff]);
+    assert_eq!(device.get_device().get_devnonce(), None);
+
+    // no JoinRequest is sent anymore
+    assert!(block_on(device.join()).is_err());
+    assert_eq!(device.get_radio().uplinks.len(), 1);
+}
+
+#[test]
+fn test_join_v1_1_session_expires_without_rekey_conf() {
+    let mut device = new_device_v1_1();
+    device
+        .get_radio()
+        .downlinks
+        .push_back(join_accept_v1_1(1, 0));
+    block_on(device.join()).unwrap();
+
+    // RekeyInd is repeated with ADR_ACK_LIMIT uplinks before the session is given up
+    for _ in 0..63 {
+        assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    }
+    assert!(matches!(
+        block_on(device.send(b"ping", 1, false)),
+        Err(Error::SessionExpired)
+    ));
+}
+
+fn is_phy_error(result: Result<impl std::fmt::Debug, Error<MockRadio>>) -> bool {
+    matches!(
+        result,
+        Err(Error::Device(lorawan_device::Error::Radio(
+            radio::Error::PhyError(())
+        )))
+    )
+}
+
+#[test]
+fn test_join_after_tx_error() {
+    let mut device = new_device();
+    device.get_radio().fail_tx = true;
+    assert!(is_phy_error(block_on(device.join())));
+
+    device.get_radio().fail_tx = false;
+    join(&mut device);
+    assert!(device.get_device().ready_to_send_data());
+}
+
+#[test]
+fn test_send_after_tx_error() {
+    let mut device = new_device();
+    join(&mut device);
+
+    device.get_radio().fail_tx = true;
+    assert!(is_phy_error(block_on(device.send(b"ping", 1, false))));
+    // the FCnt of an unconfirmed uplink which may have been sent is not reused
+    assert_eq!(device.get_device().get_fcnt_up(), Some(1));
+
+    device.get_radio().fail_tx = false;
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    assert_eq!(device.get_device().get_fcnt_up(), Some(2));
+}
+
+#[test]
+fn test_send_after_rx_error() {
+    let mut device = new_device();
+    join(&mut device);
+    device.get_radio().rx_configs.clear();
+
+    device.get_radio().fail_rx = true;
+    assert!(is_phy_error(block_on(device.send(b"ping", 1, false))));
+    // the RX windows are given up at the first error
+    assert_eq!(device.get_radio().rx_configs.len(), 1);
+
+    device.get_radio().fail_rx = false;
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    assert_eq!(device.get_device().get_fcnt_up(), Some(2));
+}
+
+#[test]
+fn test_join_after_region_error() {
+    let mut device = new_device();
+    // EU868 has no channel for DR7
+    device.get_device().set_datarate(DR::_7);
+    assert!(matches!(
+        block_on(device.join()),
+        Err(Error::Device(lorawan_device::Error::Region(_)))
+    ));
+    assert!(device.get_radio().uplinks.is_empty());
+
+    device.get_device().set_datarate(DR::_5);
+    join(&mut device);
+    assert!(device.get_device().ready_to_send_data());
+}
+
+#[test]
+fn test_send_after_region_error() {
+    let mut device = new_device();
+    join(&mut device);
+
+    device.get_device().set_datarate(DR::_7);
+    assert!(matches!(
+        block_on(device.send(b"ping", 1, false)),
+        Err(Error::Device(lorawan_device::Error::Region(_)))
+    ));
+    assert_eq!(device.get_radio().uplinks.len(), 1);
+    assert_eq!(device.get_device().get_fcnt_up(), Some(0));
+
+    device.get_device().set_datarate(DR::_5);
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    assert_eq!(device.get_device().get_fcnt_up(), Some(1));
+}
+
+#[test]
+fn test_max_payload_len() {
+    let mut device = new_device();
+    device.get_device().set_datarate(DR::_0);
+    assert_eq!(device.get_device().max_payload_len().unwrap(), 51);
+    device.get_device().set_datarate(DR::_5);
+    assert_eq!(device.get_device().max_payload_len().unwrap(), 222);
+    device.get_device().set_datarate(DR::_7);
+    assert!(device.get_device().max_payload_len().is_err());
+}
+
+#[test]
+fn test_payload_too_long() {
+    let mut device = new_device();
+    join(&mut device);
+    device.get_device().set_datarate(DR::_0);
+
+    assert!(matches!(
+        block_on(device.send(&[0; 52], 1, false)),
+        Err(Error::Device(lorawan_device::Error::Session(
+            session::Error::PayloadTooLong
+        )))
+    ));
+    assert_eq!(device.get_radio().uplinks.len(), 1);
+
+    assert!(block_on(device.send(&[0; 51], 1, false)).unwrap().is_none());
+    // MHDR, FHDR, FPort, FRMPayload and MIC
+    assert_eq!(device.get_radio().uplinks[1].data.len(), 1 + 7 + 1 + 51 + 4);
+}
+
+#[test]
+fn test_mac_commands_deferred_until_they_fit() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+    device.get_device().set_datarate(DR::_0);
+
+    let downlink = link_adr_req_downlink(&keys, 0);
+    device.get_radio().downlinks.push_back(downlink);
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_some());
+
+    // the LinkADRAns does not fit next to the largest payload
+    block_on(device.send(&[0; 51], 1, false)).unwrap();
+    assert_eq!(f_opts_len(&device.get_radio().uplinks[2]), 0);
+    block_on(device.send(b"ping", 1, false)).unwrap();
+    assert_eq!(f_opts_len(&device.get_radio().uplinks[3]), 2);
+    // and is sent only once
+    block_on(device.send(b"ping", 1, false)).unwrap();
+    assert_eq!(f_opts_len(&device.get_radio().uplinks[4]), 0);
+}
+
+#[test]
+fn test_mac_commands_on_f_port_0() {
+    let mut device = new_device_v1_1();
+    let join_accept = join_accept_v1_1(1, 0);
+    device.get_radio().downlinks.push_back(join_accept.clone());
+    block_on(device.join()).unwrap();
+
+    let nwk_key = AES128(NWK_KEY);
+    let join_eui = EUI64::new(JOIN_EUI).unwrap();
+    let dev_nonce = DevNonce::new([0, 0]).unwrap();
+    // the MIC of a LoRaWAN 1.1 JoinAccept is computed with the JSIntKey
+    let decrypted = EncryptedJoinAcceptPayload::new(join_accept)
+        .unwrap()
+        .decrypt(&nwk_key);
+    let snwksintkey = decrypted.derive_snwksintkey(&join_eui, &dev_nonce, &nwk_key);
+    let nwksenckey = decrypted.derive_nwksenckey(&join_eui, &dev_nonce, &nwk_key);
+    let appskey = decrypted.derive_appskey_v1_1(&join_eui, &dev_nonce, &AES128(APP_KEY));
+
+    // LinkADRReqs too long for the FOpts of the downlink
+    let mut link_adr_req = LinkADRReqCreator::new();
+    link_adr_req.set_channel_mask([0x07, 0x00]);
+    let cmds: Vec<&dyn SerializableMacCommand> = vec![&link_adr_req; 7];
+    let mut phy = DataPayloadCreator::new();
+    phy.set_uplink(false)
+        .set_dev_addr(&DEV_ADDR)
+        .set_fcnt(0)
+        .set_f_port(0);
+    let downlink = phy
+        .build_v1_1(
+            &[],
+            &cmds,
+            &snwksintkey,
+            &snwksintkey,
+            &nwksenckey,
+            &appskey,
+        )
+        .unwrap()
+        .to_vec();
+    device.get_radio().downlinks.push_back(downlink);
+    let downlink = block_on(device.send(b"ping", 1, false)).unwrap().unwrap();
+    assert_eq!(downlink.f_port(), Some(0));
+
+    // the LinkADRAns and the RekeyInd are too long for the FOpts of an uplink so they are only
+    // sent on FPort 0, by an uplink without application payload
+    block_on(device.send(b"ping", 1, false)).unwrap();
+    assert_eq!(f_opts_len(&device.get_radio().uplinks[2]), 0);
+    block_on(device.send(&[], 1, false)).unwrap();
+    let uplink = device.get_radio().uplinks[3].data.clone();
+    assert_eq!(f_opts_len(&device.get_radio().uplinks[3]), 0);
+    let uplink = EncryptedDataPayload::new(uplink).unwrap();
+    assert_eq!(uplink.f_port(), Some(0));
+    let uplink = uplink
+        .decrypt_v1_1(Some(&nwksenckey), Some(&appskey), 2)
+        .unwrap();
+    match uplink.frm_payload().unwrap() {
+        FRMPayload::MACCommands(cmds) => assert_eq!(cmds.mac_commands().count(), 8),
+        _ => panic!("the MAC commands are not on FPort 0"),
+    };
+}
+
+#[test]
+fn test_fcnt_down_rolls_over_and_rejects_replays() {
+    let mut device = new_device();
+    let keys = join(&mut device);
+    let mut send_with_downlink = |fcnt: u32| {
+        let downlink = data_downlink(&keys, fcnt, false, b"pong");
+        device.get_radio().downlinks.push_back(downlink);
+        block_on(device.send(b"ping", 1, false)).unwrap().is_some()
+    };
+
+    // a frame more than MAX_FCNT_GAP ahead is rejected
+    assert!(!send_with_downlink(MAX_FCNT_GAP + 1));
+    // the FCntDown only goes up to the 16 bits of the FHDR by steps within MAX_FCNT_GAP
+    for fcnt in [16_000, 32_000, 48_000, 64_000, 0xffff] {
+        assert!(send_with_downlink(fcnt));
+    }
+    // the 16 bits of the FHDR roll over while the 32-bit FCntDown of the MIC goes on
+    assert!(send_with_downlink(0x1_0000));
+    assert!(send_with_downlink(0x1_0001));
+
+    // replayed frames are rejected, whether their 16 bits are above the last FCntDown or not
+    assert!(!send_with_downlink(0x1_0001));
+    assert!(!send_with_downlink(0xffff));
+    assert!(!send_with_downlink(0x1_0000));
+    assert!(send_with_downlink(0x1_0002));
+}
+
+#[test]
+fn test_session_expires_at_rejoin_threshold() {
+    let mut device = new_device();
+    join(&mut device);
+    device.get_device().set_rejoin_threshold(Some(2));
+
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+    assert!(matches!(
+        block_on(device.send(b"ping", 1, false)),
+        Err(Error::SessionExpired)
+    ));
+    assert_eq!(device.get_device().get_fcnt_up(), Some(2));
+
+    // a new session starts over from FCntUp 0
+    device.get_radio().downlinks.push_back(join_accept());
+    block_on(device.join()).unwrap();
+    assert_eq!(device.get_device().get_fcnt_up(), Some(0));
+    assert!(block_on(device.send(b"ping", 1, false)).unwrap().is_none());
+}
@@ -0,0 +1,343 @@
+use lorawan_device::radio::{self, PhyRxTx, PhyRxTxBuf, RxQuality};
+use lorawan_device::region;
+use lorawan_device::{Device, Error, Event, Response, Timings};
+use lorawan_encoding::creator::{DataPayloadCreator, JoinAcceptCreator};
+use lorawan_encoding::default_crypto::DefaultFactory;
+use lorawan_encoding::keys::AES128;
+use lorawan_encoding::parser::{DecryptedJoinAcceptPayload, FCtrl, JoinRequestPayload};
+use rand_core::RngCore;
+
+const APP_KEY: [u8; 16] = [0x11; 16];
+const DEV_EUI: [u8; 8] = [1; 8];
+const APP_EUI: [u8; 8] = [2; 8];
+const DEV_ADDR: [u8; 4] = [0x04, 0x03, 0x02, 0x01];
+
+// a std Vec rather than a heapless one, whose clear indexes past the end of the slice in 0.6
+#[derive(Debug, Default)]
+struct Buffer(Vec<u8>);
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for Buffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl PhyRxTxBuf for Buffer {
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    fn extend(&mut self, buf: &[u8]) {
+        self.0.extend_from_slice(buf);
+    }
+}
+
+// a synchronous radio whose RX requests and cancellations can be made to fail, as well as the
+// completion of an asynchronous TX or of an RX
+#[derive(Debug, Default)]
+struct MockRadio {
+    uplinks: Vec<Vec<u8>>,
+    received: Buffer,
+    fail_rx_request: bool,
+    fail_cancel_rx: bool,
+    async_tx: bool,
+    fail_phy_event: bool,
+}
+
+impl PhyRxTx for MockRadio {
+    type PhyBuf = Buffer;
+    type PhyEvent = ();
+    type PhyError = ();
+    type PhyResponse = ();
+
+    fn get_mut_radio(&mut self) -> &mut Self {
+        self
+    }
+
+    fn get_received_packet(&mut self) -> &mut Buffer {
+        &mut self.received
+    }
+
+    fn handle_event(
+        &mut self,
+        event: radio::Event<Self>,
+    ) -> Result<radio::Response<Self>, radio::Error<Self>> {
+        match event {
+            radio::Event::TxRequest(_, buf) => {
+                self.uplinks.push(buf.0.clone());
+                if self.async_tx {
+                    Ok(radio::Response::Txing)
+                } else {
+                    Ok(radio::Response::TxDone(0))
+                }
+            }
+            radio::Event::RxRequest(_) if self.fail_rx_request => Err(radio::Error::PhyError(())),
+            radio::Event::RxRequest(_) => Ok(radio::Response::Rxing),
+            radio::Event::CancelRx if self.fail_cancel_rx => Err(radio::Error::PhyError(())),
+            radio::Event::CancelRx => Ok(radio::Response::Idle),
+            radio::Event::PhyEvent(()) if self.fail_phy_event => Err(radio::Error::PhyError(())),
+            radio::Event::PhyEvent(()) if self.async_tx => {
+                self.async_tx = false;
+                Ok(radio::Response::TxDone(0))
+            }
+            radio::Event::PhyEvent(()) => Ok(radio::Response::RxDone(RxQuality::new(-80, 5), 0)),
+        }
+    }
+}
+
+impl Timings for MockRadio {
+    fn get_rx_window_duration_ms(&self) -> u32 {
+        100
+    }
+}
+
+struct XorShift(u32);
+
+impl RngCore for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_u32(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+type MockDevice = Device<MockRadio, DefaultFactory, XorShift>;
+
+fn new_device() -> MockDevice {
+    Device::new(
+        region::Configuration::new(region::Region::EU868),
+        MockRadio::default(),
+        DEV_EUI,
+        APP_EUI,
+        APP_KEY,
+        XorShift(0x1234_5678),
+    )
+}
+
+fn handle_event(device: MockDevice, event: Event<MockRadio>) -> (MockDevice, Response) {
+    let (device, response) = device.handle_event(event);
+    (device, response.unwrap())
+}
+
+fn join_accept() -> Vec<u8> {
+    let mut phy = JoinAcceptCreator::new();
+    phy.set_app_nonce(&[0x01, 0x02, 0x03])
+        .set_net_id(&[0x01, 0x00, 0x00])
+        .set_dev_addr(&DEV_ADDR)
+        .set_dl_settings(0)
+        .set_rx_delay(1);
+    phy.build(&AES128(APP_KEY)).unwrap().to_vec()
+}
+
+// joins with a JoinAccept received in RX1
+fn join(device: MockDevice) -> MockDevice {
+    let (device, response) = handle_event(device, Event::NewSessionRequest);
+    assert!(matches!(response, Response::TimeoutRequest(_)));
+    let (mut device, response) = handle_event(device, Event::TimeoutFired);
+    assert!(matches!(response, Response::TimeoutRequest(_)));
+
+    device.get_radio().received = Buffer(join_accept());
+
+    let event = Event::RadioEvent(radio::Event::PhyEvent(()));
+    let (device, response) = handle_event(device, event);
+    assert!(matches!(response, Response::JoinSuccess));
+    device
+}
+
+// the NwkSKey and AppSKey of the session, derived from the DevNonce of the JoinRequest
+fn session_keys(device: &mut MockDevice, join_accept: Vec<u8>) -> (AES128, AES128) {
+    let key = AES128(APP_KEY);
+    let join_request = JoinRequestPayload::new(&device.get_radio().uplinks[0][..]).unwrap();
+    let decrypted = DecryptedJoinAcceptPayload::new(join_accept, &key).unwrap();
+    let nwkskey = decrypted.derive_newskey(&join_request.dev_nonce(), &key);
+    let appskey = decrypted.derive_appskey(&join_request.dev_nonce(), &key);
+    (nwkskey, appskey)
+}
+
+fn fcnt(uplink: &[u8]) -> u16 {
+    u16::from_le_bytes([uplink[6], uplink[7]])
+}
+
+#[test]
+fn test_rx_request_failure_gives_up_the_join_attempt() {
+    let mut device = new_device();
+    device.get_radio().fail_rx_request = true;
+
+    let (device, response) = handle_event(device, Event::NewSessionRequest);
+    assert!(matches!(response, Response::TimeoutRequest(_)));
+    let (mut device, response) = device.handle_event(Event::TimeoutFired);
+    assert!(matches!(
+        response,
+        Err(Error::Radio(radio::Error::PhyError(())))
+    ));
+
+    // the device is idle again and can join
+    device.get_radio().fail_rx_request = false;
+    let device = join(device);
+    assert!(device.ready_to_send_data());
+}
+
+#[test]
+fn test_rx_request_failure_gives_up_the_uplink() {
+    let mut device = join(new_device());
+    device.get_radio().fail_rx_request = true;
+
+    let (device, response) = device.send(b"ping", 1, false);
+    assert!(matches!(response, Ok(Response::TimeoutRequest(_))));
+    let (mut device, response) = device.handle_event(Event::TimeoutFired);
+    assert!(matches!(
+        response,
+        Err(Error::Radio(radio::Error::PhyError(())))
+    ));
+    assert!(device.ready_to_send_data());
+    assert_eq!(device.get_fcnt_up(), Some(1));
+
+    // the next uplink does not reuse the FCnt
+    device.get_radio().fail_rx_request = false;
+    let (mut device, response) = device.send(b"ping", 1, false);
+    assert!(matches!(response, Ok(Response::TimeoutRequest(_))));
+    let uplinks = &device.get_radio().uplinks;
+    assert_eq!(fcnt(&uplinks[1]), 0);
+    assert_eq!(fcnt(&uplinks[2]), 1);
+}
+
+#[test]
+fn test_cancel_rx_failure_gives_up_the_uplink() {
+    let mut device = join(new_device());
+    device.get_radio().fail_cancel_rx = true;
+
+    let (device, response) = device.send(b"ping", 1, false);
+    assert!(matches!(response, Ok(Response::TimeoutRequest(_))));
+    let (device, response) = handle_event(device, Event::TimeoutFired);
+    assert!(matches!(response, Response::TimeoutRequest(_)));
+    // RX1 closes without downlink
+    let (mut device, response) = device.handle_event(Event::TimeoutFired);
+    assert!(matches!(
+        response,
+        Err(Error::Radio(radio::Error::PhyError(())))
+    ));
+    assert!(device.ready_to_send_data());
+    assert_eq!(device.get_fcnt_up(), Some(1));
+
+    device.get_radio().fail_cancel_rx = false;
+    let (mut device, _) = device.send(b"ping", 1, false);
+    let uplinks = &device.get_radio().uplinks;
+    assert_eq!(fcnt(&uplinks[2]), 1);
+}
+
+#[test]
+fn test_cancel_rx_failure_keeps_the_fcnt_of_a_confirmed_uplink() {
+    let mut device = join(new_device());
+    device.get_radio().fail_cancel_rx = true;
+
+    let (device, _) = device.send(b"ping", 1, true);
+    let (device, _) = handle_event(device, Event::TimeoutFired);
+    let (device, response) = device.handle_event(Event::TimeoutFired);
+    assert!(response.is_err());
+    assert!(device.ready_to_send_data());
+    // the confirmed uplink is to be retransmitted with the same FCnt
+    assert_eq!(device.get_fcnt_up(), Some(0));
+}
+
+#[test]
+fn test_tx_failure_gives_up_the_join_attempt() {
+    let mut device = new_device();
+    device.get_radio().async_tx = true;
+    device.get_radio().fail_phy_event = true;
+
+    let (device, response) = handle_event(device, Event::NewSessionRequest);
+    assert!(matches!(response, Response::JoinRequestSending));
+    let event = Event::RadioEvent(radio::Event::PhyEvent(()));
+    let (mut device, response) = device.handle_event(event);
+    assert!(matches!(
+        response,
+        Err(Error::Radio(radio::Error::PhyError(())))
+    ));
+
+    device.get_radio().async_tx = false;
+    device.get_radio().fail_phy_event = false;
+    let device = join(device);
+    assert!(device.ready_to_send_data());
+}
+
+#[test]
+fn test_tx_failure_gives_up_the_uplink() {
+    let mut device = join(new_device());
+    device.get_radio().async_tx = true;
+    device.get_radio().fail_phy_event = true;
+
+    let (device, response) = device.send(b"ping", 1, false);
+    assert!(matches!(response, Ok(Response::UplinkSending(0))));
+    let event = Event::RadioEvent(radio::Event::PhyEvent(()));
+    let (mut device, response) = device.handle_event(event);
+    assert!(matches!(
+        response,
+        Err(Error::Radio(radio::Error::PhyError(())))
+    ));
+    assert!(device.ready_to_send_data());
+    // the uplink may have been transmitted
+    assert_eq!(device.get_fcnt_up(), Some(1));
+
+    device.get_radio().async_tx = false;
+    device.get_radio().fail_phy_event = false;
+    let (device, response) = device.send(b"ping", 1, false);
+    assert!(matches!(response, Ok(Response::TimeoutRequest(_))));
+    assert_eq!(device.get_fcnt_up(), Some(1));
+}
+
+#[test]
+fn test_rx_failure_gives_up_the_uplink() {
+    let device = join(new_device());
+
+    let (device, _) = device.send(b"ping", 1, false);
+    let (mut device, response) = handle_event(device, Event::TimeoutFired);
+    assert!(matches!(response, Response::TimeoutRequest(_)));
+    device.get_radio().fail_phy_event = true;
+    let event = Event::RadioEvent(radio::Event::PhyEvent(()));
+    let (device, response) = device.handle_event(event);
+    assert!(matches!(
+        response,
+        Err(Error::Radio(radio::Error::PhyError(())))
+    ));
+    assert!(device.ready_to_send_data());
+    assert_eq!(device.get_fcnt_up(), Some(1));
+}
+
+#[test]
+fn test_downlink_received_with_f_pending() {
+    let mut device = join(new_device());
+    let keys = session_keys(&mut device, join_accept());
+
+    let (device, _) = device.send(b"ping", 1, false);
+    let (mut device, _) = handle_event(device, Event::TimeoutFired);
+    let mut phy = DataPayloadCreator::new();
+    phy.set_uplink(false)
+        .set_fctrl(&FCtrl::new(0x10, false))
+        .set_dev_addr(&DEV_ADDR)
+        .set_fcnt(0)
+        .set_f_port(1);
+    let downlink = phy.build(b"pong", &[], &keys.0, &keys.1).unwrap().to_vec();
+    device.get_radio().received = Buffer(downlink);
+
+    let event = Event::RadioEvent(radio::Event::PhyEvent(()));
+    let (device, response) = handle_event(device, event);
+    assert!(matches!(response, Response::DownlinkReceived(0, true)));
+    assert!(device.downlink_pending());
+}
@@ -10,12 +10,13 @@
 //!
 //! See [JoinAcceptCreator.new](struct.JoinAcceptCreator.html#method.new) for an example.
 
+use super::error::Error;
 use super::keys;
 use super::keys::CryptoFactory;
 use super::maccommandcreator;
-use super::maccommands::{mac_commands_len, SerializableMacCommand};
 #[cfg(feature = "with-downlink")]
-use super::maccommands::{DLSettings, Frequency};
+use super::maccommands::DLSettings;
+use super::maccommands::{mac_commands_len, SerializableMacCommand};
 use super::parser;
 use super::securityhelpers;
 
@@ -49,10 +50,10 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> JoinAcceptCreator<D, F> {
     /// Creates a well initialized JoinAcceptCreator with specific data and crypto functions.
     ///
     /// TODO: Add more detials & and example
-    pub fn with_options<'a>(mut data: D, factory: F) -> Result<Self, &'a str> {
+    pub fn with_options(mut data: D, factory: F) -> Result<Self, Error> {
         let d = data.as_mut();
         if d.len() < 33 {
-            return Err("data slice is too short");
+            return Err(Error::BufferTooSmall);
         }
         d[0] = 0x20;
         Ok(Self {
@@ -124,6 +125,25 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> JoinAcceptCreator<D, F> {
         self
     }
 
+    /// Sets the OptNeg bit of the DLSettings of the JoinAccept.
+    ///
+    /// The bit signals LoRaWAN 1.1 devices that the network server implements LoRaWAN 1.1 and
+    /// that the JoinAccept is to be built with [build_v1_1](#method.build_v1_1).
+    ///
+    /// # Argument
+    ///
+    /// * opt_neg - whether the network server implements LoRaWAN 1.1 or later.
+    pub fn set_opt_neg(&mut self, opt_neg: bool) -> &mut Self {
+        let d = self.data.as_mut();
+        if opt_neg {
+            d[11] |= 0x80;
+        } else {
+            d[11] &= 0x7f;
+        }
+
+        self
+    }
+
     /// Sets the RX delay of the JoinAccept to the provided value.
     ///
     /// # Argument
@@ -137,26 +157,33 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> JoinAcceptCreator<D, F> {
 
     /// Sets the CFList of the JoinAccept to the provided value.
     ///
+    /// The CFListType is set according to the variant of the CFList.
+    ///
     /// # Argument
     ///
-    /// * ch_list - list of Frequences to be sent to the device.
-    pub fn set_c_f_list<'a, C: AsRef<[Frequency<'a>]>>(
-        &mut self,
-        list: C,
-    ) -> Result<&mut Self, &str> {
-        let ch_list = list.as_ref();
-        if ch_list.len() > 5 {
-            return Err("too many frequences");
-        }
+    /// * c_f_list - the CFList to be sent to the device.
+    pub fn set_c_f_list(&mut self, c_f_list: parser::CfList) -> &mut Self {
         let d = self.data.as_mut();
-        ch_list.iter().enumerate().for_each(|(i, fr)| {
-            let v = fr.value() / 100;
-            d[13 + i * 3] = (v & 0xff) as u8;
-            d[14 + i * 3] = ((v >> 8) & 0xff) as u8;
-            d[15 + i * 3] = ((v >> 16) & 0xff) as u8;
-        });
+        d[13..29].iter_mut().for_each(|v| *v = 0);
+        match c_f_list {
+            parser::CfList::DynamicChannel(ref ch_list) => {
+                ch_list.iter().enumerate().for_each(|(i, fr)| {
+                    let v = fr.value() / 100;
+                    d[13 + i * 3] = (v & 0xff) as u8;
+                    d[14 + i * 3] = ((v >> 8) & 0xff) as u8;
+                    d[15 + i * 3] = ((v >> 16) & 0xff) as u8;
+                });
+            }
+            parser::CfList::FixedChannel(ref masks) => {
+                masks.iter().enumerate().for_each(|(i, mask)| {
+                    d[13 + i * 2..15 + i * 2].copy_from_slice(mask.as_ref());
+                });
+            }
+        }
+        d[28] = c_f_list.cf_list_type();
+        self.with_c_f_list = true;
 
-        Ok(self)
+        self
     }
 
     /// Provides the binary representation of the encrypted join accept
@@ -165,20 +192,66 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> JoinAcceptCreator<D, F> {
     /// # Argument
     ///
     /// * key - the key to be used for encryption and setting the MIC.
-    pub fn build(&mut self, key: &keys::AES128) -> Result<&[u8], &str> {
+    pub fn build(&mut self, key: &keys::AES128) -> Result<&[u8], Error> {
         if !self.encrypted {
+            let len = self.payload_len();
+            set_mic(&mut self.data.as_mut()[..len], key, &self.factory);
             self.encrypt_payload(key);
         }
-        Ok(&self.data.as_mut()[..])
+        let len = self.payload_len();
+        Ok(&self.data.as_mut()[..len])
     }
 
-    fn encrypt_payload(&mut self, key: &keys::AES128) {
-        let d = if self.with_c_f_list {
-            self.data.as_mut()
+    /// Provides the binary representation of the encrypted LoRaWAN 1.1 join accept
+    /// physical payload with the MIC set.
+    ///
+    /// The JoinAccept is expected to have the OptNeg bit set (see
+    /// [set_opt_neg](#method.set_opt_neg)).
+    ///
+    /// # Argument
+    ///
+    /// * key - the key to be used for encryption, i.e. NwkKey when answering a JoinRequest
+    ///   and JSEncKey when answering a RejoinRequest.
+    /// * js_int_key - the JSIntKey of the device used for setting the MIC.
+    /// * join_req_type - 0xff for JoinRequest, or the type of the RejoinRequest.
+    /// * join_eui - the JoinEUI of the device.
+    /// * dev_nonce - the DevNonce of the JoinRequest (or the RJcount of the RejoinRequest).
+    pub fn build_v1_1<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &mut self,
+        key: &keys::AES128,
+        js_int_key: &keys::AES128,
+        join_req_type: u8,
+        join_eui: &parser::EUI64<TE>,
+        dev_nonce: &parser::DevNonce<TT>,
+    ) -> Result<&[u8], Error> {
+        if !self.encrypted {
+            let len = self.payload_len();
+            let d = &mut self.data.as_mut()[..len];
+            let mic = securityhelpers::calculate_join_accept_mic_v1_1(
+                &d[..len - 4],
+                self.factory.new_mac(js_int_key),
+                join_req_type,
+                join_eui.as_ref(),
+                dev_nonce.as_ref(),
+            );
+            d[len - 4..].copy_from_slice(&mic.0[..]);
+            self.encrypt_payload(key);
+        }
+        let len = self.payload_len();
+        Ok(&self.data.as_mut()[..len])
+    }
+
+    fn payload_len(&self) -> usize {
+        if self.with_c_f_list {
+            33
         } else {
-            &mut self.data.as_mut()[..17]
-        };
-        set_mic(d, key, &self.factory);
+            17
+        }
+    }
+
+    fn encrypt_payload(&mut self, key: &keys::AES128) {
+        let len = self.payload_len();
+        let d = &mut self.data.as_mut()[..len];
         let aes_enc = self.factory.new_dec(key);
         for i in 0..(d.len() >> 4) {
             let start = (i << 4) + 1;
@@ -189,7 +262,7 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> JoinAcceptCreator<D, F> {
     }
 }
 
-#[cfg(feature = "default-crypto,with-downlink")]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
 impl JoinAcceptCreator<[u8; 33], DefaultFactory> {
     /// Creates a well initialized JoinAcceptCreator.
     ///
@@ -204,10 +277,14 @@ impl JoinAcceptCreator<[u8; 33], DefaultFactory> {
     /// phy.set_dev_addr(&[1; 4]);
     /// phy.set_dl_settings(2);
     /// phy.set_rx_delay(1);
-    /// let mut freqs: Vec<lorawan_encoding::maccommands::Frequency> = Vec::new();
-    /// freqs.push(lorawan_encoding::maccommands::Frequency::new(&[0x58, 0x6e, 0x84,]).unwrap()).unwrap();
-    /// freqs.push(lorawan_encoding::maccommands::Frequency::new(&[0x88, 0x66, 0x84,]).unwrap()).unwrap();
-    /// phy.set_c_f_list(freqs);
+    /// let freqs = [
+    ///     lorawan_encoding::maccommands::Frequency::new(&[0x58, 0x6e, 0x84,]).unwrap(),
+    ///     lorawan_encoding::maccommands::Frequency::new(&[0x88, 0x66, 0x84,]).unwrap(),
+    ///     lorawan_encoding::maccommands::Frequency::new_from_raw(&[0, 0, 0]),
+    ///     lorawan_encoding::maccommands::Frequency::new_from_raw(&[0, 0, 0]),
+    ///     lorawan_encoding::maccommands::Frequency::new_from_raw(&[0, 0, 0]),
+    /// ];
+    /// phy.set_c_f_list(lorawan_encoding::parser::CfList::DynamicChannel(freqs));
     /// let payload = phy.build(&key).unwrap();
     /// ```
     pub fn new() -> Self {
@@ -239,10 +316,10 @@ pub struct JoinRequestCreator<D, F> {
 
 impl<D: AsMut<[u8]>, F: CryptoFactory> JoinRequestCreator<D, F> {
     /// Creates a well initialized JoinRequestCreator with specific crypto functions.
-    pub fn with_options<'a>(mut data: D, factory: F) -> Result<Self, &'a str> {
+    pub fn with_options(mut data: D, factory: F) -> Result<Self, Error> {
         let d = data.as_mut();
         if d.len() < 23 {
-            return Err("data slice is too short");
+            return Err(Error::BufferTooSmall);
         }
         d[0] = 0x00;
         Ok(Self { data, factory })
@@ -302,13 +379,221 @@ impl<D: AsMut<[u8]>, F: CryptoFactory> JoinRequestCreator<D, F> {
     /// # Argument
     ///
     /// * key - the key to be used for setting the MIC.
-    pub fn build(&mut self, key: &keys::AES128) -> Result<&[u8], &str> {
+    pub fn build(&mut self, key: &keys::AES128) -> Result<&[u8], Error> {
         let mut d = self.data.as_mut();
         set_mic(&mut d, key, &self.factory);
         Ok(&d[..])
     }
 }
 
+/// RejoinRequestCreator serves for creating binary representation of Physical
+/// Payload of RejoinRequest.
+///
+/// The layout of the payload depends on the rejoin type: type 0 and type 2 carry the NetID,
+/// while type 1 carries the JoinEUI.
+#[derive(Default)]
+pub struct RejoinRequestCreator<D, F> {
+    data: D,
+    rejoin_type: u8,
+    net_id: [u8; 3],
+    join_eui: [u8; 8],
+    dev_eui: [u8; 8],
+    rj_count: u16,
+    factory: F,
+}
+
+impl<D: AsMut<[u8]>, F: CryptoFactory> RejoinRequestCreator<D, F> {
+    /// Creates a well initialized RejoinRequestCreator with specific crypto functions.
+    ///
+    /// By default the RejoinRequest is of type 0.
+    pub fn with_options(mut data: D, factory: F) -> Result<Self, Error> {
+        let d = data.as_mut();
+        if d.len() < 24 {
+            return Err(Error::BufferTooSmall);
+        }
+        d[0] = 0xc0;
+        Ok(Self {
+            data,
+            rejoin_type: 0,
+            net_id: [0; 3],
+            join_eui: [0; 8],
+            dev_eui: [0; 8],
+            rj_count: 0,
+            factory,
+        })
+    }
+
+    /// Sets the type of the RejoinRequest to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * rejoin_type - the type of the RejoinRequest, must be 0, 1 or 2.
+    pub fn set_rejoin_type(&mut self, rejoin_type: u8) -> Result<&mut Self, Error> {
+        if rejoin_type > 2 {
+            return Err(Error::ValueOutOfRange);
+        }
+        self.rejoin_type = rejoin_type;
+
+        Ok(self)
+    }
+
+    /// Sets the network ID of the RejoinRequest to the provided value.
+    ///
+    /// Only used by RejoinRequests of type 0 and 2.
+    ///
+    /// # Argument
+    ///
+    /// * net_id - instance of lorawan_encoding::parser::NwkAddr or anything that can
+    ///   be converted into it.
+    pub fn set_net_id<H: AsRef<[u8]>, T: Into<parser::NwkAddr<H>>>(
+        &mut self,
+        net_id: T,
+    ) -> &mut Self {
+        let converted = net_id.into();
+        self.net_id.copy_from_slice(converted.as_ref());
+
+        self
+    }
+
+    /// Sets the join EUI of the RejoinRequest to the provided value.
+    ///
+    /// Only used by RejoinRequests of type 1.
+    ///
+    /// # Argument
+    ///
+    /// * join_eui - instance of lorawan_encoding::parser::EUI64 or anything that can
+    ///   be converted into it.
+    pub fn set_join_eui<H: AsRef<[u8]>, T: Into<parser::EUI64<H>>>(
+        &mut self,
+        join_eui: T,
+    ) -> &mut Self {
+        let converted = join_eui.into();
+        self.join_eui.copy_from_slice(converted.as_ref());
+
+        self
+    }
+
+    /// Sets the device EUI of the RejoinRequest to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * dev_eui - instance of lorawan_encoding::parser::EUI64 or anything that can
+    ///   be converted into it.
+    pub fn set_dev_eui<H: AsRef<[u8]>, T: Into<parser::EUI64<H>>>(
+        &mut self,
+        dev_eui: T,
+    ) -> &mut Self {
+        let converted = dev_eui.into();
+        self.dev_eui.copy_from_slice(converted.as_ref());
+
+        self
+    }
+
+    /// Sets the rejoin counter of the RejoinRequest to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * rj_count - RJcount0 for RejoinRequests of type 0 and 2, RJcount1 for type 1.
+    pub fn set_rj_count(&mut self, rj_count: u16) -> &mut Self {
+        self.rj_count = rj_count;
+
+        self
+    }
+
+    /// Provides the binary representation of the RejoinRequest physical payload
+    /// with the MIC set.
+    ///
+    /// # Argument
+    ///
+    /// * key - the key to be used for setting the MIC, SNwkSIntKey for RejoinRequests of type 0
+    ///   and 2, JSIntKey for type 1.
+    pub fn build(&mut self, key: &keys::AES128) -> Result<&[u8], Error> {
+        let d = self.data.as_mut();
+        d[1] = self.rejoin_type;
+        let mut last_filled = 2;
+        if self.rejoin_type == 1 {
+            d[last_filled..last_filled + 8].copy_from_slice(&self.join_eui);
+            last_filled += 8;
+        } else {
+            d[last_filled..last_filled + 3].copy_from_slice(&self.net_id);
+            last_filled += 3;
+        }
+        d[last_filled..last_filled + 8].copy_from_slice(&self.dev_eui);
+        last_filled += 8;
+        d[last_filled] = (self.rj_count & 0xff) as u8;
+        d[last_filled + 1] = (self.rj_count >> 8) as u8;
+        last_filled += 2;
+
+        let d = &mut d[..last_filled + 4];
+        set_mic(d, key, &self.factory);
+        Ok(d)
+    }
+}
+
+/// ProprietaryCreator serves for creating binary representation of Physical
+/// Payload of frames with MType Proprietary.
+///
+/// # Example
+///
+/// ```
+/// let mut phy = lorawan_encoding::creator::ProprietaryCreator::new();
+/// let payload = phy.build(&[0x01, 0x02, 0x03]).unwrap();
+/// ```
+pub struct ProprietaryCreator<D> {
+    data: D,
+}
+
+impl<D: AsMut<[u8]>> ProprietaryCreator<D> {
+    /// Creates a well initialized ProprietaryCreator.
+    ///
+    /// The provided buffer determines the maximum size of the frame that can be built.
+    pub fn with_options(mut data: D) -> Result<Self, Error> {
+        let d = data.as_mut();
+        if d.is_empty() {
+            return Err(Error::BufferTooSmall);
+        }
+        d[0] = 0xe0;
+        Ok(Self { data })
+    }
+
+    /// Provides the binary representation of the proprietary frame with the given payload.
+    ///
+    /// # Argument
+    ///
+    /// * payload - the vendor specific bytes that follow the MHDR.
+    pub fn build(&mut self, payload: &[u8]) -> Result<&[u8], Error> {
+        let d = self.data.as_mut();
+        if d.len() < payload.len() + 1 {
+            return Err(Error::BufferTooSmall);
+        }
+        d[1..=payload.len()].copy_from_slice(payload);
+
+        Ok(&d[..=payload.len()])
+    }
+}
+
+impl ProprietaryCreator<[u8; 256]> {
+    /// Creates a well initialized ProprietaryCreator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut phy = lorawan_encoding::creator::ProprietaryCreator::new();
+    /// let payload = phy.build(b"vendor data").unwrap();
+    /// ```
+    pub fn new() -> Self {
+        let mut data = [0; 256];
+        data[0] = 0xe0;
+        Self { data }
+    }
+}
+
+impl Default for ProprietaryCreator<[u8; 256]> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// DataPayloadCreator serves for creating binary representation of Physical
 /// Payload of DataUp or DataDown messages.
 ///
@@ -331,6 +616,9 @@ pub struct DataPayloadCreator<D, F> {
     data: D,
     data_f_port: Option<u8>,
     fcnt: u32,
+    conf_fcnt: u16,
+    tx_dr: u8,
+    tx_ch: u8,
     factory: F,
 }
 
@@ -338,16 +626,19 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> DataPayloadCreator<D, F> {
     /// Creates a well initialized DataPayloadCreator with specific crypto functions.
     ///
     /// By default the packet is unconfirmed data up packet.
-    pub fn with_options<'a>(mut data: D, factory: F) -> Result<Self, &'a str> {
+    pub fn with_options(mut data: D, factory: F) -> Result<Self, Error> {
         let d = data.as_mut();
         if d.len() < 255 {
-            return Err("data slice is too short");
+            return Err(Error::BufferTooSmall);
         }
         d[0] = 0x40;
         Ok(DataPayloadCreator {
             data,
             data_f_port: None,
             fcnt: 0,
+            conf_fcnt: 0,
+            tx_dr: 0,
+            tx_ch: 0,
             factory,
         })
     }
@@ -412,16 +703,17 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> DataPayloadCreator<D, F> {
 
     /// Sets the FCnt header of the DataPayload packet to the specified value.
     ///
-    /// NOTE: In the packet header the value will be truncated to u16.
+    /// NOTE: In the packet header only the 16 least significant bits are sent, while the full
+    /// 32-bit value is used for the MIC and the encryption.
     ///
     /// # Argument
     ///
-    /// * fctrl - the FCtrl to be set.
+    /// * fcnt - the 32-bit frame counter to be set.
     pub fn set_fcnt(&mut self, fcnt: u32) -> &mut Self {
         let d = self.data.as_mut();
         self.fcnt = fcnt;
-        d[6] = (fcnt & (0xff_u32)) as u8;
-        d[7] = (fcnt >> 8) as u8;
+        d[6] = (fcnt & 0xff) as u8;
+        d[7] = ((fcnt >> 8) & 0xff) as u8;
 
         self
     }
@@ -439,6 +731,45 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> DataPayloadCreator<D, F> {
         self
     }
 
+    /// Sets the frame counter of the confirmed frame that is acknowledged by the DataPayload.
+    ///
+    /// It is used only for the LoRaWAN 1.1 MIC computation and only when the ACK bit is set.
+    ///
+    /// # Argument
+    ///
+    /// * conf_fcnt - the frame counter of the acknowledged confirmed frame.
+    pub fn set_conf_fcnt(&mut self, conf_fcnt: u16) -> &mut Self {
+        self.conf_fcnt = conf_fcnt;
+
+        self
+    }
+
+    /// Sets the data rate used for the transmission of the DataPayload.
+    ///
+    /// It is used only for the LoRaWAN 1.1 MIC computation of uplinks.
+    ///
+    /// # Argument
+    ///
+    /// * tx_dr - the data rate of the transmission.
+    pub fn set_tx_dr(&mut self, tx_dr: u8) -> &mut Self {
+        self.tx_dr = tx_dr;
+
+        self
+    }
+
+    /// Sets the index of the channel used for the transmission of the DataPayload.
+    ///
+    /// It is used only for the LoRaWAN 1.1 MIC computation of uplinks.
+    ///
+    /// # Argument
+    ///
+    /// * tx_ch - the index of the channel of the transmission.
+    pub fn set_tx_ch(&mut self, tx_ch: u8) -> &mut Self {
+        self.tx_ch = tx_ch;
+
+        self
+    }
+
     /// Whether a set of mac commands can be piggybacked.
     pub fn can_piggyback(cmds: &[&dyn SerializableMacCommand]) -> bool {
         mac_commands_len(cmds) <= PIGGYBACK_MAC_COMMANDS_MAX_LEN
@@ -474,13 +805,95 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> DataPayloadCreator<D, F> {
     /// let app_skey = lorawan_encoding::keys::AES128([1; 16]);
     /// phy.build(&[], &cmds[..], &nwk_skey, &app_skey).unwrap();
     /// ```
-    pub fn build<'a, 'b, 'c, 'd, 'e>(
+    pub fn build(
+        &mut self,
+        payload: &[u8],
+        cmds: &[&dyn SerializableMacCommand],
+        nwk_skey: &keys::AES128,
+        app_skey: &keys::AES128,
+    ) -> Result<&[u8], Error> {
+        let len = self.fill(payload, cmds, nwk_skey, app_skey)?;
+        let d = self.data.as_mut();
+
+        // MIC set
+        let mic = securityhelpers::calculate_data_mic(
+            &d[..len],
+            self.factory.new_mac(nwk_skey),
+            self.fcnt,
+        );
+        d[len..len + 4].copy_from_slice(&mic.0[..]);
+
+        Ok(&d[..len + 4])
+    }
+
+    /// Provides the binary representation of the LoRaWAN 1.1 DataPayload physical payload
+    /// with the MIC set and payload and FOpts encrypted.
+    ///
+    /// For uplinks the values set through [set_conf_fcnt](#method.set_conf_fcnt),
+    /// [set_tx_dr](#method.set_tx_dr) and [set_tx_ch](#method.set_tx_ch) are used for the MIC,
+    /// for downlinks only the one from [set_conf_fcnt](#method.set_conf_fcnt).
+    ///
+    /// # Argument
+    ///
+    /// * payload - the FRMPayload (application) to be sent.
+    /// * cmds - the mac commands to be sent.
+    /// * f_nwk_s_int_key - the key used for the MIC of uplinks, ignored for downlinks.
+    /// * s_nwk_s_int_key - the key used for the MIC.
+    /// * nwk_s_enc_key - the key used for FOpts and MAC commands encryption.
+    /// * app_skey - the key to be used for payload encryption if fport not 0.
+    pub fn build_v1_1(
         &mut self,
         payload: &[u8],
-        cmds: &'a [&'b dyn SerializableMacCommand],
-        nwk_skey: &'c keys::AES128,
-        app_skey: &'d keys::AES128,
-    ) -> Result<&[u8], &'e str> {
+        cmds: &[&dyn SerializableMacCommand],
+        f_nwk_s_int_key: &keys::AES128,
+        s_nwk_s_int_key: &keys::AES128,
+        nwk_s_enc_key: &keys::AES128,
+        app_skey: &keys::AES128,
+    ) -> Result<&[u8], Error> {
+        let len = self.fill(payload, cmds, nwk_s_enc_key, app_skey)?;
+        let d = self.data.as_mut();
+
+        // Encrypt FOpts
+        // only downlinks with FPort > 0 use the AFCntDown
+        let a4 = match self.data_f_port {
+            Some(f_port) if d[0] & 0x20 != 0 && f_port > 0 => 0x02,
+            _ => 0x01,
+        };
+        securityhelpers::encrypt_f_opts(d, a4, self.fcnt, &self.factory.new_enc(nwk_s_enc_key));
+
+        // MIC set
+        let mic = if d[0] & 0x20 == 0 {
+            securityhelpers::calculate_uplink_data_mic_v1_1(
+                &d[..len],
+                self.factory.new_mac(f_nwk_s_int_key),
+                self.factory.new_mac(s_nwk_s_int_key),
+                self.fcnt,
+                self.conf_fcnt,
+                self.tx_dr,
+                self.tx_ch,
+            )
+        } else {
+            securityhelpers::calculate_downlink_data_mic_v1_1(
+                &d[..len],
+                self.factory.new_mac(s_nwk_s_int_key),
+                self.fcnt,
+                self.conf_fcnt,
+            )
+        };
+        d[len..len + 4].copy_from_slice(&mic.0[..]);
+
+        Ok(&d[..len + 4])
+    }
+
+    /// Fills in the payload and mac commands, encrypting the FRMPayload, and returns the length
+    /// of the packet without the MIC.
+    fn fill(
+        &mut self,
+        payload: &[u8],
+        cmds: &[&dyn SerializableMacCommand],
+        nwk_skey: &keys::AES128,
+        app_skey: &keys::AES128,
+    ) -> Result<usize, Error> {
         let mut d = self.data.as_mut();
         let mut last_filled = 8; // MHDR + FHDR without the FOpts
         let has_fport = self.data_f_port.is_some();
@@ -489,16 +902,20 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> DataPayloadCreator<D, F> {
 
         // Set MAC Commands
         if mac_cmds_len > PIGGYBACK_MAC_COMMANDS_MAX_LEN && !has_fport_zero {
-            return Err("mac commands are too big for FOpts");
+            return Err(Error::MacCommandsTooLong);
         }
 
         // Set FPort
         let mut payload_len = payload.len();
         if has_fport_zero && payload_len > 0 {
-            return Err("mac commands in payload can not be send together with payload");
+            return Err(Error::InvalidFPort);
         }
         if !has_fport && payload_len > 0 {
-            return Err("fport must be provided when there is FRMPayload");
+            return Err(Error::InvalidFPort);
+        }
+        // MHDR, FHDR, FOpts or MAC commands payload, FPort, FRMPayload and MIC have to fit
+        if last_filled + mac_cmds_len + has_fport as usize + payload_len + 4 > d.len() {
+            return Err(Error::BufferTooSmall);
         }
         // Set FOptsLen if present
         if !has_fport_zero && mac_cmds_len > 0 {
@@ -537,15 +954,7 @@ impl<D: AsMut<[u8]>, F: CryptoFactory + Default> DataPayloadCreator<D, F> {
             &self.factory.new_enc(&enc_key),
         );
 
-        // MIC set
-        let mic = securityhelpers::calculate_data_mic(
-            &d[..last_filled + payload_len],
-            self.factory.new_mac(nwk_skey),
-            self.fcnt,
-        );
-        d[last_filled + payload_len..last_filled + payload_len + 4].copy_from_slice(&mic.0[..]);
-
-        Ok(&d[..last_filled + payload_len + 4])
+        Ok(last_filled + payload_len)
     }
 }
 
@@ -577,6 +986,9 @@ impl DataPayloadCreator<GenericArray<u8, U256>, DefaultFactory> {
             data,
             data_f_port: None,
             fcnt: 0,
+            conf_fcnt: 0,
+            tx_dr: 0,
+            tx_ch: 0,
             factory: DefaultFactory,
         }
     }
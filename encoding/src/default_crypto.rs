@@ -5,11 +5,13 @@
 // copied, modified, or distributed except according to those terms.
 //
 // author: Ivaylo Petrov <ivajloip@gmail.com>
-use super::creator::JoinRequestCreator;
+use super::creator::{JoinRequestCreator, RejoinRequestCreator};
+use super::error::Error;
 use super::keys::*;
 use super::parser::{
     DecryptedDataPayload, DecryptedJoinAcceptPayload, EncryptedDataPayload,
-    EncryptedJoinAcceptPayload, JoinRequestPayload,
+    EncryptedJoinAcceptPayload, JoinRequestPayload, RejoinRequestType02Payload,
+    RejoinRequestType1Payload,
 };
 use super::securityhelpers::generic_array::{typenum::U16, GenericArray};
 use aes::cipher::{BlockCipher, NewBlockCipher};
@@ -99,7 +101,64 @@ impl<T: AsRef<[u8]>> JoinRequestPayload<T, DefaultFactory> {
     ///     0x02, 0x05, 0x04, 0x03, 0x02, 0x2d, 0x10, 0x6a, 0x99, 0x0e, 0x12];
     /// let phy = lorawan_encoding::parser::JoinRequestPayload::new(data);
     /// ```
-    pub fn new<'a>(data: T) -> Result<Self, &'a str> {
+    pub fn new(data: T) -> Result<Self, Error> {
+        Self::new_with_factory(data, DefaultFactory)
+    }
+}
+
+impl RejoinRequestCreator<[u8; 24], DefaultFactory> {
+    /// Creates a well initialized RejoinRequestCreator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut phy = lorawan_encoding::creator::RejoinRequestCreator::new();
+    /// let key = lorawan_encoding::keys::AES128([7; 16]);
+    /// phy.set_rejoin_type(1).unwrap();
+    /// phy.set_join_eui(&[1; 8]);
+    /// phy.set_dev_eui(&[2; 8]);
+    /// phy.set_rj_count(3);
+    /// let payload = phy.build(&key).unwrap();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_options([0; 24], DefaultFactory).unwrap()
+    }
+}
+
+impl<T: AsRef<[u8]>> RejoinRequestType02Payload<T, DefaultFactory> {
+    /// Creates a new RejoinRequestType02Payload if the provided data is acceptable.
+    ///
+    /// # Argument
+    ///
+    /// * data - the bytes for the payload.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let data = vec![0xc0, 0x00, 0x03, 0x02, 0x01, 0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03,
+    ///     0x02, 0x01, 0x00, 0xac, 0x7e, 0xbc, 0x60];
+    /// let phy = lorawan_encoding::parser::RejoinRequestType02Payload::new(data);
+    /// ```
+    pub fn new(data: T) -> Result<Self, Error> {
+        Self::new_with_factory(data, DefaultFactory)
+    }
+}
+
+impl<T: AsRef<[u8]>> RejoinRequestType1Payload<T, DefaultFactory> {
+    /// Creates a new RejoinRequestType1Payload if the provided data is acceptable.
+    ///
+    /// # Argument
+    ///
+    /// * data - the bytes for the payload.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let data = vec![0xc0, 0x01, 0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01, 0x05, 0x04,
+    ///     0x03, 0x02, 0x05, 0x04, 0x03, 0x02, 0x01, 0x00, 0x83, 0xbe, 0xbe, 0x40];
+    /// let phy = lorawan_encoding::parser::RejoinRequestType1Payload::new(data);
+    /// ```
+    pub fn new(data: T) -> Result<Self, Error> {
         Self::new_with_factory(data, DefaultFactory)
     }
 }
@@ -118,7 +177,7 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> EncryptedJoinAcceptPayload<T, DefaultFactory>
     ///     0x37, 0x42, 0x97, 0x51, 0x42];
     /// let phy = lorawan_encoding::parser::EncryptedJoinAcceptPayload::new(data);
     /// ```
-    pub fn new<'a>(data: T) -> Result<Self, &'a str> {
+    pub fn new(data: T) -> Result<Self, Error> {
         Self::new_with_factory(data, DefaultFactory)
     }
 }
@@ -143,7 +202,7 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> DecryptedJoinAcceptPayload<T, DefaultFactory>
     ///     0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
     /// let phy = lorawan_encoding::parser::DecryptedJoinAcceptPayload::new(&mut data[..], &key);
     /// ```
-    pub fn new<'a, 'b>(data: T, key: &'a AES128) -> Result<Self, &'b str> {
+    pub fn new(data: T, key: &AES128) -> Result<Self, Error> {
         Self::new_with_factory(data, key, DefaultFactory)
     }
 }
@@ -162,7 +221,7 @@ impl<T: AsRef<[u8]>> EncryptedDataPayload<T, DefaultFactory> {
     ///     0xa6, 0x94, 0x64, 0x26, 0x15, 0xd6, 0xc3, 0xb5, 0x82];
     /// let phy = lorawan_encoding::parser::EncryptedDataPayload::new(data);
     /// ```
-    pub fn new<'a>(data: T) -> Result<Self, &'a str> {
+    pub fn new(data: T) -> Result<Self, Error> {
         Self::new_with_factory(data, DefaultFactory)
     }
 }
@@ -192,15 +251,15 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> DecryptedDataPayload<T> {
     ///     Some(&app_skey),
     ///     1).unwrap();
     /// ```
-    pub fn new<'a, 'b>(
+    pub fn new<'a>(
         data: T,
         nwk_skey: &'a AES128,
         app_skey: Option<&'a AES128>,
         fcnt: u32,
-    ) -> Result<Self, &'b str> {
+    ) -> Result<Self, Error> {
         let t = EncryptedDataPayload::new(data)?;
         if !t.validate_mic(nwk_skey, fcnt) {
-            return Err("invalid mic");
+            return Err(Error::MicMismatch);
         }
         t.decrypt(Some(nwk_skey), app_skey, fcnt)
     }
@@ -0,0 +1,63 @@
+//! Provides the error type returned when parsing or creating LoRaWAN payloads.
+
+use core::fmt;
+
+/// Error represents the reasons for which a payload can not be parsed or created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The provided data has fewer bytes than the payload requires.
+    TooShort,
+    /// The length of the provided data does not match the payload type.
+    InvalidLength,
+    /// The major version of the LoRaWAN frame is not supported.
+    UnsupportedMajorVersion,
+    /// The MType of the frame does not match the payload type.
+    InvalidMType,
+    /// The type of the RejoinRequest is not supported.
+    UnsupportedRejoinType,
+    /// The MIC of the payload does not match the provided key.
+    MicMismatch,
+    /// The FPort is missing or does not allow the provided FRMPayload.
+    InvalidFPort,
+    /// The MAC commands do not fit in the FOpts field.
+    MacCommandsTooLong,
+    /// The provided buffer is too small to hold the payload.
+    BufferTooSmall,
+    /// The CID of the MAC command is not known for the given direction.
+    UnknownMacCommand,
+    /// The MAC command could not be built from the provided data.
+    InvalidMacCommand,
+    /// The data rate range has max data rate smaller than min data rate.
+    InvalidDataRateRange,
+    /// A field was given a value outside of its allowed range.
+    ValueOutOfRange,
+    /// A key needed to decrypt the payload was not provided.
+    MissingKey,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Error::TooShort => "insufficient number of bytes",
+            Error::InvalidLength => "invalid payload length",
+            Error::UnsupportedMajorVersion => "unsupported major version",
+            Error::InvalidMType => "invalid MType for the payload",
+            Error::UnsupportedRejoinType => "unsupported rejoin type",
+            Error::MicMismatch => "MIC did not match",
+            Error::InvalidFPort => "invalid fport for the payload",
+            Error::MacCommandsTooLong => "mac commands are too big for FOpts",
+            Error::BufferTooSmall => "data slice is too short",
+            Error::UnknownMacCommand => "unknown mac command",
+            Error::InvalidMacCommand => "invalid mac command",
+            Error::InvalidDataRateRange => {
+                "data rate range can not have max data rate smaller than min data rate"
+            }
+            Error::ValueOutOfRange => "value out of range",
+            Error::MissingKey => "key needed to decrypt the payload was None",
+        };
+        f.write_str(msg)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
@@ -5,6 +5,8 @@
 // copied, modified, or distributed except according to those terms.
 //
 // author: Ivaylo Petrov <ivajloip@gmail.com>
+use super::parser::EUI64;
+use super::securityhelpers;
 use super::securityhelpers::generic_array::{typenum::U16, GenericArray};
 
 /// AES128 represents 128 bit AES key.
@@ -61,3 +63,34 @@ pub trait CryptoFactory {
     /// Method that creates a MAC calculator.
     fn new_mac(&self, key: &AES128) -> Self::M;
 }
+
+/// Derives the LoRaWAN 1.1 JSIntKey that is used for the MIC of JoinAccepts with OptNeg set.
+///
+/// # Argument
+///
+/// * nwk_key - the NwkKey of the device.
+/// * dev_eui - the DevEUI of the device.
+/// * factory - the factory that shall be used to create object for crypto functions.
+pub fn derive_js_int_key<T: AsRef<[u8]>, F: CryptoFactory>(
+    nwk_key: &AES128,
+    dev_eui: &EUI64<T>,
+    factory: &F,
+) -> AES128 {
+    securityhelpers::derive_join_server_key_v1_1(0x06, dev_eui.as_ref(), &factory.new_enc(nwk_key))
+}
+
+/// Derives the LoRaWAN 1.1 JSEncKey that is used for the encryption of JoinAccepts sent in
+/// response to RejoinRequests.
+///
+/// # Argument
+///
+/// * nwk_key - the NwkKey of the device.
+/// * dev_eui - the DevEUI of the device.
+/// * factory - the factory that shall be used to create object for crypto functions.
+pub fn derive_js_enc_key<T: AsRef<[u8]>, F: CryptoFactory>(
+    nwk_key: &AES128,
+    dev_eui: &EUI64<T>,
+    factory: &F,
+) -> AES128 {
+    securityhelpers::derive_join_server_key_v1_1(0x05, dev_eui.as_ref(), &factory.new_enc(nwk_key))
+}
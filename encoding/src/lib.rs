@@ -9,7 +9,12 @@
 //! This module implements LoRaWAN packet handling and parsing.
 #![no_std]
 #![allow(clippy::upper_case_acronyms)]
+
+#[cfg(feature = "std")]
+extern crate std;
+
 pub mod creator;
+pub mod error;
 pub mod keys;
 pub mod maccommandcreator;
 pub mod maccommands;
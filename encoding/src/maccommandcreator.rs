@@ -6,6 +6,7 @@
 //
 // Author: Ivaylo Petrov <ivajloip@gmail.com>
 
+use super::error::Error;
 use super::maccommands::*;
 
 macro_rules! impl_mac_cmd_creator_boilerplate {
@@ -157,9 +158,9 @@ impl LinkADRReqCreator {
     /// # Argument
     ///
     /// * data_rate - data rate index of the ADR request. The value must be between 0 and 15.
-    pub fn set_data_rate(&mut self, data_rate: u8) -> Result<&mut Self, &str> {
+    pub fn set_data_rate(&mut self, data_rate: u8) -> Result<&mut Self, Error> {
         if data_rate > 0x0f {
-            return Err("data_rate out of range");
+            return Err(Error::ValueOutOfRange);
         }
         self.data[1] &= 0x0f;
         self.data[1] |= data_rate << 4;
@@ -172,9 +173,9 @@ impl LinkADRReqCreator {
     /// # Argument
     ///
     /// * tx_power - TX power index. The value must be between 0 and 15.
-    pub fn set_tx_power(&mut self, tx_power: u8) -> Result<&mut Self, &str> {
+    pub fn set_tx_power(&mut self, tx_power: u8) -> Result<&mut Self, Error> {
         if tx_power > 0x0f {
-            return Err("tx_power out of range");
+            return Err(Error::ValueOutOfRange);
         }
         self.data[1] &= 0xf0;
         self.data[1] |= tx_power & 0x0f;
@@ -287,7 +288,7 @@ impl DutyCycleReqCreator {
     ///
     /// * max_duty_cycle - the value used to determine the aggregated duty cycle using the formula
     /// `1 / (2 ** max_duty_cycle)`.
-    pub fn set_max_duty_cycle(&mut self, max_duty_cycle: u8) -> Result<&mut Self, &str> {
+    pub fn set_max_duty_cycle(&mut self, max_duty_cycle: u8) -> Result<&mut Self, Error> {
         self.data[1] = max_duty_cycle;
 
         Ok(self)
@@ -452,9 +453,9 @@ impl DevStatusAnsCreator {
     /// # Argument
     ///
     /// * margin - the value to be used as margin.
-    pub fn set_margin(&mut self, margin: i8) -> Result<&mut Self, &str> {
+    pub fn set_margin(&mut self, margin: i8) -> Result<&mut Self, Error> {
         if margin < -32 || margin > 31 {
-            return Err("margin out of range");
+            return Err(Error::ValueOutOfRange);
         }
         self.data[2] = ((margin << 2) as u8) >> 2;
 
@@ -581,9 +582,9 @@ impl RXTimingSetupReqCreator {
     /// # Argument
     ///
     /// * delay - the value to be used as delay.
-    pub fn set_delay(&mut self, delay: u8) -> Result<&mut Self, &str> {
+    pub fn set_delay(&mut self, delay: u8) -> Result<&mut Self, Error> {
         if delay > 0x0f {
-            return Err("delay out of range");
+            return Err(Error::ValueOutOfRange);
         }
         self.data[1] &= 0xf0;
         self.data[1] |= delay;
@@ -604,13 +605,323 @@ pub struct RXTimingSetupAnsCreator {}
 
 impl_mac_cmd_creator_boilerplate!(RXTimingSetupAnsCreator, 0x08);
 
-pub fn build_mac_commands<'a, 'b, 'c, T: AsMut<[u8]>>(
-    cmds: &'a [&'b dyn SerializableMacCommand],
+/// PingSlotInfoReqCreator serves for creating PingSlotInfoReq MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::PingSlotInfoReqCreator::new();
+/// let res = creator.set_periodicity(0x07).unwrap().build();
+/// ```
+pub struct PingSlotInfoReqCreator {
+    data: [u8; 2],
+}
+
+impl_mac_cmd_creator_boilerplate!(PingSlotInfoReqCreator, 0x10, 2);
+
+impl PingSlotInfoReqCreator {
+    /// Sets the periodicity of the PingSlotInfoReq to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * periodicity - the value used to determine the ping period using the formula
+    ///   `2 ** periodicity` seconds. The value must be between 0 and 7.
+    pub fn set_periodicity(&mut self, periodicity: u8) -> Result<&mut Self, Error> {
+        if periodicity > 0x07 {
+            return Err(Error::ValueOutOfRange);
+        }
+        self.data[1] &= 0xf8;
+        self.data[1] |= periodicity;
+
+        Ok(self)
+    }
+}
+
+/// PingSlotInfoAnsCreator serves for creating PingSlotInfoAns MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let creator = lorawan_encoding::maccommandcreator::PingSlotInfoAnsCreator::new();
+/// let res = creator.build();
+/// ```
+pub struct PingSlotInfoAnsCreator {}
+
+impl_mac_cmd_creator_boilerplate!(PingSlotInfoAnsCreator, 0x10);
+
+/// PingSlotChannelReqCreator serves for creating PingSlotChannelReq MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::PingSlotChannelReqCreator::new();
+/// let res = creator
+///     .set_frequency(&[0x12, 0x34, 0x56])
+///     .set_data_rate(0x03)
+///     .unwrap()
+///     .build();
+/// ```
+pub struct PingSlotChannelReqCreator {
+    data: [u8; 5],
+}
+
+impl_mac_cmd_creator_boilerplate!(PingSlotChannelReqCreator, 0x11, 5);
+
+impl PingSlotChannelReqCreator {
+    /// Sets the frequency of the PingSlotChannelReq to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * frequency - instance of maccommands::Frequency or anything that can be converted
+    ///   into it.
+    pub fn set_frequency<'a, T: Into<Frequency<'a>>>(&mut self, frequency: T) -> &mut Self {
+        let converted = frequency.into();
+        self.data[1..4].copy_from_slice(converted.as_ref());
+
+        self
+    }
+
+    /// Sets the data rate of the PingSlotChannelReq to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * data_rate - data rate index used for the ping slots. The value must be between 0 and 15.
+    pub fn set_data_rate(&mut self, data_rate: u8) -> Result<&mut Self, Error> {
+        if data_rate > 0x0f {
+            return Err(Error::ValueOutOfRange);
+        }
+        self.data[4] &= 0xf0;
+        self.data[4] |= data_rate;
+
+        Ok(self)
+    }
+}
+
+/// PingSlotChannelAnsCreator serves for creating PingSlotChannelAns MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::PingSlotChannelAnsCreator::new();
+/// let res = creator
+///     .set_channel_frequency_ack(true)
+///     .set_data_rate_ack(true)
+///     .build();
+/// ```
+pub struct PingSlotChannelAnsCreator {
+    data: [u8; 2],
+}
+
+impl_mac_cmd_creator_boilerplate!(PingSlotChannelAnsCreator, 0x11, 2);
+
+impl PingSlotChannelAnsCreator {
+    /// Sets the channel frequency acknowledgement of the PingSlotChannelAns to the provided
+    /// value.
+    ///
+    /// # Argument
+    ///
+    /// * ack - true meaning that the channel frequency was acceptable or false otherwise.
+    pub fn set_channel_frequency_ack(&mut self, ack: bool) -> &mut Self {
+        self.data[1] &= 0xfe;
+        self.data[1] |= ack as u8;
+
+        self
+    }
+
+    /// Sets the data rate acknowledgement of the PingSlotChannelAns to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * ack - true meaning that the data rate was acceptable or false otherwise.
+    pub fn set_data_rate_ack(&mut self, ack: bool) -> &mut Self {
+        self.data[1] &= 0xfd;
+        self.data[1] |= (ack as u8) << 1;
+
+        self
+    }
+}
+
+/// BeaconTimingReqCreator serves for creating BeaconTimingReq MacCommand.
+///
+/// Note: this command is deprecated since LoRaWAN 1.0.3.
+///
+/// # Examples
+///
+/// ```
+/// let creator = lorawan_encoding::maccommandcreator::BeaconTimingReqCreator::new();
+/// let res = creator.build();
+/// ```
+pub struct BeaconTimingReqCreator {}
+
+impl_mac_cmd_creator_boilerplate!(BeaconTimingReqCreator, 0x12);
+
+/// BeaconTimingAnsCreator serves for creating BeaconTimingAns MacCommand.
+///
+/// Note: this command is deprecated since LoRaWAN 1.0.3.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::BeaconTimingAnsCreator::new();
+/// let res = creator.set_delay(0x1234).set_channel(0x05).build();
+/// ```
+pub struct BeaconTimingAnsCreator {
+    data: [u8; 4],
+}
+
+impl_mac_cmd_creator_boilerplate!(BeaconTimingAnsCreator, 0x12, 4);
+
+impl BeaconTimingAnsCreator {
+    /// Sets the delay of the BeaconTimingAns to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * delay - the delay in units of 30ms until the start of the next beacon.
+    pub fn set_delay(&mut self, delay: u16) -> &mut Self {
+        self.data[1] = (delay & 0xff) as u8;
+        self.data[2] = (delay >> 8) as u8;
+
+        self
+    }
+
+    /// Sets the channel of the BeaconTimingAns to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * channel - the index of the channel on which the next beacon will be broadcast.
+    pub fn set_channel(&mut self, channel: u8) -> &mut Self {
+        self.data[3] = channel;
+
+        self
+    }
+}
+
+/// BeaconFreqReqCreator serves for creating BeaconFreqReq MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::BeaconFreqReqCreator::new();
+/// let res = creator.set_frequency(&[0x12, 0x34, 0x56]).build();
+/// ```
+pub struct BeaconFreqReqCreator {
+    data: [u8; 4],
+}
+
+impl_mac_cmd_creator_boilerplate!(BeaconFreqReqCreator, 0x13, 4);
+
+impl BeaconFreqReqCreator {
+    /// Sets the frequency of the BeaconFreqReq to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * frequency - instance of maccommands::Frequency or anything that can be converted
+    ///   into it.
+    pub fn set_frequency<'a, T: Into<Frequency<'a>>>(&mut self, frequency: T) -> &mut Self {
+        let converted = frequency.into();
+        self.data[1..4].copy_from_slice(converted.as_ref());
+
+        self
+    }
+}
+
+/// BeaconFreqAnsCreator serves for creating BeaconFreqAns MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::BeaconFreqAnsCreator::new();
+/// let res = creator.set_beacon_frequency_ack(true).build();
+/// ```
+pub struct BeaconFreqAnsCreator {
+    data: [u8; 2],
+}
+
+impl_mac_cmd_creator_boilerplate!(BeaconFreqAnsCreator, 0x13, 2);
+
+impl BeaconFreqAnsCreator {
+    /// Sets the beacon frequency acknowledgement of the BeaconFreqAns to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * ack - true meaning that the beacon frequency was acceptable or false otherwise.
+    pub fn set_beacon_frequency_ack(&mut self, ack: bool) -> &mut Self {
+        self.data[1] &= 0xfe;
+        self.data[1] |= ack as u8;
+
+        self
+    }
+}
+
+/// RekeyIndCreator serves for creating RekeyInd MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::RekeyIndCreator::new();
+/// let res = creator.set_minor_version(1).unwrap().build();
+/// ```
+pub struct RekeyIndCreator {
+    data: [u8; 2],
+}
+
+impl_mac_cmd_creator_boilerplate!(RekeyIndCreator, 0x0b, 2);
+
+impl RekeyIndCreator {
+    /// Sets the LoRaWAN minor version of the RekeyInd to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * minor_version - the minor version supported by the device. The value must be between 0
+    ///   and 15.
+    pub fn set_minor_version(&mut self, minor_version: u8) -> Result<&mut Self, Error> {
+        if minor_version > 0x0f {
+            return Err(Error::ValueOutOfRange);
+        }
+        self.data[1] = minor_version;
+
+        Ok(self)
+    }
+}
+
+/// RekeyConfCreator serves for creating RekeyConf MacCommand.
+///
+/// # Examples
+///
+/// ```
+/// let mut creator = lorawan_encoding::maccommandcreator::RekeyConfCreator::new();
+/// let res = creator.set_minor_version(1).unwrap().build();
+/// ```
+pub struct RekeyConfCreator {
+    data: [u8; 2],
+}
+
+impl_mac_cmd_creator_boilerplate!(RekeyConfCreator, 0x0b, 2);
+
+impl RekeyConfCreator {
+    /// Sets the LoRaWAN minor version of the RekeyConf to the provided value.
+    ///
+    /// # Argument
+    ///
+    /// * minor_version - the minor version selected by the network server. The value must be
+    ///   between 0 and 15.
+    pub fn set_minor_version(&mut self, minor_version: u8) -> Result<&mut Self, Error> {
+        if minor_version > 0x0f {
+            return Err(Error::ValueOutOfRange);
+        }
+        self.data[1] = minor_version;
+
+        Ok(self)
+    }
+}
+
+pub fn build_mac_commands<T: AsMut<[u8]>>(
+    cmds: &[&dyn SerializableMacCommand],
     mut out: T,
-) -> Result<usize, &'c str> {
+) -> Result<usize, Error> {
     let res = out.as_mut();
     if mac_commands_len(cmds) > res.len() {
-        return Err("failed to serialize mac commands in provided buffer: too small");
+        return Err(Error::BufferTooSmall);
     }
     let mut i = 0;
     for mc in cmds {
@@ -6,6 +6,8 @@
 //
 // author: Ivaylo Petrov <ivajloip@gmail.com>
 
+use super::error::Error;
+
 /// MacCommand represents the enumeration of all LoRaWAN MACCommands.
 #[derive(Debug, PartialEq)]
 pub enum MacCommand<'a> {
@@ -23,6 +25,17 @@ pub enum MacCommand<'a> {
     NewChannelAns(NewChannelAnsPayload<'a>),
     RXTimingSetupReq(RXTimingSetupReqPayload<'a>),
     RXTimingSetupAns(RXTimingSetupAnsPayload),
+    RekeyInd(RekeyIndPayload<'a>),
+    RekeyConf(RekeyConfPayload<'a>),
+    PingSlotInfoReq(PingSlotInfoReqPayload<'a>),
+    PingSlotInfoAns(PingSlotInfoAnsPayload),
+    PingSlotChannelReq(PingSlotChannelReqPayload<'a>),
+    PingSlotChannelAns(PingSlotChannelAnsPayload<'a>),
+    BeaconTimingReq(BeaconTimingReqPayload),
+    BeaconTimingAns(BeaconTimingAnsPayload<'a>),
+    BeaconFreqReq(BeaconFreqReqPayload<'a>),
+    BeaconFreqAns(BeaconFreqAnsPayload<'a>),
+    Proprietary(ProprietaryPayload<'a>),
 }
 
 impl<'a> MacCommand<'a> {
@@ -43,6 +56,17 @@ impl<'a> MacCommand<'a> {
             MacCommand::NewChannelAns(_) => NewChannelAnsPayload::len(),
             MacCommand::RXTimingSetupReq(_) => RXTimingSetupReqPayload::len(),
             MacCommand::RXTimingSetupAns(_) => RXTimingSetupAnsPayload::len(),
+            MacCommand::RekeyInd(_) => RekeyIndPayload::len(),
+            MacCommand::RekeyConf(_) => RekeyConfPayload::len(),
+            MacCommand::PingSlotInfoReq(_) => PingSlotInfoReqPayload::len(),
+            MacCommand::PingSlotInfoAns(_) => PingSlotInfoAnsPayload::len(),
+            MacCommand::PingSlotChannelReq(_) => PingSlotChannelReqPayload::len(),
+            MacCommand::PingSlotChannelAns(_) => PingSlotChannelAnsPayload::len(),
+            MacCommand::BeaconTimingReq(_) => BeaconTimingReqPayload::len(),
+            MacCommand::BeaconTimingAns(_) => BeaconTimingAnsPayload::len(),
+            MacCommand::BeaconFreqReq(_) => BeaconFreqReqPayload::len(),
+            MacCommand::BeaconFreqAns(_) => BeaconFreqAnsPayload::len(),
+            MacCommand::Proprietary(ref v) => v.len(),
         }
     }
 
@@ -62,6 +86,17 @@ impl<'a> MacCommand<'a> {
             MacCommand::NewChannelAns(ref v) => &v.0,
             MacCommand::RXTimingSetupReq(ref v) => &v.0,
             MacCommand::RXTimingSetupAns(_) => &[],
+            MacCommand::RekeyInd(ref v) => v.0,
+            MacCommand::RekeyConf(ref v) => v.0,
+            MacCommand::PingSlotInfoReq(ref v) => v.0,
+            MacCommand::PingSlotInfoAns(_) => &[],
+            MacCommand::PingSlotChannelReq(ref v) => v.0,
+            MacCommand::PingSlotChannelAns(ref v) => v.0,
+            MacCommand::BeaconTimingReq(_) => &[],
+            MacCommand::BeaconTimingAns(ref v) => v.0,
+            MacCommand::BeaconFreqReq(ref v) => v.0,
+            MacCommand::BeaconFreqAns(ref v) => v.0,
+            MacCommand::Proprietary(ref v) => v.payload(),
         }
     }
 }
@@ -93,6 +128,17 @@ impl<'a> SerializableMacCommand for MacCommand<'a> {
             MacCommand::NewChannelAns(_) => NewChannelAnsPayload::cid(),
             MacCommand::RXTimingSetupReq(_) => RXTimingSetupReqPayload::cid(),
             MacCommand::RXTimingSetupAns(_) => RXTimingSetupAnsPayload::cid(),
+            MacCommand::RekeyInd(_) => RekeyIndPayload::cid(),
+            MacCommand::RekeyConf(_) => RekeyConfPayload::cid(),
+            MacCommand::PingSlotInfoReq(_) => PingSlotInfoReqPayload::cid(),
+            MacCommand::PingSlotInfoAns(_) => PingSlotInfoAnsPayload::cid(),
+            MacCommand::PingSlotChannelReq(_) => PingSlotChannelReqPayload::cid(),
+            MacCommand::PingSlotChannelAns(_) => PingSlotChannelAnsPayload::cid(),
+            MacCommand::BeaconTimingReq(_) => BeaconTimingReqPayload::cid(),
+            MacCommand::BeaconTimingAns(_) => BeaconTimingAnsPayload::cid(),
+            MacCommand::BeaconFreqReq(_) => BeaconFreqReqPayload::cid(),
+            MacCommand::BeaconFreqAns(_) => BeaconFreqAnsPayload::cid(),
+            MacCommand::Proprietary(ref v) => v.cid(),
         }
     }
 
@@ -119,11 +165,11 @@ macro_rules! mac_cmd_zero_len {
             pub struct $type();
 
             impl $type {
-                pub fn new(_: &[u8]) -> Result<$type, &str> {
+                pub fn new(_: &[u8]) -> Result<$type, Error> {
                     Ok($type())
                 }
 
-                pub fn new_as_mac_cmd<'a>(data: &[u8]) -> Result<(MacCommand<'a>, usize), &str> {
+                pub fn new_as_mac_cmd<'a>(data: &[u8]) -> Result<(MacCommand<'a>, usize), Error> {
                     Ok((MacCommand::$name($type::new(data)?), 0))
                 }
 
@@ -141,12 +187,12 @@ macro_rules! mac_cmd_zero_len {
             }
         )*
 
-        fn parse_zero_len_mac_cmd<'a, 'b>(data: &'a [u8], uplink: bool) -> Result<(usize, MacCommand<'a>), &'b str> {
+        fn parse_zero_len_mac_cmd<'a>(data: &'a [u8], uplink: bool) -> Result<(usize, MacCommand<'a>), Error> {
             match (data[0], uplink) {
                 $(
                     ($cid, $uplink) => Ok((0, MacCommand::$name($type::new(&[])?))),
                 )*
-                _ => Err("uknown mac command")
+                _ => Err(Error::UnknownMacCommand)
             }
         }
     }
@@ -166,15 +212,15 @@ macro_rules! mac_cmds {
 
             impl<'a> $type<'a> {
                 /// Creates a new instance of the mac command if there is enought data.
-                pub fn new<'b>(data: &'a [u8]) -> Result<$type<'a>, &'b str> {
+                pub fn new(data: &'a [u8]) -> Result<$type<'a>, Error> {
                     if data.len() < $size {
-                        Err("incorrect size for")
+                        Err(Error::InvalidMacCommand)
                     } else {
                         Ok($type(&data))
                     }
                 }
 
-                pub fn new_as_mac_cmd<'b>(data: &'a [u8]) -> Result<(MacCommand<'a>, usize), &'b str> {
+                pub fn new_as_mac_cmd(data: &'a [u8]) -> Result<(MacCommand<'a>, usize), Error> {
                     Ok((MacCommand::$name($type::new(data)?), $size))
                 }
 
@@ -195,7 +241,7 @@ macro_rules! mac_cmds {
             }
         )*
 
-        fn parse_one_mac_cmd<'a, 'b>(data: &'a [u8], uplink: bool) -> Result<(usize, MacCommand<'a>), &'b str> {
+        fn parse_one_mac_cmd<'a>(data: &'a [u8], uplink: bool) -> Result<(usize, MacCommand<'a>), Error> {
             match (data[0], uplink) {
                 $(
                     ($cid, $uplink) if data.len() > $size => Ok(($size, MacCommand::$name($type::new(&data[1.. 1 + $size])?))),
@@ -222,6 +268,16 @@ mac_cmd_zero_len! {
     /// RXTimingSetupAnsPayload represents the RXTimingSetupAns LoRaWAN MACCommand.
     #[derive(Debug, PartialEq)]
     struct RXTimingSetupAnsPayload[cmd=RXTimingSetupAns, cid=0x08, uplink=true]
+
+    /// PingSlotInfoAnsPayload represents the PingSlotInfoAns LoRaWAN MACCommand.
+    #[derive(Debug, PartialEq)]
+    struct PingSlotInfoAnsPayload[cmd=PingSlotInfoAns, cid=0x10, uplink=false]
+
+    /// BeaconTimingReqPayload represents the BeaconTimingReq LoRaWAN MACCommand.
+    ///
+    /// Note: this command is deprecated since LoRaWAN 1.0.3.
+    #[derive(Debug, PartialEq)]
+    struct BeaconTimingReqPayload[cmd=BeaconTimingReq, cid=0x12, uplink=true]
 }
 
 mac_cmds! {
@@ -264,6 +320,44 @@ mac_cmds! {
     /// RXTimingSetupReqPayload represents the RXTimingSetupReq LoRaWAN MACCommand.
     #[derive(Debug, PartialEq)]
     struct RXTimingSetupReqPayload[cmd=RXTimingSetupReq, cid=0x08, uplink=false, size=1]
+
+    /// RekeyIndPayload represents the RekeyInd LoRaWAN MACCommand.
+    ///
+    /// Note: this command is available since LoRaWAN 1.1.
+    #[derive(Debug, PartialEq)]
+    struct RekeyIndPayload[cmd=RekeyInd, cid=0x0b, uplink=true, size=1]
+
+    /// RekeyConfPayload represents the RekeyConf LoRaWAN MACCommand.
+    ///
+    /// Note: this command is available since LoRaWAN 1.1.
+    #[derive(Debug, PartialEq)]
+    struct RekeyConfPayload[cmd=RekeyConf, cid=0x0b, uplink=false, size=1]
+
+    /// PingSlotInfoReqPayload represents the PingSlotInfoReq LoRaWAN MACCommand.
+    #[derive(Debug, PartialEq)]
+    struct PingSlotInfoReqPayload[cmd=PingSlotInfoReq, cid=0x10, uplink=true, size=1]
+
+    /// PingSlotChannelReqPayload represents the PingSlotChannelReq LoRaWAN MACCommand.
+    #[derive(Debug, PartialEq)]
+    struct PingSlotChannelReqPayload[cmd=PingSlotChannelReq, cid=0x11, uplink=false, size=4]
+
+    /// PingSlotChannelAnsPayload represents the PingSlotChannelAns LoRaWAN MACCommand.
+    #[derive(Debug, PartialEq)]
+    struct PingSlotChannelAnsPayload[cmd=PingSlotChannelAns, cid=0x11, uplink=true, size=1]
+
+    /// BeaconTimingAnsPayload represents the BeaconTimingAns LoRaWAN MACCommand.
+    ///
+    /// Note: this command is deprecated since LoRaWAN 1.0.3.
+    #[derive(Debug, PartialEq)]
+    struct BeaconTimingAnsPayload[cmd=BeaconTimingAns, cid=0x12, uplink=false, size=3]
+
+    /// BeaconFreqReqPayload represents the BeaconFreqReq LoRaWAN MACCommand.
+    #[derive(Debug, PartialEq)]
+    struct BeaconFreqReqPayload[cmd=BeaconFreqReq, cid=0x13, uplink=false, size=3]
+
+    /// BeaconFreqAnsPayload represents the BeaconFreqAns LoRaWAN MACCommand.
+    #[derive(Debug, PartialEq)]
+    struct BeaconFreqAnsPayload[cmd=BeaconFreqAns, cid=0x13, uplink=true, size=1]
 }
 
 macro_rules! create_ack_fn {
@@ -293,7 +387,8 @@ macro_rules! create_value_reader_fn {
 /// Parses bytes to mac commands if possible.
 ///
 /// Could return error if some values are out of range or the payload does not end at mac command
-/// boundry.
+/// boundry. Proprietary mac commands are only recognized if the iterator is configured using
+/// [with_proprietary](struct.MacCommandIterator.html#method.with_proprietary).
 /// # Argument
 ///
 /// * bytes - the data from which the MAC commands are to be built.
@@ -311,6 +406,29 @@ pub fn parse_mac_commands(data: &[u8], uplink: bool) -> MacCommandIterator {
         index: 0,
         data,
         uplink,
+        proprietary: None,
+    }
+}
+
+/// Provides the payload lengths of the proprietary mac commands (CIDs 0x80 to 0xFF) known to the
+/// user, so that they can be parsed.
+///
+/// It is implemented for every `Fn(u8, bool) -> Option<usize>`, so a closure or a function can
+/// be used directly.
+pub trait ProprietaryMacCommands {
+    /// Gives the length of the payload (without the CID) of the proprietary mac command with the
+    /// given cid, or None if the mac command is unknown.
+    ///
+    /// # Argument
+    ///
+    /// * cid - the command identifier, between 0x80 and 0xFF.
+    /// * uplink - whether the packet is uplink or downlink.
+    fn payload_len(&self, cid: u8, uplink: bool) -> Option<usize>;
+}
+
+impl<F: Fn(u8, bool) -> Option<usize>> ProprietaryMacCommands for F {
+    fn payload_len(&self, cid: u8, uplink: bool) -> Option<usize> {
+        self(cid, uplink)
     }
 }
 
@@ -319,6 +437,48 @@ pub struct MacCommandIterator<'a> {
     data: &'a [u8],
     index: usize,
     uplink: bool,
+    proprietary: Option<&'a dyn ProprietaryMacCommands>,
+}
+
+impl<'a> MacCommandIterator<'a> {
+    /// Makes the iterator also yield the proprietary mac commands known to `proprietary`.
+    ///
+    /// # Argument
+    ///
+    /// * proprietary - provides the lengths of the supported proprietary mac commands.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lorawan_encoding::maccommands::{parse_mac_commands, MacCommand};
+    ///
+    /// let data = vec![0x80, 0x01, 0x02, 0x02];
+    /// let lengths = |cid, uplink| match (cid, uplink) {
+    ///     (0x80, true) => Some(2),
+    ///     _ => None,
+    /// };
+    /// let mac_cmds: Vec<MacCommand> = parse_mac_commands(&data[..], true)
+    ///     .with_proprietary(&lengths)
+    ///     .collect();
+    /// assert_eq!(mac_cmds.len(), 2);
+    /// ```
+    pub fn with_proprietary(mut self, proprietary: &'a dyn ProprietaryMacCommands) -> Self {
+        self.proprietary = Some(proprietary);
+        self
+    }
+
+    fn parse_proprietary(&self, data: &'a [u8]) -> Option<(usize, MacCommand<'a>)> {
+        let cid = data[0];
+        if cid < PROPRIETARY_CID_MIN {
+            return None;
+        }
+        let len = self.proprietary?.payload_len(cid, self.uplink)?;
+        if data.len() <= len {
+            return None;
+        }
+        let payload = ProprietaryPayload::new(cid, &data[1..1 + len]).ok()?;
+        Some((len, MacCommand::Proprietary(payload)))
+    }
 }
 
 impl<'a> Iterator for MacCommandIterator<'a> {
@@ -326,7 +486,11 @@ impl<'a> Iterator for MacCommandIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.index < self.data.len() {
-            if let Ok((l, v)) = parse_one_mac_cmd(&self.data[self.index..], self.uplink) {
+            let data = &self.data[self.index..];
+            let parsed = parse_one_mac_cmd(data, self.uplink)
+                .ok()
+                .or_else(|| self.parse_proprietary(data));
+            if let Some((l, v)) = parsed {
                 self.index += 1 + l;
                 return Some(v);
             }
@@ -335,6 +499,39 @@ impl<'a> Iterator for MacCommandIterator<'a> {
     }
 }
 
+const PROPRIETARY_CID_MIN: u8 = 0x80;
+
+/// ProprietaryPayload represents a proprietary LoRaWAN MACCommand (CIDs 0x80 to 0xFF).
+#[derive(Debug, PartialEq)]
+pub struct ProprietaryPayload<'a>(u8, &'a [u8]);
+
+impl<'a> ProprietaryPayload<'a> {
+    /// Creates a new instance of the proprietary mac command if the cid is in the proprietary
+    /// range.
+    pub fn new(cid: u8, data: &'a [u8]) -> Result<ProprietaryPayload<'a>, Error> {
+        if cid < PROPRIETARY_CID_MIN {
+            return Err(Error::InvalidMacCommand);
+        }
+        Ok(ProprietaryPayload(cid, data))
+    }
+
+    /// Command identifier.
+    pub fn cid(&self) -> u8 {
+        self.0
+    }
+
+    /// The raw bytes of the payload of the mac command without the cid.
+    pub fn payload(&self) -> &'a [u8] {
+        self.1
+    }
+
+    /// Length of the payload of the mac command.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.1.len()
+    }
+}
+
 impl<'a> LinkCheckAnsPayload<'a> {
     create_value_reader_fn!(
         /// The link margin in dB of the last successfully received LinkCheckReq command.
@@ -384,9 +581,9 @@ pub struct ChannelMask([u8; 2]);
 
 impl ChannelMask {
     /// Constructs a new ChannelMask from the provided data.
-    pub fn new(data: &[u8]) -> Result<Self, &str> {
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
         if data.len() < 2 {
-            return Err("not enough bytes to read");
+            return Err(Error::TooShort);
         }
         Ok(Self::new_from_raw(data))
     }
@@ -405,9 +602,9 @@ impl ChannelMask {
     }
 
     /// Verifies if a given channel is enabled.
-    pub fn is_enabled(&self, index: usize) -> Result<bool, &str> {
+    pub fn is_enabled(&self, index: usize) -> Result<bool, Error> {
         if index > 15 {
-            return Err("index should be between 0 and 15");
+            return Err(Error::ValueOutOfRange);
         }
         Ok(self.channel_enabled(index))
     }
@@ -526,6 +723,13 @@ impl DLSettings {
         DLSettings(byte)
     }
 
+    /// Whether the network server implements LoRaWAN 1.1 or later (OptNeg bit).
+    ///
+    /// Note: this flag is meaningful only in JoinAccept and is RFU in RXParamSetupReq.
+    pub fn opt_neg(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
     /// The offset between the uplink data rate and the downlink data rate used to communicate with
     /// the end-device on the first reception slot (RX1).
     pub fn rx1_dr_offset(&self) -> u8 {
@@ -661,7 +865,7 @@ impl DataRateRange {
     }
 
     /// Constructs a new DataRateRange from the provided byte.
-    pub fn new(byte: u8) -> Result<DataRateRange, &'static str> {
+    pub fn new(byte: u8) -> Result<DataRateRange, Error> {
         if let Err(err) = Self::can_build_from(byte) {
             return Err(err);
         }
@@ -670,9 +874,9 @@ impl DataRateRange {
     }
 
     /// Check if the byte can be used to create DataRateRange.
-    pub fn can_build_from(byte: u8) -> Result<(), &'static str> {
+    pub fn can_build_from(byte: u8) -> Result<(), Error> {
         if (byte >> 4) < (byte & 0x0f) {
-            return Err("data rate range can not have max data rate smaller than min data rate");
+            return Err(Error::InvalidDataRateRange);
         }
         Ok(())
     }
@@ -724,3 +928,84 @@ impl<'a> RXTimingSetupReqPayload<'a> {
         self.0[0] & 0x0f
     }
 }
+
+impl<'a> PingSlotInfoReqPayload<'a> {
+    /// Periodicity of the ping slots, the device opens a ping slot every `2^periodicity` seconds.
+    pub fn periodicity(&self) -> u8 {
+        self.0[0] & 0x07
+    }
+}
+
+impl<'a> PingSlotChannelReqPayload<'a> {
+    /// The frequency used for the ping slots, 0 meaning the default frequency hopping.
+    pub fn frequency(&self) -> Frequency<'_> {
+        Frequency::new_from_raw(&self.0[0..3])
+    }
+
+    /// The data rate used for the ping slots.
+    pub fn data_rate(&self) -> u8 {
+        self.0[3] & 0x0f
+    }
+}
+
+impl<'a> PingSlotChannelAnsPayload<'a> {
+    create_ack_fn!(
+        /// Whether the channel frequency change was applied successsfully.
+        channel_freq_ack,
+        0
+    );
+
+    create_ack_fn!(
+        /// Whether the data rate change was applied successsfully.
+        data_rate_ack,
+        1
+    );
+
+    /// Whether the device has accepted the new ping slot parameters.
+    pub fn ack(&self) -> bool {
+        self.0[0] == 0x03
+    }
+}
+
+impl<'a> BeaconTimingAnsPayload<'a> {
+    /// The delay in units of 30ms between the end of the RX window of the downlink carrying the
+    /// command and the start of the next beacon.
+    pub fn delay(&self) -> u16 {
+        (u16::from(self.0[1]) << 8) | u16::from(self.0[0])
+    }
+
+    /// The index of the beaconing channel on which the next beacon will be broadcast.
+    pub fn channel(&self) -> u8 {
+        self.0[2]
+    }
+}
+
+impl<'a> BeaconFreqReqPayload<'a> {
+    /// The frequency on which the beacons are to be received, 0 meaning the default beacon
+    /// frequency.
+    pub fn frequency(&self) -> Frequency<'_> {
+        Frequency::new_from_raw(&self.0[0..3])
+    }
+}
+
+impl<'a> RekeyIndPayload<'a> {
+    /// The minor version of LoRaWAN supported by the device (1 for LoRaWAN 1.1).
+    pub fn minor_version(&self) -> u8 {
+        self.0[0] & 0x0f
+    }
+}
+
+impl<'a> RekeyConfPayload<'a> {
+    /// The minor version of LoRaWAN selected by the network server (1 for LoRaWAN 1.1).
+    pub fn minor_version(&self) -> u8 {
+        self.0[0] & 0x0f
+    }
+}
+
+impl<'a> BeaconFreqAnsPayload<'a> {
+    create_ack_fn!(
+        /// Whether the beacon frequency change was applied successsfully.
+        beacon_freq_ack,
+        0
+    );
+}
@@ -28,8 +28,11 @@
 //! }
 //! ```
 
+use super::error::Error;
 use super::keys::{CryptoFactory, Encrypter, AES128, MIC};
-use super::maccommands::{parse_mac_commands, DLSettings, Frequency, MacCommandIterator};
+use super::maccommands::{
+    parse_mac_commands, ChannelMask, DLSettings, Frequency, MacCommandIterator,
+};
 use super::securityhelpers;
 use super::securityhelpers::generic_array::GenericArray;
 
@@ -108,12 +111,14 @@ macro_rules! fixed_len_struct {
 
 /// PhyPayload is a type that represents a physical LoRaWAN payload.
 ///
-/// It can either be JoinRequest, JoinAccept, or DataPayload.
+/// It can either be JoinRequest, JoinAccept, RejoinRequest, DataPayload or Proprietary.
 #[derive(Debug, PartialEq)]
 pub enum PhyPayload<T, F> {
     JoinRequest(JoinRequestPayload<T, F>),
     JoinAccept(JoinAcceptPayload<T, F>),
+    RejoinRequest(RejoinRequestPayload<T, F>),
     Data(DataPayload<T, F>),
+    Proprietary(ProprietaryPhyPayload<T>),
 }
 
 impl<T: AsRef<[u8]>, F> AsRef<[u8]> for PhyPayload<T, F> {
@@ -121,7 +126,9 @@ impl<T: AsRef<[u8]>, F> AsRef<[u8]> for PhyPayload<T, F> {
         match self {
             PhyPayload::JoinRequest(jr) => jr.as_bytes(),
             PhyPayload::JoinAccept(ja) => ja.as_bytes(),
+            PhyPayload::RejoinRequest(rr) => rr.as_bytes(),
             PhyPayload::Data(data) => data.as_bytes(),
+            PhyPayload::Proprietary(p) => p.as_ref(),
         }
     }
 }
@@ -234,16 +241,14 @@ impl<T: AsRef<[u8]>, F: CryptoFactory> JoinRequestPayload<T, F> {
     /// let phy = lorawan_encoding::parser::JoinRequestPayload::new_with_factory(data,
     ///     lorawan_encoding::default_crypto::DefaultFactory);
     /// ```
-    pub fn new_with_factory<'a>(data: T, factory: F) -> Result<Self, &'a str> {
-        if !Self::can_build_from(data.as_ref()) {
-            Err("can not build JoinRequestPayload from the provided data")
-        } else {
-            Ok(Self(data, factory))
-        }
+    pub fn new_with_factory(data: T, factory: F) -> Result<Self, Error> {
+        Self::can_build_from(data.as_ref())?;
+        Ok(Self(data, factory))
     }
 
-    fn can_build_from(bytes: &[u8]) -> bool {
-        bytes.len() == 23 && MHDR(bytes[0]).mtype() == MType::JoinRequest
+    fn can_build_from(bytes: &[u8]) -> Result<(), Error> {
+        check_mtype(bytes, MType::JoinRequest)?;
+        check_len(bytes.len() == 23)
     }
 
     /// Gives the APP EUI of the JoinRequest.
@@ -272,6 +277,234 @@ impl<T: AsRef<[u8]>, F: CryptoFactory> JoinRequestPayload<T, F> {
     }
 }
 
+/// RejoinRequestPayload is a type that represents a RejoinRequest.
+///
+/// Type 0 and type 2 RejoinRequests share the same layout, while type 1 RejoinRequest carries
+/// the JoinEUI instead of the NetID.
+#[derive(Debug, PartialEq)]
+pub enum RejoinRequestPayload<T, F> {
+    Type02(RejoinRequestType02Payload<T, F>),
+    Type1(RejoinRequestType1Payload<T, F>),
+}
+
+impl<T: AsRef<[u8]>, F> AsPhyPayloadBytes for RejoinRequestPayload<T, F> {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            RejoinRequestPayload::Type02(rr) => rr.as_bytes(),
+            RejoinRequestPayload::Type1(rr) => rr.as_bytes(),
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>, F: CryptoFactory> RejoinRequestPayload<T, F> {
+    /// Creates a new RejoinRequestPayload if the provided data is acceptable.
+    ///
+    /// # Argument
+    ///
+    /// * data - the bytes for the payload.
+    /// * factory - the factory that shall be used to create object for crypto functions.
+    pub fn new_with_factory(data: T, factory: F) -> Result<Self, Error> {
+        let bytes = data.as_ref();
+        if bytes.len() < 2 {
+            return Err(Error::TooShort);
+        }
+        match bytes[1] {
+            0 | 2 => Ok(RejoinRequestPayload::Type02(
+                RejoinRequestType02Payload::new_with_factory(data, factory)?,
+            )),
+            1 => Ok(RejoinRequestPayload::Type1(
+                RejoinRequestType1Payload::new_with_factory(data, factory)?,
+            )),
+            _ => Err(Error::UnsupportedRejoinType),
+        }
+    }
+}
+
+/// RejoinRequestType02Payload represents a RejoinRequest of type 0 or 2.
+///
+/// It can be built either directly through the [new](#method.new) or using the
+/// [parse](fn.parse.html) function.
+#[derive(Debug, PartialEq)]
+pub struct RejoinRequestType02Payload<T, F>(T, F);
+
+impl<T: AsRef<[u8]>, F> AsPhyPayloadBytes for RejoinRequestType02Payload<T, F> {
+    fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl<T: AsRef<[u8]>, F: CryptoFactory> RejoinRequestType02Payload<T, F> {
+    /// Creates a new RejoinRequestType02Payload if the provided data is acceptable.
+    ///
+    /// # Argument
+    ///
+    /// * data - the bytes for the payload.
+    /// * factory - the factory that shall be used to create object for crypto functions.
+    pub fn new_with_factory(data: T, factory: F) -> Result<Self, Error> {
+        Self::can_build_from(data.as_ref())?;
+        Ok(Self(data, factory))
+    }
+
+    fn can_build_from(bytes: &[u8]) -> Result<(), Error> {
+        check_mtype(bytes, MType::RejoinRequest)?;
+        check_len(bytes.len() == 19)?;
+        if bytes[1] != 0 && bytes[1] != 2 {
+            return Err(Error::UnsupportedRejoinType);
+        }
+        Ok(())
+    }
+
+    /// Gives the type of the RejoinRequest, either 0 or 2.
+    pub fn rejoin_type(&self) -> u8 {
+        self.0.as_ref()[1]
+    }
+
+    /// Gives the net ID of the RejoinRequest.
+    pub fn net_id(&self) -> NwkAddr<&[u8]> {
+        NwkAddr::new_from_raw(&self.0.as_ref()[2..5])
+    }
+
+    /// Gives the DEV EUI of the RejoinRequest.
+    pub fn dev_eui(&self) -> EUI64<&[u8]> {
+        EUI64::new_from_raw(&self.0.as_ref()[5..13])
+    }
+
+    /// Gives the RJcount0 of the RejoinRequest.
+    pub fn rj_count0(&self) -> u16 {
+        let d = self.0.as_ref();
+        (u16::from(d[14]) << 8) | u16::from(d[13])
+    }
+
+    /// Verifies that the RejoinRequest has correct MIC.
+    ///
+    /// # Argument
+    ///
+    /// * key - the SNwkSIntKey of the device.
+    pub fn validate_mic(&self, key: &AES128) -> bool {
+        self.mic() == self.calculate_mic(key)
+    }
+
+    fn calculate_mic(&self, key: &AES128) -> MIC {
+        let d = self.0.as_ref();
+        securityhelpers::calculate_mic(&d[..d.len() - 4], self.1.new_mac(key))
+    }
+}
+
+/// RejoinRequestType1Payload represents a RejoinRequest of type 1.
+///
+/// It can be built either directly through the [new](#method.new) or using the
+/// [parse](fn.parse.html) function.
+#[derive(Debug, PartialEq)]
+pub struct RejoinRequestType1Payload<T, F>(T, F);
+
+impl<T: AsRef<[u8]>, F> AsPhyPayloadBytes for RejoinRequestType1Payload<T, F> {
+    fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl<T: AsRef<[u8]>, F: CryptoFactory> RejoinRequestType1Payload<T, F> {
+    /// Creates a new RejoinRequestType1Payload if the provided data is acceptable.
+    ///
+    /// # Argument
+    ///
+    /// * data - the bytes for the payload.
+    /// * factory - the factory that shall be used to create object for crypto functions.
+    pub fn new_with_factory(data: T, factory: F) -> Result<Self, Error> {
+        Self::can_build_from(data.as_ref())?;
+        Ok(Self(data, factory))
+    }
+
+    fn can_build_from(bytes: &[u8]) -> Result<(), Error> {
+        check_mtype(bytes, MType::RejoinRequest)?;
+        check_len(bytes.len() == 24)?;
+        if bytes[1] != 1 {
+            return Err(Error::UnsupportedRejoinType);
+        }
+        Ok(())
+    }
+
+    /// Gives the type of the RejoinRequest, always 1.
+    pub fn rejoin_type(&self) -> u8 {
+        self.0.as_ref()[1]
+    }
+
+    /// Gives the JOIN EUI of the RejoinRequest.
+    pub fn join_eui(&self) -> EUI64<&[u8]> {
+        EUI64::new_from_raw(&self.0.as_ref()[2..10])
+    }
+
+    /// Gives the DEV EUI of the RejoinRequest.
+    pub fn dev_eui(&self) -> EUI64<&[u8]> {
+        EUI64::new_from_raw(&self.0.as_ref()[10..18])
+    }
+
+    /// Gives the RJcount1 of the RejoinRequest.
+    pub fn rj_count1(&self) -> u16 {
+        let d = self.0.as_ref();
+        (u16::from(d[19]) << 8) | u16::from(d[18])
+    }
+
+    /// Verifies that the RejoinRequest has correct MIC.
+    ///
+    /// # Argument
+    ///
+    /// * key - the JSIntKey of the device.
+    pub fn validate_mic(&self, key: &AES128) -> bool {
+        self.mic() == self.calculate_mic(key)
+    }
+
+    fn calculate_mic(&self, key: &AES128) -> MIC {
+        let d = self.0.as_ref();
+        securityhelpers::calculate_mic(&d[..d.len() - 4], self.1.new_mac(key))
+    }
+}
+
+/// ProprietaryPhyPayload represents a frame with MType Proprietary.
+///
+/// The format of such frames is vendor specific, thus only the MHDR is interpreted and the rest
+/// of the bytes are given as they are. In particular no MIC is assumed to be present.
+///
+/// It can be built either directly through the [new](#method.new) or using the
+/// [parse](fn.parse.html) function.
+#[derive(Debug, PartialEq)]
+pub struct ProprietaryPhyPayload<T>(T);
+
+impl<T: AsRef<[u8]>> AsRef<[u8]> for ProprietaryPhyPayload<T> {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl<T: AsRef<[u8]>> ProprietaryPhyPayload<T> {
+    /// Creates a new ProprietaryPhyPayload if the provided data is acceptable.
+    ///
+    /// # Argument
+    ///
+    /// * data - the bytes for the payload.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let data = vec![0xe0, 0x01, 0x02, 0x03];
+    /// let phy = lorawan_encoding::parser::ProprietaryPhyPayload::new(data);
+    /// ```
+    pub fn new(data: T) -> Result<Self, Error> {
+        check_mtype(data.as_ref(), MType::Proprietary)?;
+        Ok(Self(data))
+    }
+
+    /// Gives the MHDR of the ProprietaryPhyPayload.
+    pub fn mhdr(&self) -> MHDR {
+        MHDR(self.0.as_ref()[0])
+    }
+
+    /// Gives the bytes following the MHDR without any interpretation.
+    pub fn payload(&self) -> &[u8] {
+        &self.0.as_ref()[1..]
+    }
+}
+
 /// EncryptedJoinAcceptPayload represents an encrypted JoinAccept.
 ///
 /// It can be built either directly through the [new](#method.new) or using the
@@ -292,16 +525,14 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>, F: CryptoFactory> EncryptedJoinAcceptPayload<
     ///
     /// * data - the bytes for the payload.
     /// * factory - the factory that shall be used to create object for crypto functions.
-    pub fn new_with_factory<'a>(data: T, factory: F) -> Result<Self, &'a str> {
-        if Self::can_build_from(data.as_ref()) {
-            Ok(Self(data, factory))
-        } else {
-            Err("can not build EncryptedJoinAcceptPayload from the provided data")
-        }
+    pub fn new_with_factory(data: T, factory: F) -> Result<Self, Error> {
+        Self::can_build_from(data.as_ref())?;
+        Ok(Self(data, factory))
     }
 
-    fn can_build_from(bytes: &[u8]) -> bool {
-        (bytes.len() == 17 || bytes.len() == 33) && MHDR(bytes[0]).mtype() == MType::JoinAccept
+    fn can_build_from(bytes: &[u8]) -> Result<(), Error> {
+        check_mtype(bytes, MType::JoinAccept)?;
+        check_len(bytes.len() == 17 || bytes.len() == 33)
     }
 
     /// Decrypts the EncryptedJoinAcceptPayload producing a DecryptedJoinAcceptPayload.
@@ -363,6 +594,44 @@ impl<T: AsRef<[u8]>, F: CryptoFactory> DecryptedJoinAcceptPayload<T, F> {
         securityhelpers::calculate_mic(&d[..d.len() - 4], self.1.new_mac(key))
     }
 
+    /// Verifies that the LoRaWAN 1.1 JoinAccept (with OptNeg set) has correct MIC.
+    ///
+    /// # Argument
+    ///
+    /// * key - the JSIntKey of the device.
+    /// * join_req_type - 0xff for JoinRequest, or the type of the RejoinRequest.
+    /// * join_eui - the JoinEUI of the device.
+    /// * dev_nonce - the DevNonce of the JoinRequest (or the RJcount of the RejoinRequest).
+    pub fn validate_mic_v1_1<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        key: &AES128,
+        join_req_type: u8,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+    ) -> bool {
+        self.mic() == self.calculate_mic_v1_1(key, join_req_type, join_eui, dev_nonce)
+    }
+
+    /// Computes the MIC of a LoRaWAN 1.1 JoinAccept (with OptNeg set).
+    ///
+    /// For the arguments see [validate_mic_v1_1](#method.validate_mic_v1_1).
+    pub fn calculate_mic_v1_1<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        key: &AES128,
+        join_req_type: u8,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+    ) -> MIC {
+        let d = self.0.as_ref();
+        securityhelpers::calculate_join_accept_mic_v1_1(
+            &d[..d.len() - 4],
+            self.1.new_mac(key),
+            join_req_type,
+            join_eui.as_ref(),
+            dev_nonce.as_ref(),
+        )
+    }
+
     /// Computes the network session key for a given device.
     ///
     /// # Argument
@@ -427,6 +696,87 @@ impl<T: AsRef<[u8]>, F: CryptoFactory> DecryptedJoinAcceptPayload<T, F> {
         self.derive_session_key(0x2, dev_nonce, key)
     }
 
+    /// Computes the LoRaWAN 1.1 forwarding network session integrity key for a given device.
+    ///
+    /// # Argument
+    ///
+    /// * join_eui - the JoinEUI of the device.
+    /// * dev_nonce - the nonce from the device.
+    /// * key - the NwkKey.
+    pub fn derive_fnwksintkey<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+        key: &AES128,
+    ) -> AES128 {
+        self.derive_session_key_v1_1(0x1, join_eui, dev_nonce, key)
+    }
+
+    /// Computes the LoRaWAN 1.1 serving network session integrity key for a given device.
+    ///
+    /// # Argument
+    ///
+    /// * join_eui - the JoinEUI of the device.
+    /// * dev_nonce - the nonce from the device.
+    /// * key - the NwkKey.
+    pub fn derive_snwksintkey<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+        key: &AES128,
+    ) -> AES128 {
+        self.derive_session_key_v1_1(0x3, join_eui, dev_nonce, key)
+    }
+
+    /// Computes the LoRaWAN 1.1 network session encryption key for a given device.
+    ///
+    /// # Argument
+    ///
+    /// * join_eui - the JoinEUI of the device.
+    /// * dev_nonce - the nonce from the device.
+    /// * key - the NwkKey.
+    pub fn derive_nwksenckey<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+        key: &AES128,
+    ) -> AES128 {
+        self.derive_session_key_v1_1(0x4, join_eui, dev_nonce, key)
+    }
+
+    /// Computes the LoRaWAN 1.1 application session key for a given device.
+    ///
+    /// # Argument
+    ///
+    /// * join_eui - the JoinEUI of the device.
+    /// * dev_nonce - the nonce from the device.
+    /// * key - the AppKey.
+    pub fn derive_appskey_v1_1<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+        key: &AES128,
+    ) -> AES128 {
+        self.derive_session_key_v1_1(0x2, join_eui, dev_nonce, key)
+    }
+
+    fn derive_session_key_v1_1<TE: AsRef<[u8]>, TT: AsRef<[u8]>>(
+        &self,
+        first_byte: u8,
+        join_eui: &EUI64<TE>,
+        dev_nonce: &DevNonce<TT>,
+        key: &AES128,
+    ) -> AES128 {
+        // note: JoinNonce has the same position as the AppNonce from LoRaWAN 1.0
+        securityhelpers::derive_session_key_v1_1(
+            first_byte,
+            self.app_nonce().as_ref(),
+            join_eui.as_ref(),
+            dev_nonce.as_ref(),
+            &self.1.new_enc(key),
+        )
+    }
+
     fn derive_session_key<TT: AsRef<[u8]>>(
         &self,
         first_byte: u8,
@@ -467,6 +817,13 @@ impl<T: AsRef<[u8]>, F> DecryptedJoinAcceptPayload<T, F> {
         AppNonce::new_from_raw(&self.0.as_ref()[1..4])
     }
 
+    /// Gives the JoinNonce of the JoinAccept.
+    ///
+    /// This is the LoRaWAN 1.1 name of the [app_nonce](#method.app_nonce) field.
+    pub fn join_nonce(&self) -> AppNonce<&[u8]> {
+        self.app_nonce()
+    }
+
     /// Gives the net ID of the JoinAccept.
     pub fn net_id(&self) -> NwkAddr<&[u8]> {
         NwkAddr::new_from_raw(&self.0.as_ref()[4..7])
@@ -487,20 +844,53 @@ impl<T: AsRef<[u8]>, F> DecryptedJoinAcceptPayload<T, F> {
         self.0.as_ref()[12] & 0x0f
     }
 
-    /// Gives the channel frequency list of the JoinAccept.
-    pub fn c_f_list(&self) -> Option<[Frequency; 5]> {
+    /// Gives the CFList of the JoinAccept if there is one.
+    ///
+    /// The content of the CFList is interpreted according to its CFListType. None is returned
+    /// when the CFListType is not known.
+    pub fn c_f_list(&self) -> Option<CfList> {
         if self.0.as_ref().len() == 17 {
             return None;
         }
         let d = self.0.as_ref();
-        let res = [
-            Frequency::new_from_raw(&d[13..16]),
-            Frequency::new_from_raw(&d[16..19]),
-            Frequency::new_from_raw(&d[19..22]),
-            Frequency::new_from_raw(&d[22..25]),
-            Frequency::new_from_raw(&d[25..28]),
-        ];
-        Some(res)
+        match d[28] {
+            0 => Some(CfList::DynamicChannel([
+                Frequency::new_from_raw(&d[13..16]),
+                Frequency::new_from_raw(&d[16..19]),
+                Frequency::new_from_raw(&d[19..22]),
+                Frequency::new_from_raw(&d[22..25]),
+                Frequency::new_from_raw(&d[25..28]),
+            ])),
+            1 => Some(CfList::FixedChannel([
+                ChannelMask::new_from_raw(&d[13..15]),
+                ChannelMask::new_from_raw(&d[15..17]),
+                ChannelMask::new_from_raw(&d[17..19]),
+                ChannelMask::new_from_raw(&d[19..21]),
+                ChannelMask::new_from_raw(&d[21..23]),
+                ChannelMask::new_from_raw(&d[23..25]),
+            ])),
+            _ => None,
+        }
+    }
+}
+
+/// CfList represents the CFList of a JoinAccept.
+#[derive(Debug, PartialEq)]
+pub enum CfList<'a> {
+    /// List of the frequencies of 5 additional channels (CFListType 0).
+    DynamicChannel([Frequency<'a>; 5]),
+    /// Masks ChMask0 to ChMask5 of the enabled channels, each covering 16 channels (CFListType
+    /// 1). US915 uses ChMask0 to ChMask4 and CN470 all of them, the others being RFU.
+    FixedChannel([ChannelMask; 6]),
+}
+
+impl<'a> CfList<'a> {
+    /// Gives the CFListType of the CFList.
+    pub fn cf_list_type(&self) -> u8 {
+        match self {
+            CfList::DynamicChannel(_) => 0,
+            CfList::FixedChannel(_) => 1,
+        }
     }
 }
 
@@ -515,13 +905,13 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>, F: CryptoFactory> DecryptedJoinAcceptPayload<
     /// * bytes - the data from which the PhyPayload is to be built.
     /// * key - the key that is to be used to decrypt the payload.
     /// * factory - the factory that shall be used to create object for crypto functions.
-    pub fn new_with_factory<'a, 'b>(data: T, key: &'a AES128, factory: F) -> Result<Self, &'b str> {
+    pub fn new_with_factory(data: T, key: &AES128, factory: F) -> Result<Self, Error> {
         let t = EncryptedJoinAcceptPayload::new_with_factory(data, factory)?;
         let res = t.decrypt(key);
         if res.validate_mic(key) {
             Ok(res)
         } else {
-            Err("MIC did not match")
+            Err(Error::MicMismatch)
         }
     }
 }
@@ -549,6 +939,13 @@ pub trait DataHeader {
         mtype == MType::UnconfirmedDataUp || mtype == MType::ConfirmedDataUp
     }
 
+    /// Gives whether the payload is confirmed or not.
+    fn is_confirmed(&self) -> bool {
+        let mtype = MHDR(self.as_data_bytes()[0]).mtype();
+
+        mtype == MType::ConfirmedDataUp || mtype == MType::ConfirmedDataDown
+    }
+
     /// Gives the FPort of the DataPayload if there is one.
     fn f_port(&self) -> Option<u8> {
         let fhdr_length = self.fhdr_length();
@@ -565,6 +962,24 @@ pub trait DataHeader {
     }
 }
 
+fn check_mtype(bytes: &[u8], mtype: MType) -> Result<(), Error> {
+    if bytes.is_empty() {
+        Err(Error::TooShort)
+    } else if MHDR(bytes[0]).mtype() != mtype {
+        Err(Error::InvalidMType)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_len(acceptable: bool) -> Result<(), Error> {
+    if acceptable {
+        Ok(())
+    } else {
+        Err(Error::InvalidLength)
+    }
+}
+
 fn fhdr_length(b: u8) -> usize {
     7 + (b & 0x0f) as usize
 }
@@ -595,29 +1010,30 @@ impl<T: AsRef<[u8]>, F: CryptoFactory> EncryptedDataPayload<T, F> {
     ///
     /// * data - the bytes for the payload.
     /// * factory - the factory that shall be used to create object for crypto functions.
-    pub fn new_with_factory<'a>(data: T, factory: F) -> Result<Self, &'a str> {
-        if Self::can_build_from(data.as_ref()) {
-            Ok(Self(data, factory))
-        } else {
-            Err("can not build EncryptedDataPayload from the provided data")
-        }
+    pub fn new_with_factory(data: T, factory: F) -> Result<Self, Error> {
+        Self::can_build_from(data.as_ref())?;
+        Ok(Self(data, factory))
     }
 
-    fn can_build_from(bytes: &[u8]) -> bool {
-        let has_acceptable_len = bytes.len() >= 12 &&
-            // TODO: Bug related to possibly insufficient number of bytes
-            fhdr_length(bytes[5]) <= bytes.len();
-        if !has_acceptable_len {
-            return false;
+    fn can_build_from(bytes: &[u8]) -> Result<(), Error> {
+        if bytes.is_empty() {
+            return Err(Error::TooShort);
         }
-
-        matches!(
+        let is_data = matches!(
             MHDR(bytes[0]).mtype(),
             MType::ConfirmedDataUp
                 | MType::ConfirmedDataDown
                 | MType::UnconfirmedDataUp
                 | MType::UnconfirmedDataDown
-        )
+        );
+        if !is_data {
+            return Err(Error::InvalidMType);
+        }
+        // TODO: Bug related to possibly insufficient number of bytes
+        if bytes.len() < 12 || fhdr_length(bytes[5]) > bytes.len() {
+            return Err(Error::TooShort);
+        }
+        Ok(())
     }
 
     /// Verifies that the DataPayload has correct MIC.
@@ -629,6 +1045,63 @@ impl<T: AsRef<[u8]>, F: CryptoFactory> EncryptedDataPayload<T, F> {
         let d = self.0.as_ref();
         securityhelpers::calculate_data_mic(&d[..d.len() - 4], self.1.new_mac(key), fcnt)
     }
+
+    /// Verifies that the LoRaWAN 1.1 uplink DataPayload has correct MIC.
+    ///
+    /// # Argument
+    ///
+    /// * f_nwk_s_int_key - the FNwkSIntKey of the device.
+    /// * s_nwk_s_int_key - the SNwkSIntKey of the device.
+    /// * fcnt - the full uplink frame counter.
+    /// * conf_fcnt - the frame counter of the confirmed downlink that is acknowledged (only used
+    ///   if the ACK bit is set).
+    /// * tx_dr - the data rate used for the uplink.
+    /// * tx_ch - the index of the channel used for the uplink.
+    pub fn validate_uplink_mic_v1_1(
+        &self,
+        f_nwk_s_int_key: &AES128,
+        s_nwk_s_int_key: &AES128,
+        fcnt: u32,
+        conf_fcnt: u16,
+        tx_dr: u8,
+        tx_ch: u8,
+    ) -> bool {
+        let d = self.0.as_ref();
+        self.mic()
+            == securityhelpers::calculate_uplink_data_mic_v1_1(
+                &d[..d.len() - 4],
+                self.1.new_mac(f_nwk_s_int_key),
+                self.1.new_mac(s_nwk_s_int_key),
+                fcnt,
+                conf_fcnt,
+                tx_dr,
+                tx_ch,
+            )
+    }
+
+    /// Verifies that the LoRaWAN 1.1 downlink DataPayload has correct MIC.
+    ///
+    /// # Argument
+    ///
+    /// * s_nwk_s_int_key - the SNwkSIntKey of the device.
+    /// * fcnt - the full downlink frame counter (AFCntDown or NFCntDown).
+    /// * conf_fcnt - the frame counter of the confirmed uplink that is acknowledged (only used if
+    ///   the ACK bit is set).
+    pub fn validate_downlink_mic_v1_1(
+        &self,
+        s_nwk_s_int_key: &AES128,
+        fcnt: u32,
+        conf_fcnt: u16,
+    ) -> bool {
+        let d = self.0.as_ref();
+        self.mic()
+            == securityhelpers::calculate_downlink_data_mic_v1_1(
+                &d[..d.len() - 4],
+                self.1.new_mac(s_nwk_s_int_key),
+                fcnt,
+                conf_fcnt,
+            )
+    }
 }
 
 impl<T: AsRef<[u8]> + AsMut<[u8]>, F: CryptoFactory> EncryptedDataPayload<T, F> {
@@ -659,12 +1132,12 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>, F: CryptoFactory> EncryptedDataPayload<T, F>
     /// let enc_phy = lorawan_encoding::parser::EncryptedDataPayload::new(data).unwrap();
     /// let dec_phy = enc_phy.decrypt(None, Some(&key), 1);
     /// ```
-    pub fn decrypt<'a, 'b>(
+    pub fn decrypt<'a>(
         mut self,
         nwk_skey: Option<&'a AES128>,
         app_skey: Option<&'a AES128>,
         fcnt: u32,
-    ) -> Result<DecryptedDataPayload<T>, &'b str> {
+    ) -> Result<DecryptedDataPayload<T>, Error> {
         let fhdr_length = self.fhdr_length();
         let fhdr = self.fhdr();
         let full_fcnt = compute_fcnt(fcnt, fhdr.fcnt());
@@ -674,7 +1147,7 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>, F: CryptoFactory> EncryptedDataPayload<T, F>
             nwk_skey
         };
         if key.is_none() {
-            return Err("key needed to decrypt the frm data payload was None");
+            return Err(Error::MissingKey);
         }
         let mut data = self.0.as_mut();
         let len = data.len();
@@ -693,6 +1166,46 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>, F: CryptoFactory> EncryptedDataPayload<T, F>
         Ok(DecryptedDataPayload(self.0))
     }
 
+    /// Decrypts the LoRaWAN 1.1 EncryptedDataPayload payload.
+    ///
+    /// Unlike [decrypt](#method.decrypt), this method decrypts the FOpts as well. Both the FOpts
+    /// and the FRMPayload with FPort 0 use the NwkSEncKey. It does not verify the mic.
+    ///
+    /// # Argument
+    ///
+    /// * nwk_s_enc_key - the Network Session Encryption key used to decrypt the FOpts and the mac
+    ///   commands in case the payload is transporting those.
+    /// * app_skey - the Application Session key used to decrypt the application payload in case
+    ///   the payload is transporting that.
+    /// * fcnt - the counter used to encrypt the payload.
+    pub fn decrypt_v1_1<'a>(
+        mut self,
+        nwk_s_enc_key: Option<&'a AES128>,
+        app_skey: Option<&'a AES128>,
+        fcnt: u32,
+    ) -> Result<DecryptedDataPayload<T>, Error> {
+        let fhdr = self.fhdr();
+        let full_fcnt = compute_fcnt(fcnt, fhdr.fcnt());
+        if fhdr.fctrl().f_opts_len() > 0 {
+            if let Some(key) = nwk_s_enc_key {
+                // only downlinks with FPort > 0 use the AFCntDown
+                let a4 = match self.f_port() {
+                    Some(f_port) if !self.is_uplink() && f_port > 0 => 0x02,
+                    _ => 0x01,
+                };
+                securityhelpers::encrypt_f_opts(
+                    self.0.as_mut(),
+                    a4,
+                    full_fcnt,
+                    &self.1.new_enc(key),
+                );
+            } else {
+                return Err(Error::MissingKey);
+            }
+        }
+        self.decrypt(nwk_s_enc_key, app_skey, fcnt)
+    }
+
     /// Verifies the mic and decrypts the EncryptedDataPayload payload if mic matches.
     ///
     /// This is helper method that combines validate_mic and decrypt. In case the mic is fine, it
@@ -718,6 +1231,41 @@ fn compute_fcnt(old_fcnt: u32, fcnt: u16) -> u32 {
     ((old_fcnt >> 16) << 16) ^ u32::from(fcnt)
 }
 
+/// The maximum accepted difference between the expected and the received frame counter.
+pub const MAX_FCNT_GAP: u32 = 16384;
+
+/// Reconstructs the full 32-bit frame counter from the 16 bits transmitted in the FHDR.
+///
+/// The result is the smallest counter not lower than `next_fcnt` whose 16 least significant bits
+/// match `fcnt`. None is returned when it is more than MAX_FCNT_GAP ahead of `next_fcnt`, which
+/// covers replayed frames as well as frames too far in the future.
+///
+/// # Argument
+///
+/// * next_fcnt - the lowest acceptable frame counter, i.e. the last accepted one plus 1 or 0 for
+///   a new session.
+/// * fcnt - the frame counter as found in the FHDR.
+///
+/// # Examples
+///
+/// ```
+/// use lorawan_encoding::parser::restore_fcnt;
+///
+/// assert_eq!(restore_fcnt(0x1_fffe, 0x0001), Some(0x2_0001));
+/// assert_eq!(restore_fcnt(0x1_0005, 0x0004), None);
+/// ```
+pub fn restore_fcnt(next_fcnt: u32, fcnt: u16) -> Option<u32> {
+    let mut full_fcnt = compute_fcnt(next_fcnt, fcnt);
+    if full_fcnt < next_fcnt {
+        full_fcnt = full_fcnt.checked_add(0x1_0000)?;
+    }
+    if full_fcnt - next_fcnt > MAX_FCNT_GAP {
+        None
+    } else {
+        Some(full_fcnt)
+    }
+}
+
 /// DecryptedDataPayload represents a decrypted DataPayload.
 ///
 /// It can be built either directly through the [new](#method.new) or using the
@@ -734,7 +1282,7 @@ impl<T: AsRef<[u8]>> DataHeader for DecryptedDataPayload<T> {
 impl<T: AsRef<[u8]>> DecryptedDataPayload<T> {
     /// Returns FRMPayload that can represent either application payload or mac commands if fport
     /// is 0.
-    pub fn frm_payload(&self) -> Result<FRMPayload, &str> {
+    pub fn frm_payload(&self) -> Result<FRMPayload, Error> {
         let data = self.as_data_bytes();
         let len = data.len();
         let fhdr_length = self.fhdr_length();
@@ -771,9 +1319,9 @@ impl<T: AsRef<[u8]>> DecryptedDataPayload<T> {
 /// }
 /// ```
 #[cfg(feature = "default-crypto")]
-pub fn parse<'a, T: AsRef<[u8]> + AsMut<[u8]>>(
+pub fn parse<T: AsRef<[u8]> + AsMut<[u8]>>(
     data: T,
-) -> Result<PhyPayload<T, DefaultFactory>, &'a str> {
+) -> Result<PhyPayload<T, DefaultFactory>, Error> {
     parse_with_factory(data, DefaultFactory)
 }
 
@@ -787,7 +1335,7 @@ pub fn parse<'a, T: AsRef<[u8]> + AsMut<[u8]>>(
 ///
 /// * bytes - the data from which the PhyPayload is to be built.
 /// * factory - the factory that shall be used to create object for crypto functions.
-pub fn parse_with_factory<'a, T, F>(data: T, factory: F) -> Result<PhyPayload<T, F>, &'a str>
+pub fn parse_with_factory<T, F>(data: T, factory: F) -> Result<PhyPayload<T, F>, Error>
 where
     T: AsRef<[u8]> + AsMut<[u8]>,
     F: CryptoFactory,
@@ -795,35 +1343,42 @@ where
     let bytes = data.as_ref();
     check_phy_data(bytes)?;
     match MHDR(bytes[0]).mtype() {
+        MType::Proprietary => Ok(PhyPayload::Proprietary(ProprietaryPhyPayload::new(data)?)),
         MType::JoinRequest => Ok(PhyPayload::JoinRequest(
             JoinRequestPayload::new_with_factory(data, factory)?,
         )),
         MType::JoinAccept => Ok(PhyPayload::JoinAccept(JoinAcceptPayload::Encrypted(
             EncryptedJoinAcceptPayload::new_with_factory(data, factory)?,
         ))),
+        MType::RejoinRequest => Ok(PhyPayload::RejoinRequest(
+            RejoinRequestPayload::new_with_factory(data, factory)?,
+        )),
         MType::UnconfirmedDataUp
         | MType::ConfirmedDataUp
         | MType::UnconfirmedDataDown
         | MType::ConfirmedDataDown => Ok(PhyPayload::Data(DataPayload::Encrypted(
             EncryptedDataPayload::new_with_factory(data, factory)?,
         ))),
-        _ => Err("unsupported message type"),
     }
 }
 
-fn check_phy_data<'a, 'b>(bytes: &'a [u8]) -> Result<(), &'b str> {
+fn check_phy_data(bytes: &[u8]) -> Result<(), Error> {
     let len = bytes.len();
     if len == 0 {
-        return Err("can not parse empty payload as LoRaWAN phy payload");
+        return Err(Error::TooShort);
     }
     let mhdr = MHDR(bytes[0]);
     if mhdr.major() != Major::LoRaWANR1 {
-        return Err("Unsupported major version");
+        return Err(Error::UnsupportedMajorVersion);
+    }
+    // the length of proprietary payloads is vendor specific.
+    if mhdr.mtype() == MType::Proprietary {
+        return Ok(());
     }
     // the smallest payload is a data payload without fport and FRMPayload
     // which is 12 bytes long.
     if len < 12 {
-        Err("insufficient number of bytes")
+        Err(Error::TooShort)
     } else {
         Ok(())
     }
@@ -847,7 +1402,7 @@ impl MHDR {
             3 => MType::UnconfirmedDataDown,
             4 => MType::ConfirmedDataUp,
             5 => MType::ConfirmedDataDown,
-            6 => MType::RFU,
+            6 => MType::RejoinRequest,
             _ => MType::Proprietary,
         }
     }
@@ -877,7 +1432,7 @@ pub enum MType {
     UnconfirmedDataDown,
     ConfirmedDataUp,
     ConfirmedDataDown,
-    RFU,
+    RejoinRequest,
     Proprietary,
 }
 
@@ -20,6 +20,106 @@ pub fn calculate_data_mic<M: keys::Mac>(data: &[u8], key: M, fcnt: u32) -> keys:
     calculate_mic_with_header(&header[..], data, key)
 }
 
+/// calculate_uplink_data_mic_v1_1 computes the MIC of a correct LoRaWAN 1.1 uplink data packet.
+///
+/// The conf_fcnt is taken into account only if the ACK bit of the packet is set.
+///
+/// # Argument
+///
+/// * data - the packet without the MIC.
+/// * f_nwk_s_int_key - MAC instance using FNwkSIntKey.
+/// * s_nwk_s_int_key - MAC instance using SNwkSIntKey.
+/// * fcnt - the uplink frame counter.
+/// * conf_fcnt - the frame counter of the confirmed downlink being acknowledged.
+/// * tx_dr - the data rate used for the transmission of the packet.
+/// * tx_ch - the index of the channel used for the transmission of the packet.
+pub fn calculate_uplink_data_mic_v1_1<M: keys::Mac>(
+    data: &[u8],
+    f_nwk_s_int_key: M,
+    s_nwk_s_int_key: M,
+    fcnt: u32,
+    conf_fcnt: u16,
+    tx_dr: u8,
+    tx_ch: u8,
+) -> keys::MIC {
+    let mut b0 = [0; 16];
+    generate_helper_block(data, 0x49, fcnt, &mut b0[..]);
+    b0[15] = data.len() as u8;
+
+    let mut b1 = b0;
+    let conf_fcnt = acked_conf_fcnt(data, conf_fcnt);
+    b1[1] = (conf_fcnt & 0xff) as u8;
+    b1[2] = (conf_fcnt >> 8) as u8;
+    b1[3] = tx_dr;
+    b1[4] = tx_ch;
+
+    let cmac_f = calculate_mic_with_header(&b0[..], data, f_nwk_s_int_key);
+    let cmac_s = calculate_mic_with_header(&b1[..], data, s_nwk_s_int_key);
+
+    keys::MIC([cmac_s.0[0], cmac_s.0[1], cmac_f.0[0], cmac_f.0[1]])
+}
+
+/// calculate_downlink_data_mic_v1_1 computes the MIC of a correct LoRaWAN 1.1 downlink data
+/// packet.
+///
+/// The conf_fcnt is taken into account only if the ACK bit of the packet is set.
+///
+/// # Argument
+///
+/// * data - the packet without the MIC.
+/// * s_nwk_s_int_key - MAC instance using SNwkSIntKey.
+/// * fcnt - the downlink frame counter (either AFCntDown or NFCntDown).
+/// * conf_fcnt - the frame counter of the confirmed uplink being acknowledged.
+pub fn calculate_downlink_data_mic_v1_1<M: keys::Mac>(
+    data: &[u8],
+    s_nwk_s_int_key: M,
+    fcnt: u32,
+    conf_fcnt: u16,
+) -> keys::MIC {
+    let mut b0 = [0; 16];
+    generate_helper_block(data, 0x49, fcnt, &mut b0[..]);
+    b0[15] = data.len() as u8;
+    let conf_fcnt = acked_conf_fcnt(data, conf_fcnt);
+    b0[1] = (conf_fcnt & 0xff) as u8;
+    b0[2] = (conf_fcnt >> 8) as u8;
+
+    calculate_mic_with_header(&b0[..], data, s_nwk_s_int_key)
+}
+
+/// calculate_join_accept_mic_v1_1 computes the MIC of a LoRaWAN 1.1 JoinAccept sent to a device
+/// that has the OptNeg bit set.
+///
+/// # Argument
+///
+/// * data - the decrypted JoinAccept without the MIC.
+/// * js_int_key - MAC instance using JSIntKey.
+/// * join_req_type - 0xff for JoinRequest, or the type of the RejoinRequest (0, 1 or 2).
+/// * join_eui - the JoinEUI as in the packet.
+/// * dev_nonce - the DevNonce (or RJcount for RejoinRequest) as in the packet.
+pub fn calculate_join_accept_mic_v1_1<M: keys::Mac>(
+    data: &[u8],
+    js_int_key: M,
+    join_req_type: u8,
+    join_eui: &[u8],
+    dev_nonce: &[u8],
+) -> keys::MIC {
+    let mut header = [0; 11];
+    header[0] = join_req_type;
+    header[1..9].copy_from_slice(&join_eui[..8]);
+    header[9..11].copy_from_slice(&dev_nonce[..2]);
+
+    calculate_mic_with_header(&header[..], data, js_int_key)
+}
+
+fn acked_conf_fcnt(data: &[u8], conf_fcnt: u16) -> u16 {
+    // ACK bit of FCtrl
+    if data[5] & 0x20 != 0 {
+        conf_fcnt
+    } else {
+        0
+    }
+}
+
 fn generate_helper_block(data: &[u8], first: u8, fcnt: u32, res: &mut [u8]) {
     res[0] = first;
     // res[1..5] are 0
@@ -59,10 +159,19 @@ pub fn encrypt_frm_data_payload(
     fcnt: u32,
     aes_enc: &dyn keys::Encrypter,
 ) {
-    let len = end - start;
-
     let mut a = [0u8; 16];
     generate_helper_block(phy_payload, 0x01, fcnt, &mut a[..]);
+    encrypt_with_helper_block(phy_payload, start, end, a, aes_enc);
+}
+
+fn encrypt_with_helper_block(
+    phy_payload: &mut [u8],
+    start: usize,
+    end: usize,
+    mut a: [u8; 16],
+    aes_enc: &dyn keys::Encrypter,
+) {
+    let len = end - start;
 
     let mut s = [0u8; 16];
     let mut s_block = GenericArray::from_mut_slice(&mut s[..]);
@@ -79,3 +188,78 @@ pub fn encrypt_frm_data_payload(
         phy_payload[start + i] ^= s_block[j]
     }
 }
+
+/// encrypt_f_opts encrypts the FOpts of a LoRaWAN 1.1 data packet in place.
+///
+/// As the operation is symmetric, it is used for decryption as well.
+///
+/// # Argument
+///
+/// * phy_payload - the data packet.
+/// * a4 - the byte A[4] of the encryption block, which the LoRaWAN 1.1 errata defines as 0x02
+///   for downlinks with FPort > 0, whose FCnt is the AFCntDown, and 0x01 otherwise.
+/// * fcnt - the frame counter of the packet.
+/// * aes_enc - Encrypter instance using NwkSEncKey.
+pub fn encrypt_f_opts(phy_payload: &mut [u8], a4: u8, fcnt: u32, aes_enc: &dyn keys::Encrypter) {
+    let f_opts_len = (phy_payload[5] & 0x0f) as usize;
+    let mut a = [0u8; 16];
+    generate_helper_block(phy_payload, 0x01, fcnt, &mut a[..]);
+    a[4] = a4;
+    encrypt_with_helper_block(phy_payload, 8, 8 + f_opts_len, a, aes_enc);
+}
+
+/// derive_session_key_v1_1 computes a LoRaWAN 1.1 session key.
+///
+/// # Argument
+///
+/// * first_byte - the identifier of the key that is derived (0x01 for FNwkSIntKey, 0x02 for
+///   AppSKey, 0x03 for SNwkSIntKey and 0x04 for NwkSEncKey).
+/// * join_nonce - the JoinNonce from the JoinAccept as in the packet.
+/// * join_eui - the JoinEUI as in the packet.
+/// * dev_nonce - the DevNonce as in the packet.
+/// * aes_enc - Encrypter instance using NwkKey (AppKey for AppSKey).
+pub fn derive_session_key_v1_1(
+    first_byte: u8,
+    join_nonce: &[u8],
+    join_eui: &[u8],
+    dev_nonce: &[u8],
+    aes_enc: &dyn keys::Encrypter,
+) -> keys::AES128 {
+    let mut block = [0u8; 16];
+    block[0] = first_byte;
+    block[1..4].copy_from_slice(&join_nonce[..3]);
+    block[4..12].copy_from_slice(&join_eui[..8]);
+    block[12..14].copy_from_slice(&dev_nonce[..2]);
+
+    let mut input = block.into();
+    aes_enc.encrypt_block(&mut input);
+
+    let mut output_key = [0u8; 16];
+    output_key.copy_from_slice(&input[0..16]);
+    keys::AES128(output_key)
+}
+
+/// derive_join_server_key_v1_1 computes a LoRaWAN 1.1 key used for JoinAccept messages.
+///
+/// # Argument
+///
+/// * first_byte - the identifier of the key that is derived (0x05 for JSEncKey and 0x06 for
+///   JSIntKey).
+/// * dev_eui - the DevEUI as in the packet.
+/// * aes_enc - Encrypter instance using NwkKey.
+pub fn derive_join_server_key_v1_1(
+    first_byte: u8,
+    dev_eui: &[u8],
+    aes_enc: &dyn keys::Encrypter,
+) -> keys::AES128 {
+    let mut block = [0u8; 16];
+    block[0] = first_byte;
+    block[1..9].copy_from_slice(&dev_eui[..8]);
+
+    let mut input = block.into();
+    aes_enc.encrypt_block(&mut input);
+
+    let mut output_key = [0u8; 16];
+    output_key.copy_from_slice(&input[0..16]);
+    keys::AES128(output_key)
+}
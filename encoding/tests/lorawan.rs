@@ -8,6 +8,7 @@
 
 use lorawan_encoding::creator::*;
 use lorawan_encoding::default_crypto::DefaultFactory;
+use lorawan_encoding::error::Error;
 use lorawan_encoding::keys::*;
 use lorawan_encoding::maccommandcreator::*;
 use lorawan_encoding::maccommands::*;
@@ -22,6 +23,24 @@ fn phy_join_request_payload() -> Vec<u8> {
     res
 }
 
+fn phy_rejoin_request_type0_payload() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0xc0, 0x00, 0x03, 0x02, 0x01, 0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02, 0x01, 0x00,
+        0xac, 0x7e, 0xbc, 0x60,
+    ]);
+    res
+}
+
+fn phy_rejoin_request_type1_payload() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0xc0, 0x01, 0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01, 0x05, 0x04, 0x03, 0x02, 0x05,
+        0x04, 0x03, 0x02, 0x01, 0x00, 0x83, 0xbe, 0xbe, 0x40,
+    ]);
+    res
+}
+
 fn phy_join_accept_payload() -> Vec<u8> {
     let mut res = Vec::new();
     res.extend_from_slice(&[
@@ -42,6 +61,28 @@ fn phy_join_accept_payload_with_c_f_list() -> Vec<u8> {
     //867100000, 867300000, 867500000, 867700000, 867900000
 }
 
+fn phy_join_accept_payload_with_channel_mask_c_f_list() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0x20, 0x09, 0xfc, 0xe8, 0x0f, 0xef, 0x55, 0x4e, 0x34, 0x36, 0x65, 0x53, 0xf7, 0x4f, 0x44,
+        0xb9, 0xa1, 0x86, 0x5c, 0xa5, 0x0e, 0xe0, 0xd8, 0x9e, 0x24, 0x3e, 0x77, 0xe5, 0xf3, 0x83,
+        0xfd, 0x33, 0x60,
+    ]);
+    res
+    // channels 8 to 15 enabled
+}
+
+fn phy_join_accept_payload_with_channel_mask_4_c_f_list() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0x20, 0x09, 0xfc, 0xe8, 0x0f, 0xef, 0x55, 0x4e, 0x34, 0x36, 0x65, 0x53, 0xf7, 0x4f, 0x44,
+        0xb9, 0xa1, 0x3c, 0xff, 0xa4, 0xf7, 0xfb, 0x5e, 0xab, 0x0c, 0x1d, 0x28, 0xdf, 0xad, 0x68,
+        0x26, 0x9c, 0xe5,
+    ]);
+    res
+    // channels 8 to 15 and 65 enabled
+}
+
 fn phy_dataup_payload() -> Vec<u8> {
     let mut res = Vec::new();
     res.extend_from_slice(&[
@@ -51,6 +92,42 @@ fn phy_dataup_payload() -> Vec<u8> {
     res
 }
 
+// the FOpts of the 1.1 payloads are encrypted with A[4] set to 0x02 for downlinks with FPort > 0
+// and 0x01 otherwise, as defined by the LoRaWAN 1.1 errata
+fn phy_dataup_payload_v1_1() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0x40, 0x04, 0x03, 0x02, 0x01, 0x21, 0x01, 0x00, 0x24, 0x01, 0xe1, 0x0b, 0xc9, 0x98, 0xd7,
+        0xcc, 0x06, 0x67, 0xc0,
+    ]);
+    res
+}
+
+fn phy_datadown_payload_v1_1() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0x60, 0x04, 0x03, 0x02, 0x01, 0x21, 0x02, 0x00, 0x4d, 0x01, 0x56, 0x22, 0x88, 0x12, 0xe3,
+        0x88,
+    ]);
+    res
+}
+
+fn phy_datadown_payload_v1_1_without_f_port() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0x60, 0x04, 0x03, 0x02, 0x01, 0x21, 0x03, 0x00, 0x0b, 0xb6, 0x71, 0x42, 0xca,
+    ]);
+    res
+}
+
+fn phy_datadown_payload_v1_1_with_f_port_0() -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&[
+        0x60, 0x04, 0x03, 0x02, 0x01, 0x21, 0x04, 0x00, 0xf6, 0x00, 0xca, 0x52, 0xa8, 0x31, 0x22,
+    ]);
+    res
+}
+
 fn phy_long_dataup_payload() -> Vec<u8> {
     let mut res = Vec::new();
     res.extend_from_slice(&[
@@ -127,7 +204,7 @@ fn test_mhdr_mtype() {
         (0x60, MType::UnconfirmedDataDown),
         (0x80, MType::ConfirmedDataUp),
         (0xa0, MType::ConfirmedDataDown),
-        (0xc0, MType::RFU),
+        (0xc0, MType::RejoinRequest),
         (0xe0, MType::Proprietary),
     ];
     for &(ref v, ref expected) in &examples {
@@ -151,7 +228,7 @@ fn test_parse_phy_payload_with_too_few_bytes_is_err() {
         0x80, 0x04, 0x03, 0x02, 0x01, 0x00, 0xff, 0x01, 0x02, 0x03, 0x04,
     ];
     let phy = parse(bytes);
-    assert!(phy.is_err());
+    assert_eq!(phy.err(), Some(Error::TooShort));
 }
 
 #[test]
@@ -162,7 +239,7 @@ fn test_parse_phy_payload_with_unsupported_major_versoin() {
     let phy = parse(bytes);
 
     // this is now part of the API.
-    assert_eq!(phy.err(), Some("Unsupported major version"));
+    assert_eq!(phy.err(), Some(Error::UnsupportedMajorVersion));
 }
 
 #[test]
@@ -198,6 +275,194 @@ fn test_parse_data_payload() {
     );
 }
 
+#[test]
+fn test_parse_rejoin_request_type0_payload() {
+    let phy = parse(phy_rejoin_request_type0_payload());
+    assert_eq!(
+        phy,
+        Ok(PhyPayload::RejoinRequest(RejoinRequestPayload::Type02(
+            RejoinRequestType02Payload::new(phy_rejoin_request_type0_payload()).unwrap()
+        )))
+    );
+}
+
+#[test]
+fn test_parse_rejoin_request_type1_payload() {
+    let phy = parse(phy_rejoin_request_type1_payload());
+    assert_eq!(
+        phy,
+        Ok(PhyPayload::RejoinRequest(RejoinRequestPayload::Type1(
+            RejoinRequestType1Payload::new(phy_rejoin_request_type1_payload()).unwrap()
+        )))
+    );
+}
+
+#[test]
+fn test_parse_rejoin_request_with_bad_rejoin_type() {
+    let mut data = phy_rejoin_request_type0_payload();
+    data[1] = 3;
+    assert!(parse(data).is_err());
+}
+
+#[test]
+fn test_parse_rejoin_request_with_bad_length() {
+    let mut data = phy_rejoin_request_type0_payload();
+    data[1] = 1;
+    assert!(parse(data).is_err());
+}
+
+#[test]
+fn test_rejoin_request_type0_extraction() {
+    let data = phy_rejoin_request_type0_payload();
+    let rejoin_request = RejoinRequestType02Payload::new(&data[..]).unwrap();
+    assert_eq!(rejoin_request.rejoin_type(), 0);
+    assert_eq!(rejoin_request.net_id(), NwkAddr::new(&data[2..5]).unwrap());
+    assert_eq!(rejoin_request.dev_eui(), EUI64::new(&data[5..13]).unwrap());
+    assert_eq!(rejoin_request.rj_count0(), 1);
+}
+
+#[test]
+fn test_rejoin_request_type1_extraction() {
+    let data = phy_rejoin_request_type1_payload();
+    let rejoin_request = RejoinRequestType1Payload::new(&data[..]).unwrap();
+    assert_eq!(rejoin_request.rejoin_type(), 1);
+    assert_eq!(rejoin_request.join_eui(), EUI64::new(&data[2..10]).unwrap());
+    assert_eq!(rejoin_request.dev_eui(), EUI64::new(&data[10..18]).unwrap());
+    assert_eq!(rejoin_request.rj_count1(), 1);
+}
+
+#[test]
+fn test_validate_rejoin_request_mic() {
+    let type0 = RejoinRequestType02Payload::new(phy_rejoin_request_type0_payload()).unwrap();
+    assert!(type0.validate_mic(&AES128([2; 16])));
+    assert!(!type0.validate_mic(&AES128([3; 16])));
+
+    let type1 = RejoinRequestType1Payload::new(phy_rejoin_request_type1_payload()).unwrap();
+    assert!(type1.validate_mic(&AES128([3; 16])));
+    assert!(!type1.validate_mic(&AES128([2; 16])));
+}
+
+#[test]
+fn test_rejoin_request_type0_creator() {
+    let mut phy = RejoinRequestCreator::new();
+    let key = AES128([2; 16]);
+    phy.set_net_id(&[0x03, 0x02, 0x01])
+        .set_dev_eui(&[0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02])
+        .set_rj_count(1);
+
+    assert_eq!(
+        phy.build(&key).unwrap(),
+        &phy_rejoin_request_type0_payload()[..]
+    );
+}
+
+#[test]
+fn test_rejoin_request_type1_creator() {
+    let mut phy = RejoinRequestCreator::new();
+    let key = AES128([3; 16]);
+    phy.set_rejoin_type(1)
+        .unwrap()
+        .set_join_eui(&[0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01])
+        .set_dev_eui(&[0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02])
+        .set_rj_count(1);
+
+    assert_eq!(
+        phy.build(&key).unwrap(),
+        &phy_rejoin_request_type1_payload()[..]
+    );
+}
+
+#[test]
+fn test_rejoin_request_creator_bad_rejoin_type() {
+    let mut phy = RejoinRequestCreator::new();
+    assert!(phy.set_rejoin_type(3).is_err());
+}
+
+#[test]
+fn test_parse_proprietary_payload() {
+    let data = vec![0xe0, 0x01, 0x02, 0x03];
+    let phy = parse(data.clone());
+    assert_eq!(
+        phy,
+        Ok(PhyPayload::Proprietary(
+            ProprietaryPhyPayload::new(data).unwrap()
+        ))
+    );
+}
+
+#[test]
+fn test_proprietary_payload_extraction() {
+    let data = [0xe0, 0x01, 0x02, 0x03];
+    let phy = ProprietaryPhyPayload::new(&data[..]).unwrap();
+    assert_eq!(phy.mhdr().mtype(), MType::Proprietary);
+    assert_eq!(phy.mhdr().major(), Major::LoRaWANR1);
+    assert_eq!(phy.payload(), &[0x01, 0x02, 0x03]);
+    assert_eq!(phy.as_ref(), &data[..]);
+}
+
+#[test]
+fn test_parse_proprietary_payload_only_mhdr() {
+    let phy = parse(vec![0xe0]);
+    if let Ok(PhyPayload::Proprietary(p)) = phy {
+        assert!(p.payload().is_empty());
+    } else {
+        panic!("failed to parse proprietary payload");
+    }
+}
+
+#[test]
+fn test_new_proprietary_payload_wrong_mtype() {
+    let data = phy_join_request_payload();
+    assert!(ProprietaryPhyPayload::new(data).is_err());
+}
+
+#[test]
+fn test_proprietary_creator() {
+    let mut phy = ProprietaryCreator::new();
+    assert_eq!(
+        phy.build(&[0x01, 0x02, 0x03]),
+        Ok(&[0xe0, 0x01, 0x02, 0x03][..])
+    );
+}
+
+#[test]
+fn test_proprietary_creator_payload_too_long() {
+    let mut phy = ProprietaryCreator::with_options([0; 4]).unwrap();
+    assert!(phy.build(&[0x01, 0x02, 0x03]).is_ok());
+    assert!(phy.build(&[0x01, 0x02, 0x03, 0x04]).is_err());
+}
+
+#[test]
+fn test_new_payload_with_wrong_mtype() {
+    assert_eq!(
+        JoinRequestPayload::new(phy_dataup_payload()).err(),
+        Some(Error::InvalidMType)
+    );
+    assert_eq!(
+        EncryptedDataPayload::new(phy_join_request_payload()).err(),
+        Some(Error::InvalidMType)
+    );
+}
+
+#[test]
+fn test_new_join_accept_payload_with_wrong_key() {
+    let mut bytes = phy_join_accept_payload();
+    let key = AES128([1; 16]);
+    assert_eq!(
+        DecryptedJoinAcceptPayload::new(&mut bytes[..], &key).err(),
+        Some(Error::MicMismatch)
+    );
+}
+
+#[test]
+fn test_error_display() {
+    assert_eq!(Error::MicMismatch.to_string(), "MIC did not match");
+    assert_eq!(
+        Error::UnsupportedMajorVersion.to_string(),
+        "unsupported major version"
+    );
+}
+
 #[test]
 fn test_new_join_accept_payload_too_short() {
     let mut bytes = phy_join_accept_payload();
@@ -268,7 +533,50 @@ fn test_new_join_accept_payload_with_c_f_list() {
         Frequency::new_from_raw(&[0x88, 0x66, 0x84]),
         Frequency::new_from_raw(&[0x58, 0x6E, 0x84]),
     ];
-    assert_eq!(decrypted_phy.c_f_list(), Some(expected_c_f_list));
+    assert_eq!(
+        decrypted_phy.c_f_list(),
+        Some(CfList::DynamicChannel(expected_c_f_list))
+    );
+}
+
+#[test]
+fn test_new_join_accept_payload_with_channel_mask_c_f_list() {
+    let data = phy_join_accept_payload_with_channel_mask_c_f_list();
+    let key = AES128([1; 16]);
+    let decrypted_phy = DecryptedJoinAcceptPayload::new(data, &key).unwrap();
+
+    let expected_c_f_list = [
+        ChannelMask::new_from_raw(&[0x00, 0xff]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+    ];
+    assert_eq!(
+        decrypted_phy.c_f_list(),
+        Some(CfList::FixedChannel(expected_c_f_list))
+    );
+}
+
+#[test]
+fn test_new_join_accept_payload_with_channel_mask_4_c_f_list() {
+    let data = phy_join_accept_payload_with_channel_mask_4_c_f_list();
+    let key = AES128([1; 16]);
+    let decrypted_phy = DecryptedJoinAcceptPayload::new(data, &key).unwrap();
+
+    let expected_c_f_list = [
+        ChannelMask::new_from_raw(&[0x00, 0xff]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x02, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+    ];
+    assert_eq!(
+        decrypted_phy.c_f_list(),
+        Some(CfList::FixedChannel(expected_c_f_list))
+    );
 }
 
 #[test]
@@ -319,6 +627,14 @@ fn test_f_port_could_be_absent_in_data_payload() {
     assert!(data_payload.f_port().is_none());
 }
 
+#[test]
+fn test_data_payload_is_confirmed() {
+    let uplink = EncryptedDataPayload::new(phy_dataup_payload()).unwrap();
+    assert!(!uplink.is_confirmed());
+    let downlink = EncryptedDataPayload::new(phy_datadown_payload()).unwrap();
+    assert!(downlink.is_confirmed());
+}
+
 #[test]
 fn test_complete_data_payload_fhdr() {
     let app_skey = AES128([1; 16]);
@@ -418,6 +734,188 @@ fn test_decrypt_downlink_missing_f_port_bug() {
         .is_ok());
 }
 
+#[test]
+fn test_validate_uplink_mic_v1_1() {
+    let phy = EncryptedDataPayload::new(phy_dataup_payload_v1_1()).unwrap();
+    let f_nwk_s_int_key = AES128([1; 16]);
+    let s_nwk_s_int_key = AES128([2; 16]);
+
+    assert!(phy.validate_uplink_mic_v1_1(&f_nwk_s_int_key, &s_nwk_s_int_key, 1, 5, 3, 2));
+    assert!(!phy.validate_uplink_mic_v1_1(&f_nwk_s_int_key, &s_nwk_s_int_key, 1, 5, 3, 1));
+    assert!(!phy.validate_uplink_mic_v1_1(&f_nwk_s_int_key, &s_nwk_s_int_key, 1, 4, 3, 2));
+    assert!(!phy.validate_uplink_mic_v1_1(&s_nwk_s_int_key, &f_nwk_s_int_key, 1, 5, 3, 2));
+}
+
+#[test]
+fn test_validate_downlink_mic_v1_1() {
+    let phy = EncryptedDataPayload::new(phy_datadown_payload_v1_1()).unwrap();
+    let s_nwk_s_int_key = AES128([2; 16]);
+
+    assert!(phy.validate_downlink_mic_v1_1(&s_nwk_s_int_key, 2, 7));
+    assert!(!phy.validate_downlink_mic_v1_1(&s_nwk_s_int_key, 2, 6));
+    assert!(!phy.validate_downlink_mic_v1_1(&AES128([1; 16]), 2, 7));
+}
+
+#[test]
+fn test_decrypt_v1_1() {
+    let phy = EncryptedDataPayload::new(phy_dataup_payload_v1_1()).unwrap();
+    let nwk_s_enc_key = AES128([3; 16]);
+    let app_skey = AES128([4; 16]);
+
+    let decrypted = phy
+        .decrypt_v1_1(Some(&nwk_s_enc_key), Some(&app_skey), 1)
+        .unwrap();
+    let fhdr = decrypted.fhdr();
+    let fopts: Vec<MacCommand> = fhdr.fopts().collect();
+    assert_eq!(fopts, vec![MacCommand::LinkCheckReq(LinkCheckReqPayload())]);
+    assert_eq!(decrypted.frm_payload(), Ok(FRMPayload::Data(&b"hello"[..])));
+}
+
+#[test]
+fn test_decrypt_v1_1_downlink() {
+    let phy = EncryptedDataPayload::new(phy_datadown_payload_v1_1()).unwrap();
+    let nwk_s_enc_key = AES128([3; 16]);
+    let app_skey = AES128([4; 16]);
+
+    let decrypted = phy
+        .decrypt_v1_1(Some(&nwk_s_enc_key), Some(&app_skey), 2)
+        .unwrap();
+    let fhdr = decrypted.fhdr();
+    let fopts: Vec<MacCommand> = fhdr.fopts().collect();
+    assert_eq!(fopts, vec![MacCommand::DevStatusReq(DevStatusReqPayload())]);
+    assert_eq!(decrypted.frm_payload(), Ok(FRMPayload::Data(&b"hi"[..])));
+}
+
+#[test]
+fn test_decrypt_v1_1_downlink_without_f_port() {
+    let phy = EncryptedDataPayload::new(phy_datadown_payload_v1_1_without_f_port()).unwrap();
+    let nwk_s_enc_key = AES128([3; 16]);
+    let app_skey = AES128([4; 16]);
+
+    let decrypted = phy
+        .decrypt_v1_1(Some(&nwk_s_enc_key), Some(&app_skey), 3)
+        .unwrap();
+    let fhdr = decrypted.fhdr();
+    let fopts: Vec<MacCommand> = fhdr.fopts().collect();
+    assert_eq!(fopts, vec![MacCommand::DevStatusReq(DevStatusReqPayload())]);
+    assert_eq!(decrypted.f_port(), None);
+}
+
+#[test]
+fn test_decrypt_v1_1_downlink_with_f_port_0() {
+    let phy = EncryptedDataPayload::new(phy_datadown_payload_v1_1_with_f_port_0()).unwrap();
+    let nwk_s_enc_key = AES128([3; 16]);
+    let app_skey = AES128([4; 16]);
+
+    let decrypted = phy
+        .decrypt_v1_1(Some(&nwk_s_enc_key), Some(&app_skey), 4)
+        .unwrap();
+    let fhdr = decrypted.fhdr();
+    let fopts: Vec<MacCommand> = fhdr.fopts().collect();
+    assert_eq!(fopts, vec![MacCommand::DevStatusReq(DevStatusReqPayload())]);
+    assert_eq!(decrypted.f_port(), Some(0));
+    if let Ok(FRMPayload::MACCommands(mac_commands)) = decrypted.frm_payload() {
+        let mac_commands: Vec<MacCommand> = mac_commands.mac_commands().collect();
+        assert_eq!(
+            mac_commands,
+            vec![MacCommand::DevStatusReq(DevStatusReqPayload())]
+        );
+    } else {
+        panic!("expected MAC commands in the FRMPayload");
+    }
+}
+
+#[test]
+fn test_decrypt_v1_1_without_nwk_s_enc_key_when_fopts() {
+    let phy = EncryptedDataPayload::new(phy_dataup_payload_v1_1()).unwrap();
+    let app_skey = AES128([4; 16]);
+
+    assert!(phy.decrypt_v1_1(None, Some(&app_skey), 1).is_err());
+}
+
+#[test]
+fn test_data_payload_creator_v1_1_uplink() {
+    let mut phy = DataPayloadCreator::new();
+    let mac_cmd = MacCommand::LinkCheckReq(LinkCheckReqPayload());
+    let cmds: [&dyn SerializableMacCommand; 1] = [&mac_cmd];
+    let fctrl = FCtrl::new(0x20, true);
+    phy.set_confirmed(false)
+        .set_uplink(true)
+        .set_f_port(1)
+        .set_dev_addr(&[4, 3, 2, 1])
+        .set_fctrl(&fctrl)
+        .set_fcnt(1)
+        .set_conf_fcnt(5)
+        .set_tx_dr(3)
+        .set_tx_ch(2);
+
+    assert_eq!(
+        phy.build_v1_1(
+            b"hello",
+            &cmds,
+            &AES128([1; 16]),
+            &AES128([2; 16]),
+            &AES128([3; 16]),
+            &AES128([4; 16])
+        )
+        .unwrap(),
+        &phy_dataup_payload_v1_1()[..]
+    );
+}
+
+#[test]
+fn test_data_payload_creator_v1_1_downlink() {
+    let mut phy = DataPayloadCreator::new();
+    let mac_cmd = MacCommand::DevStatusReq(DevStatusReqPayload());
+    let cmds: [&dyn SerializableMacCommand; 1] = [&mac_cmd];
+    phy.set_confirmed(false)
+        .set_uplink(false)
+        .set_f_port(1)
+        .set_dev_addr(&[4, 3, 2, 1])
+        .set_fctrl(&FCtrl::new(0x20, false))
+        .set_fcnt(2)
+        .set_conf_fcnt(7);
+
+    assert_eq!(
+        phy.build_v1_1(
+            b"hi",
+            &cmds,
+            &AES128([1; 16]),
+            &AES128([2; 16]),
+            &AES128([3; 16]),
+            &AES128([4; 16])
+        )
+        .unwrap(),
+        &phy_datadown_payload_v1_1()[..]
+    );
+}
+
+#[test]
+fn test_data_payload_creator_v1_1_downlink_without_f_port() {
+    let mut phy = DataPayloadCreator::new();
+    let mac_cmd = MacCommand::DevStatusReq(DevStatusReqPayload());
+    let cmds: [&dyn SerializableMacCommand; 1] = [&mac_cmd];
+    phy.set_confirmed(false)
+        .set_uplink(false)
+        .set_dev_addr(&[4, 3, 2, 1])
+        .set_fctrl(&FCtrl::new(0x20, false))
+        .set_fcnt(3)
+        .set_conf_fcnt(7);
+
+    assert_eq!(
+        phy.build_v1_1(
+            &[],
+            &cmds,
+            &AES128([1; 16]),
+            &AES128([2; 16]),
+            &AES128([3; 16]),
+            &AES128([4; 16])
+        )
+        .unwrap(),
+        &phy_datadown_payload_v1_1_without_f_port()[..]
+    );
+}
+
 #[test]
 fn test_new_frequency() {
     let freq = Frequency::new(&[0x18, 0x4F, 0x84]);
@@ -462,6 +960,32 @@ fn test_data_payload_uplink_creator() {
     );
 }
 
+#[test]
+fn test_data_payload_uplink_creator_with_32_bit_fcnt() {
+    let mut phy = DataPayloadCreator::new();
+    let nwk_skey = AES128([2; 16]);
+    let app_skey = AES128([1; 16]);
+    phy.set_f_port(1)
+        .set_dev_addr(&[4, 3, 2, 1])
+        .set_fcnt(0x0102_0304);
+
+    let bytes = phy
+        .build(b"hello", &[], &nwk_skey, &app_skey)
+        .unwrap()
+        .to_vec();
+    let encrypted = EncryptedDataPayload::new(bytes).unwrap();
+    assert_eq!(encrypted.fhdr().fcnt(), 0x0304);
+    assert!(encrypted.validate_mic(&nwk_skey, 0x0102_0304));
+    assert!(!encrypted.validate_mic(&nwk_skey, 0x0304));
+    let decrypted = encrypted
+        .decrypt(None, Some(&app_skey), 0x0102_0304)
+        .unwrap();
+    assert_eq!(
+        decrypted.frm_payload().unwrap(),
+        FRMPayload::Data(&b"hello"[..])
+    );
+}
+
 #[test]
 fn test_long_data_payload_uplink_creator() {
     let mut phy = DataPayloadCreator::new();
@@ -512,7 +1036,10 @@ fn test_data_payload_creator_when_payload_and_fport_0() {
     let nwk_skey = AES128([2; 16]);
     let app_skey = AES128([1; 16]);
     phy.set_f_port(0);
-    assert!(phy.build(b"hello", &[], &nwk_skey, &app_skey).is_err());
+    assert_eq!(
+        phy.build(b"hello", &[], &nwk_skey, &app_skey).err(),
+        Some(Error::InvalidFPort)
+    );
 }
 
 #[test]
@@ -525,7 +1052,27 @@ fn test_data_payload_creator_when_encrypt_but_not_fport_0() {
     let mut cmds: Vec<&dyn SerializableMacCommand> = Vec::new();
     cmds.extend_from_slice(&[&new_channel_req, &new_channel_req, &new_channel_req]);
     phy.set_f_port(1);
-    assert!(phy.build(b"", &cmds[..], &nwk_skey, &app_skey).is_err());
+    assert_eq!(
+        phy.build(b"", &cmds[..], &nwk_skey, &app_skey).err(),
+        Some(Error::MacCommandsTooLong)
+    );
+}
+
+#[test]
+fn test_data_payload_creator_when_payload_too_long() {
+    let mut phy = DataPayloadCreator::new();
+    let nwk_skey = AES128([2; 16]);
+    let app_skey = AES128([1; 16]);
+    phy.set_f_port(1);
+    assert_eq!(
+        phy.build(&[0; 244], &[], &nwk_skey, &app_skey).err(),
+        Some(Error::BufferTooSmall)
+    );
+    assert_eq!(
+        phy.build(&[0; 243], &[], &nwk_skey, &app_skey)
+            .map(|p| p.len()),
+        Ok(256)
+    );
 }
 
 #[test]
@@ -533,7 +1080,10 @@ fn test_data_payload_creator_when_payload_no_fport() {
     let mut phy = DataPayloadCreator::new();
     let nwk_skey = AES128([2; 16]);
     let app_skey = AES128([1; 16]);
-    assert!(phy.build(b"hello", &[], &nwk_skey, &app_skey).is_err());
+    assert_eq!(
+        phy.build(b"hello", &[], &nwk_skey, &app_skey).err(),
+        Some(Error::InvalidFPort)
+    );
 }
 
 #[test]
@@ -624,7 +1174,7 @@ fn test_validate_join_request_mic_when_not_ok() {
 }
 
 #[test]
-#[cfg(feature = "default-crypto,with-downlink")]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
 fn test_join_accept_creator() {
     let mut phy = JoinAcceptCreator::new();
     let key = AES128(app_key());
@@ -638,6 +1188,152 @@ fn test_join_accept_creator() {
     assert_eq!(phy.build(&key).unwrap(), &phy_join_accept_payload()[..]);
 }
 
+#[test]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
+fn test_join_accept_creator_with_c_f_list() {
+    let mut phy = JoinAcceptCreator::new();
+    let key = AES128([1; 16]);
+    let freqs = [
+        Frequency::new_from_raw(&[0x18, 0x4F, 0x84]),
+        Frequency::new_from_raw(&[0xE8, 0x56, 0x84]),
+        Frequency::new_from_raw(&[0xB8, 0x5E, 0x84]),
+        Frequency::new_from_raw(&[0x88, 0x66, 0x84]),
+        Frequency::new_from_raw(&[0x58, 0x6E, 0x84]),
+    ];
+    phy.set_app_nonce(&[0x03, 0x02, 0x01])
+        .set_net_id(&[0x07, 0x08, 0x09])
+        .set_dev_addr(&[0x06, 0x05, 0x04, 0x03])
+        .set_dl_settings(0x12)
+        .set_rx_delay(0x03)
+        .set_c_f_list(CfList::DynamicChannel(freqs));
+
+    assert_eq!(
+        phy.build(&key).unwrap(),
+        &phy_join_accept_payload_with_c_f_list()[..]
+    );
+}
+
+#[test]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
+fn test_join_accept_creator_with_channel_mask_c_f_list() {
+    let mut phy = JoinAcceptCreator::new();
+    let key = AES128([1; 16]);
+    let masks = [
+        ChannelMask::new_from_raw(&[0x00, 0xff]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+    ];
+    phy.set_app_nonce(&[0x03, 0x02, 0x01])
+        .set_net_id(&[0x07, 0x08, 0x09])
+        .set_dev_addr(&[0x06, 0x05, 0x04, 0x03])
+        .set_dl_settings(0x12)
+        .set_rx_delay(0x03)
+        .set_c_f_list(CfList::FixedChannel(masks));
+
+    assert_eq!(
+        phy.build(&key).unwrap(),
+        &phy_join_accept_payload_with_channel_mask_c_f_list()[..]
+    );
+}
+
+#[test]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
+fn test_join_accept_creator_with_channel_mask_4_c_f_list() {
+    let mut phy = JoinAcceptCreator::new();
+    let key = AES128([1; 16]);
+    let masks = [
+        ChannelMask::new_from_raw(&[0x00, 0xff]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+        ChannelMask::new_from_raw(&[0x02, 0x00]),
+        ChannelMask::new_from_raw(&[0x00, 0x00]),
+    ];
+    phy.set_app_nonce(&[0x03, 0x02, 0x01])
+        .set_net_id(&[0x07, 0x08, 0x09])
+        .set_dev_addr(&[0x06, 0x05, 0x04, 0x03])
+        .set_dl_settings(0x12)
+        .set_rx_delay(0x03)
+        .set_c_f_list(CfList::FixedChannel(masks));
+
+    assert_eq!(
+        phy.build(&key).unwrap(),
+        &phy_join_accept_payload_with_channel_mask_4_c_f_list()[..]
+    );
+}
+
+#[test]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
+fn test_join_accept_creator_v1_1() {
+    let nwk_key = AES128([
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ]);
+    let dev_eui = EUI64::new([0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02]).unwrap();
+    let join_eui = EUI64::new([0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01]).unwrap();
+    let dev_nonce = DevNonce::new([0x01, 0x00]).unwrap();
+    let js_int_key = derive_js_int_key(&nwk_key, &dev_eui, &DefaultFactory);
+    let mut phy = JoinAcceptCreator::new();
+    phy.set_app_nonce(&[0x01, 0x00, 0x00])
+        .set_net_id(&[0x13, 0x00, 0x00])
+        .set_dev_addr(&[0x04, 0x03, 0x02, 0x01])
+        .set_dl_settings(0)
+        .set_opt_neg(true)
+        .set_rx_delay(1);
+
+    let expected = [
+        0x20, 0x1e, 0x54, 0x44, 0x68, 0x00, 0x79, 0x8f, 0x94, 0xbb, 0x9d, 0xac, 0xbc, 0x81, 0x60,
+        0xd0, 0xe0,
+    ];
+    assert_eq!(
+        phy.build_v1_1(&nwk_key, &js_int_key, 0xff, &join_eui, &dev_nonce)
+            .unwrap(),
+        &expected[..]
+    );
+}
+
+#[test]
+#[cfg(all(feature = "default-crypto", feature = "with-downlink"))]
+fn test_rejoin_accept_creator_v1_1() {
+    let nwk_key = AES128([
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ]);
+    let dev_eui = EUI64::new([0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02]).unwrap();
+    let join_eui = EUI64::new([0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01]).unwrap();
+    let rj_count = DevNonce::new([0x05, 0x00]).unwrap();
+    let js_int_key = derive_js_int_key(&nwk_key, &dev_eui, &DefaultFactory);
+    let js_enc_key = derive_js_enc_key(&nwk_key, &dev_eui, &DefaultFactory);
+    let mut phy = JoinAcceptCreator::new();
+    phy.set_app_nonce(&[0x01, 0x00, 0x00])
+        .set_net_id(&[0x13, 0x00, 0x00])
+        .set_dev_addr(&[0x04, 0x03, 0x02, 0x01])
+        .set_dl_settings(0x80)
+        .set_rx_delay(1);
+
+    let expected = [
+        0x20, 0x65, 0xda, 0x02, 0xb2, 0xda, 0x73, 0x6e, 0xd1, 0x49, 0xaa, 0xf0, 0x46, 0xd4, 0x4d,
+        0x87, 0xaf,
+    ];
+    let built = phy
+        .build_v1_1(&js_enc_key, &js_int_key, 0x00, &join_eui, &rj_count)
+        .unwrap()
+        .to_vec();
+    assert_eq!(built, expected.to_vec());
+
+    let join_accept = EncryptedJoinAcceptPayload::new(built)
+        .unwrap()
+        .decrypt(&js_enc_key);
+    assert_eq!(
+        join_accept.join_nonce(),
+        AppNonce::new(&[0x01, 0x00, 0x00]).unwrap()
+    );
+    assert!(join_accept.validate_mic_v1_1(&js_int_key, 0x00, &join_eui, &rj_count));
+}
+
 #[test]
 fn test_join_request_creator() {
     let mut phy = JoinRequestCreator::new();
@@ -695,9 +1391,114 @@ fn test_derive_appskey() {
     assert_eq!(appskey.0, expect);
 }
 
+#[test]
+fn test_derive_session_keys_v1_1() {
+    let key = AES128(app_key());
+    let join_request = JoinRequestPayload::new(phy_join_request_payload()).unwrap();
+    let join_accept = DecryptedJoinAcceptPayload::new(phy_join_accept_payload(), &key).unwrap();
+    let join_eui = join_request.app_eui();
+    let dev_nonce = join_request.dev_nonce();
+
+    let f_nwk_s_int_key = join_accept.derive_fnwksintkey(&join_eui, &dev_nonce, &key);
+    let expect = [
+        0x59, 0xf8, 0xb7, 0xee, 0x16, 0xbf, 0x12, 0x59, 0xbe, 0x9b, 0xfd, 0x83, 0x8d, 0x94, 0x40,
+        0x2a,
+    ];
+    assert_eq!(f_nwk_s_int_key.0, expect);
+
+    let s_nwk_s_int_key = join_accept.derive_snwksintkey(&join_eui, &dev_nonce, &key);
+    let expect = [
+        0x39, 0x48, 0xa4, 0x26, 0x53, 0xa4, 0x0e, 0xa2, 0x7b, 0x41, 0x6e, 0xa2, 0x0f, 0x93, 0x5a,
+        0x13,
+    ];
+    assert_eq!(s_nwk_s_int_key.0, expect);
+
+    let nwk_s_enc_key = join_accept.derive_nwksenckey(&join_eui, &dev_nonce, &key);
+    let expect = [
+        0xd2, 0xed, 0xca, 0x02, 0xd2, 0x94, 0xd8, 0x59, 0x08, 0xdb, 0x8a, 0xdb, 0x35, 0xcd, 0x52,
+        0x41,
+    ];
+    assert_eq!(nwk_s_enc_key.0, expect);
+
+    let appskey = join_accept.derive_appskey_v1_1(&join_eui, &dev_nonce, &key);
+    let expect = [
+        0xc8, 0x9a, 0xa2, 0xcb, 0x91, 0x0d, 0xb5, 0xba, 0x3c, 0xbd, 0x4d, 0x2c, 0xae, 0x81, 0xe8,
+        0xc0,
+    ];
+    assert_eq!(appskey.0, expect);
+}
+
+#[test]
+fn test_derive_join_server_keys_v1_1() {
+    let nwk_key = AES128([
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ]);
+    let dev_eui = EUI64::new([0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02]).unwrap();
+
+    let js_int_key = derive_js_int_key(&nwk_key, &dev_eui, &DefaultFactory);
+    let expect = [
+        0x4e, 0xff, 0x1d, 0x89, 0xfb, 0x85, 0x12, 0x2c, 0x22, 0x21, 0xec, 0x75, 0x45, 0x14, 0xeb,
+        0x88,
+    ];
+    assert_eq!(js_int_key.0, expect);
+
+    let js_enc_key = derive_js_enc_key(&nwk_key, &dev_eui, &DefaultFactory);
+    let expect = [
+        0xa3, 0x45, 0x9f, 0xe4, 0x47, 0x46, 0x21, 0xd8, 0x00, 0x98, 0xe9, 0xc5, 0x0d, 0x9b, 0xfd,
+        0x53,
+    ];
+    assert_eq!(js_enc_key.0, expect);
+}
+
+#[test]
+fn test_validate_join_accept_mic_v1_1() {
+    let nwk_key = AES128([
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ]);
+    let dev_eui = EUI64::new([0x05, 0x04, 0x03, 0x02, 0x05, 0x04, 0x03, 0x02]).unwrap();
+    let join_eui = EUI64::new([0x04, 0x03, 0x02, 0x01, 0x04, 0x03, 0x02, 0x01]).unwrap();
+    let dev_nonce = DevNonce::new([0x01, 0x00]).unwrap();
+    let data = vec![
+        0x20, 0x1e, 0x54, 0x44, 0x68, 0x00, 0x79, 0x8f, 0x94, 0xbb, 0x9d, 0xac, 0xbc, 0x81, 0x60,
+        0xd0, 0xe0,
+    ];
+    let js_int_key = derive_js_int_key(&nwk_key, &dev_eui, &DefaultFactory);
+
+    let join_accept = EncryptedJoinAcceptPayload::new(data)
+        .unwrap()
+        .decrypt(&nwk_key);
+    assert!(join_accept.dl_settings().opt_neg());
+    assert!(join_accept.validate_mic_v1_1(&js_int_key, 0xff, &join_eui, &dev_nonce));
+    assert!(!join_accept.validate_mic_v1_1(&js_int_key, 0x00, &join_eui, &dev_nonce));
+    assert!(!join_accept.validate_mic(&nwk_key));
+}
+
 #[test]
 #[cfg(feature = "with-to-string")]
 fn test_eui64_to_string() {
     let eui = EUI64::new(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xff]).unwrap();
     assert_eq!(eui.to_string(), "123456789abcdeff".to_owned());
 }
+
+#[test]
+fn test_restore_fcnt() {
+    assert_eq!(restore_fcnt(0, 0), Some(0));
+    assert_eq!(restore_fcnt(5, 7), Some(7));
+    assert_eq!(restore_fcnt(0xffff, 0x0000), Some(0x1_0000));
+    assert_eq!(restore_fcnt(0x2_fff0, 0x0010), Some(0x3_0010));
+    assert_eq!(restore_fcnt(0, MAX_FCNT_GAP as u16), Some(MAX_FCNT_GAP));
+    assert_eq!(restore_fcnt(0xffff_ff00, 0xff01), Some(0xffff_ff01));
+}
+
+#[test]
+fn test_restore_fcnt_rejects_replay_and_big_gaps() {
+    // replayed frames
+    assert_eq!(restore_fcnt(6, 5), None);
+    assert_eq!(restore_fcnt(1, 0), None);
+    // too far ahead
+    assert_eq!(restore_fcnt(0, MAX_FCNT_GAP as u16 + 1), None);
+    // beyond the 32-bit frame counter
+    assert_eq!(restore_fcnt(0xffff_fff0, 0x0001), None);
+}
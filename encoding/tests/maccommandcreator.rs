@@ -166,6 +166,104 @@ fn test_rx_timing_setup_ans_creator() {
     assert_eq!(res, [RXTimingSetupAnsPayload::cid()]);
 }
 
+#[test]
+fn test_ping_slot_info_req_creator() {
+    let mut creator = PingSlotInfoReqCreator::new();
+    let res = creator.set_periodicity(0x07).unwrap().build();
+    assert_eq!(res, [PingSlotInfoReqPayload::cid(), 0x07]);
+}
+
+#[test]
+fn test_ping_slot_info_req_creator_bad_periodicity() {
+    let mut creator = PingSlotInfoReqCreator::new();
+    assert!(creator.set_periodicity(0x08).is_err());
+}
+
+#[test]
+fn test_ping_slot_info_ans_creator() {
+    let creator = PingSlotInfoAnsCreator::new();
+    let res = creator.build();
+    assert_eq!(res, [PingSlotInfoAnsPayload::cid()]);
+}
+
+#[test]
+fn test_ping_slot_channel_req_creator() {
+    let mut creator = PingSlotChannelReqCreator::new();
+    let res = creator
+        .set_frequency(&[0x12, 0x34, 0x56])
+        .set_data_rate(0x03)
+        .unwrap()
+        .build();
+    assert_eq!(
+        res,
+        [PingSlotChannelReqPayload::cid(), 0x12, 0x34, 0x56, 0x03]
+    );
+}
+
+#[test]
+fn test_ping_slot_channel_req_creator_bad_data_rate() {
+    let mut creator = PingSlotChannelReqCreator::new();
+    assert!(creator.set_data_rate(0x10).is_err());
+}
+
+#[test]
+fn test_ping_slot_channel_ans_creator() {
+    let mut creator = PingSlotChannelAnsCreator::new();
+    let res = creator
+        .set_channel_frequency_ack(true)
+        .set_data_rate_ack(true)
+        .build();
+    assert_eq!(res, [PingSlotChannelAnsPayload::cid(), 0x03]);
+}
+
+#[test]
+fn test_beacon_timing_req_creator() {
+    let creator = BeaconTimingReqCreator::new();
+    let res = creator.build();
+    assert_eq!(res, [BeaconTimingReqPayload::cid()]);
+}
+
+#[test]
+fn test_beacon_timing_ans_creator() {
+    let mut creator = BeaconTimingAnsCreator::new();
+    let res = creator.set_delay(0x1234).set_channel(0x05).build();
+    assert_eq!(res, [BeaconTimingAnsPayload::cid(), 0x34, 0x12, 0x05]);
+}
+
+#[test]
+fn test_beacon_freq_req_creator() {
+    let mut creator = BeaconFreqReqCreator::new();
+    let res = creator.set_frequency(&[0x12, 0x34, 0x56]).build();
+    assert_eq!(res, [BeaconFreqReqPayload::cid(), 0x12, 0x34, 0x56]);
+}
+
+#[test]
+fn test_beacon_freq_ans_creator() {
+    let mut creator = BeaconFreqAnsCreator::new();
+    let res = creator.set_beacon_frequency_ack(true).build();
+    assert_eq!(res, [BeaconFreqAnsPayload::cid(), 0x01]);
+}
+
+#[test]
+fn test_rekey_ind_creator() {
+    let mut creator = RekeyIndCreator::new();
+    let res = creator.set_minor_version(1).unwrap().build();
+    assert_eq!(res, [RekeyIndPayload::cid(), 0x01]);
+}
+
+#[test]
+fn test_rekey_ind_creator_bad_minor_version() {
+    let mut creator = RekeyIndCreator::new();
+    assert!(creator.set_minor_version(0x10).is_err());
+}
+
+#[test]
+fn test_rekey_conf_creator() {
+    let mut creator = RekeyConfCreator::new();
+    let res = creator.set_minor_version(1).unwrap().build();
+    assert_eq!(res, [RekeyConfPayload::cid(), 0x01]);
+}
+
 #[test]
 fn test_build_mac_commands() {
     let rx_timing_setup_req = RXTimingSetupReqPayload::new_as_mac_cmd(&[0x02]).unwrap().0;
@@ -228,6 +228,113 @@ fn test_rx_timing_setup_ans() {
     test_helper!(RXTimingSetupAns, RXTimingSetupAnsPayload);
 }
 
+#[test]
+fn test_ping_slot_info_req() {
+    let data = vec![0x05];
+    test_helper!(
+        data,
+        PingSlotInfoReq,
+        PingSlotInfoReqPayload,
+        1,
+        (periodicity, 5),
+    );
+}
+
+#[test]
+fn test_ping_slot_info_ans() {
+    test_helper!(PingSlotInfoAns, PingSlotInfoAnsPayload);
+}
+
+#[test]
+fn test_ping_slot_channel_req() {
+    let data = vec![0x18, 0x4f, 0x84, 0x03];
+    test_helper!(
+        data,
+        PingSlotChannelReq,
+        PingSlotChannelReqPayload,
+        4,
+        (frequency, Frequency::new_from_raw(&data[0..3])),
+        (data_rate, 3),
+    );
+}
+
+#[test]
+fn test_ping_slot_channel_ans() {
+    let examples = [
+        ([0x00], false, false, false),
+        ([0x01], true, false, false),
+        ([0x02], false, true, false),
+        ([0x03], true, true, true),
+    ];
+    assert!(PingSlotChannelAnsPayload::new_as_mac_cmd(&[]).is_err());
+    for &(ref v, ref e_ch_freq, ref e_dr, ref e_ack) in &examples {
+        let mc = PingSlotChannelAnsPayload::new_as_mac_cmd(&v[..]);
+        assert!(mc.is_ok());
+        if let (MacCommand::PingSlotChannelAns(psca), size) = mc.unwrap() {
+            assert_eq!(size, 1);
+            assert_eq!(psca.channel_freq_ack(), *e_ch_freq);
+            assert_eq!(psca.data_rate_ack(), *e_dr);
+            assert_eq!(psca.ack(), *e_ack);
+        } else {
+            panic!("failed to parse PingSlotChannelAnsPayload");
+        }
+    }
+}
+
+#[test]
+fn test_beacon_timing_req() {
+    test_helper!(BeaconTimingReq, BeaconTimingReqPayload);
+}
+
+#[test]
+fn test_beacon_timing_ans() {
+    let data = vec![0x34, 0x12, 0x05];
+    test_helper!(
+        data,
+        BeaconTimingAns,
+        BeaconTimingAnsPayload,
+        3,
+        (delay, 0x1234),
+        (channel, 5),
+    );
+}
+
+#[test]
+fn test_beacon_freq_req() {
+    let data = vec![0x18, 0x4f, 0x84];
+    test_helper!(
+        data,
+        BeaconFreqReq,
+        BeaconFreqReqPayload,
+        3,
+        (frequency, Frequency::new_from_raw(&data[..])),
+    );
+}
+
+#[test]
+fn test_beacon_freq_ans() {
+    let data = vec![0x01];
+    test_helper!(
+        data,
+        BeaconFreqAns,
+        BeaconFreqAnsPayload,
+        1,
+        (beacon_freq_ack, true),
+    );
+}
+
+#[test]
+fn test_rekey_ind() {
+    let data = vec![0x01];
+    test_helper!(data, RekeyInd, RekeyIndPayload, 1, (minor_version, 1),);
+}
+
+#[test]
+fn test_rekey_conf() {
+    let data = vec![0x01];
+    test_helper!(data, RekeyConf, RekeyConfPayload, 1, (minor_version, 1),);
+}
+
 #[test]
 fn test_parse_mac_commands_empty_downlink() {
     assert_eq!(parse_mac_commands(&[], false).count(), 0);
@@ -270,6 +377,85 @@ fn test_parse_mac_commands_with_multiple_cmds_with_payloads() {
     );
 }
 
+#[test]
+fn test_parse_mac_commands_with_class_b_cmds() {
+    let data = vec![0x10, 0x11, 0x18, 0x4f, 0x84, 0x03, 0x13, 0x18, 0x4f, 0x84];
+    let mut commands = parse_mac_commands(&data, false);
+
+    assert_eq!(
+        commands.next(),
+        Some(MacCommand::PingSlotInfoAns(PingSlotInfoAnsPayload()))
+    );
+    assert_eq!(
+        commands.next(),
+        Some(MacCommand::PingSlotChannelReq(
+            PingSlotChannelReqPayload::new(&data[2..6]).unwrap()
+        ))
+    );
+    assert_eq!(
+        commands.next(),
+        Some(MacCommand::BeaconFreqReq(
+            BeaconFreqReqPayload::new(&data[7..]).unwrap()
+        ))
+    );
+    assert_eq!(commands.next(), None);
+}
+
+#[test]
+fn test_parse_mac_commands_stops_at_unknown_proprietary_cmd() {
+    let data = vec![0x80, 0x01, 0x02];
+    assert_eq!(parse_mac_commands(&data, true).count(), 0);
+}
+
+#[test]
+fn test_parse_mac_commands_with_proprietary_cmds() {
+    let data = vec![0x02, 0x80, 0x01, 0x02, 0x81, 0x03, 0x00];
+    let lengths = |cid, uplink| match (cid, uplink) {
+        (0x80, true) => Some(2),
+        (0x81, true) => Some(0),
+        _ => None,
+    };
+    let mut commands = parse_mac_commands(&data, true).with_proprietary(&lengths);
+
+    assert_eq!(
+        commands.next(),
+        Some(MacCommand::LinkCheckReq(LinkCheckReqPayload()))
+    );
+    let cmd = commands.next().unwrap();
+    assert_eq!(cmd.cid(), 0x80);
+    assert_eq!(cmd.bytes(), &[0x01, 0x02]);
+    assert_eq!(
+        commands.next(),
+        Some(MacCommand::Proprietary(
+            ProprietaryPayload::new(0x81, &[]).unwrap()
+        ))
+    );
+    assert_eq!(
+        commands.next(),
+        Some(MacCommand::LinkADRAns(
+            LinkADRAnsPayload::new(&[0x00]).unwrap()
+        ))
+    );
+    assert_eq!(commands.next(), None);
+}
+
+#[test]
+fn test_parse_mac_commands_with_truncated_proprietary_cmd() {
+    let data = vec![0x80, 0x01];
+    let lengths = |_, _| Some(2);
+    assert_eq!(
+        parse_mac_commands(&data, false)
+            .with_proprietary(&lengths)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_proprietary_payload_bad_cid() {
+    assert!(ProprietaryPayload::new(0x7f, &[]).is_err());
+}
+
 fn mac_cmds_payload() -> Vec<u8> {
     vec![LinkCheckReqPayload::cid(), LinkADRAnsPayload::cid(), 0x00]
 }
@@ -362,3 +548,11 @@ fn test_mac_commands_len_with_mac_cmds() {
 
     assert_eq!(mac_commands_len(&cmds[..]), 5);
 }
+
+#[test]
+fn test_mac_commands_len_with_proprietary_cmd() {
+    let proprietary = MacCommand::Proprietary(ProprietaryPayload::new(0x80, &[1, 2, 3]).unwrap());
+    let cmds: Vec<&dyn SerializableMacCommand> = vec![&proprietary];
+
+    assert_eq!(mac_commands_len(&cmds[..]), 4);
+}